@@ -1,11 +1,43 @@
-use std::fs;
-
 use anyhow::{Context, Result, anyhow};
 use colored::*;
 
-use crate::models::{Config, DotPath, DotfileEntry, DotfileStatus};
+use crate::models::{
+    Condition, Config, DeployMode, DotPath, DotfileEntry, DotfileStatus, Operation, Plan,
+    SYSTEM_DIR, secret_digest,
+};
+use crate::utils::escalate;
+use crate::utils::hash::hash_path;
+use crate::utils::ignore::IgnoreRules;
+use crate::utils::path_compare;
+use crate::utils::walk::SymlinkPolicy;
+
+/// Options of `dotzilla add`
+#[derive(Debug, Default)]
+pub struct AddOptions {
+    /// Conditions that must hold for the dotfile to be active
+    pub when: Vec<String>,
+    /// Treat the repository copy as a template
+    pub template: bool,
+    /// Store the repository copy encrypted
+    pub encrypt: bool,
+    /// How `link` puts the dotfile in place
+    pub deploy: Option<DeployMode>,
+    /// Gitignore-style patterns for paths inside a directory to leave out
+    pub ignore: Vec<String>,
+    /// What to do with symlinks inside a directory
+    pub symlinks: Option<SymlinkPolicy>,
+}
+
+pub fn add_dotfile(
+    mut config: Config,
+    dotfile_path: DotPath,
+    options: AddOptions,
+    dry_run: bool,
+) -> Result<()> {
+    for expr in &options.when {
+        Condition::parse(expr)?;
+    }
 
-pub fn add_dotfile(mut config: Config, dotfile_path: DotPath) -> Result<()> {
     if !dotfile_path.abs_path.exists() {
         return Err(anyhow!(
             "Dotfile at {} does not exist",
@@ -13,66 +45,197 @@ pub fn add_dotfile(mut config: Config, dotfile_path: DotPath) -> Result<()> {
         ));
     }
 
-    if let Some(parent) = dotfile_path.abs_target.parent() {
-        if !parent.exists() {
-            fs::create_dir_all(parent).with_context(|| {
-                format!(
-                    "Failed to create directory for target path: {}",
-                    parent.display()
-                )
-            })?;
-        }
+    // Re-adding a dotfile keeps the settings it already had
+    let existing = config.get_dotfile(&dotfile_path).ok().cloned();
+
+    // A file inside an unfolded directory joins that dotfile instead of becoming its own
+    if existing.is_none()
+        && let Some((parent, parent_entry)) = unfolded_parent(&config, &dotfile_path)
+    {
+        return add_to_unfolded(
+            config,
+            &dotfile_path,
+            &parent,
+            parent_entry,
+            options,
+            dry_run,
+        );
     }
 
-    if dotfile_path.abs_path.is_dir() {
-        let mut opts = fs_extra::dir::CopyOptions::new();
-        opts.copy_inside = true;
-        opts.overwrite = true;
-        opts.skip_exist = false;
-
-        if fs::remove_dir_all(&dotfile_path.abs_target).is_ok() {
-            println!(
-                "{} Removed existing directory: {}",
-                "✓".green(),
-                dotfile_path.abs_target.display()
-            );
-        }
+    if !dotfile_path.is_system()
+        && dotfile_path
+            .abs_target
+            .starts_with(config.repo_path().join(SYSTEM_DIR))
+    {
+        return Err(anyhow!(
+            "~/{} is reserved in the repository for dotfiles outside the home directory, {} can't be tracked",
+            SYSTEM_DIR,
+            dotfile_path
+        ));
+    }
 
-        fs_extra::dir::copy(&dotfile_path.abs_path, &dotfile_path.abs_target, &opts).with_context(
-            || {
-                format!(
-                    "Failed to copy directory from {} to {}",
-                    dotfile_path.abs_path.display(),
-                    dotfile_path.target.display()
-                )
-            },
-        )?;
+    // System files the user adding them can't change are changed through the escalation
+    // command on every machine
+    let privileged = existing.as_ref().is_some_and(|entry| entry.privileged)
+        || (dotfile_path.is_system() && escalate::needs_root(&dotfile_path.abs_path));
 
-        println!(
-            "{} Copied directory from {} to {}",
-            "✓".green(),
-            dotfile_path.abs_path.display(),
-            dotfile_path.abs_target.display()
-        );
+    let template = options.template || existing.as_ref().is_some_and(|entry| entry.template);
+    let encrypted = options.encrypt || existing.as_ref().is_some_and(|entry| entry.encrypted);
+    let deploy = options
+        .deploy
+        .or(existing.as_ref().map(|entry| entry.deploy))
+        .unwrap_or(if encrypted {
+            DeployMode::Copy
+        } else {
+            DeployMode::Symlink
+        });
+
+    if template && dotfile_path.abs_path.is_dir() {
+        return Err(anyhow!(
+            "Only files can be templates, {} is a directory",
+            dotfile_path.abs_path.display()
+        ));
+    }
+
+    if encrypted && dotfile_path.abs_path.is_dir() {
+        return Err(anyhow!(
+            "Only files can be encrypted, {} is a directory",
+            dotfile_path.abs_path.display()
+        ));
+    }
+
+    if encrypted && deploy != DeployMode::Copy {
+        return Err(anyhow!(
+            "{} is encrypted and can only be deployed as a copy",
+            dotfile_path
+        ));
+    }
+
+    if deploy == DeployMode::Hardlink && dotfile_path.abs_path.is_dir() {
+        return Err(anyhow!(
+            "Only files can be hard linked, {} is a directory",
+            dotfile_path.abs_path.display()
+        ));
+    }
+
+    if deploy == DeployMode::Unfold && !dotfile_path.abs_path.is_dir() {
+        return Err(anyhow!(
+            "Only directories can be unfolded, {} is a file",
+            dotfile_path.abs_path.display()
+        ));
+    }
+
+    if !options.ignore.is_empty() && !dotfile_path.abs_path.is_dir() {
+        return Err(anyhow!(
+            "Ignore patterns only apply to directories, {} is a file",
+            dotfile_path.abs_path.display()
+        ));
+    }
+
+    if options.symlinks.is_some() && !dotfile_path.abs_path.is_dir() {
+        return Err(anyhow!(
+            "Symlink policies only apply to directories, {} is a file",
+            dotfile_path.abs_path.display()
+        ));
+    }
+    let symlinks = options
+        .symlinks
+        .or(existing.as_ref().map(|entry| entry.symlinks))
+        .unwrap_or_default();
+
+    // Re-adding without patterns keeps the ones the entry already had
+    let ignore = if options.ignore.is_empty() {
+        existing
+            .as_ref()
+            .map(|existing| existing.ignore.clone())
+            .unwrap_or_default()
     } else {
-        fs::copy(&dotfile_path.abs_path, &dotfile_path.abs_target).with_context(|| {
-            format!(
-                "Failed to copy file from {} to {}",
-                dotfile_path.abs_path.display(),
-                dotfile_path.abs_target.display()
-            )
-        })?;
+        options.ignore
+    };
+    let ignore_rules = config.ignore_rules(&ignore)?.with_symlinks(symlinks);
+
+    if encrypted && template {
+        return Err(anyhow!(
+            "{} cannot be both a template and encrypted",
+            dotfile_path
+        ));
+    }
 
+    let mut plan = Plan::new();
+    if let Some(parent) = dotfile_path.abs_target.parent()
+        && !parent.exists()
+    {
+        plan.push(Operation::CreateDir(parent.to_path_buf()));
+    }
+
+    // The local path already shows the repository copy, as a symlink to it or as an
+    // unfolded directory of file links, so copying it over would lose that copy
+    let shows_repo =
+        existing.as_ref().is_some_and(|existing| {
+            existing.deploy == DeployMode::Unfold && !dotfile_path.abs_path.is_symlink()
+        }) || path_compare::linked_paths(&dotfile_path.abs_path, &dotfile_path.abs_target)
+            .unwrap_or(false);
+
+    if shows_repo {
         println!(
-            "{} Copied file from {} to {}",
-            "✓".green(),
-            dotfile_path.abs_path.display(),
-            dotfile_path.abs_target.display()
+            "{} {} is linked to the repository, keeping the repository copy",
+            "-".dimmed(),
+            dotfile_path
         );
+    } else if encrypted {
+        plan.push(Operation::Encrypt {
+            from: dotfile_path.abs_path.clone(),
+            to: dotfile_path.abs_target.clone(),
+        });
+    } else {
+        // A directory is replaced as a whole instead of merged into the old copy
+        if dotfile_path.abs_path.is_dir() && dotfile_path.abs_target.exists() {
+            plan.push(Operation::Delete(dotfile_path.abs_target.clone()));
+        }
+        plan.push(Operation::Copy {
+            from: dotfile_path.abs_path.clone(),
+            to: dotfile_path.abs_target.clone(),
+            ignore: ignore_rules.clone(),
+        });
+    }
+
+    if privileged && !escalate::is_root() {
+        plan.escalate(config.repo_path(), config.escalation_command());
+    }
+
+    if dry_run {
+        plan.print();
+        return Ok(());
     }
+    plan.execute()?;
 
     let mut entry = DotfileEntry::from_dotpath(&dotfile_path);
     entry.status = DotfileStatus::Tracked;
+    entry.when = if options.when.is_empty() {
+        existing
+            .as_ref()
+            .map(|existing| existing.when.clone())
+            .unwrap_or_default()
+    } else {
+        options.when
+    };
+    entry.template = template;
+    entry.encrypted = encrypted;
+    entry.deploy = deploy;
+    entry.ignore = ignore;
+    entry.symlinks = symlinks;
+    entry.privileged = privileged;
+    // Encrypted entries are tracked by a keyed digest of their plaintext, since the
+    // ciphertext changes on every encryption
+    entry.hash = Some(if encrypted {
+        let plaintext = std::fs::read(&dotfile_path.abs_path)
+            .with_context(|| format!("Failed to read {}", dotfile_path.abs_path.display()))?;
+        secret_digest(dotfile_path.to_name(), &plaintext)?
+    } else {
+        hash_path(&dotfile_path.abs_target, &ignore_rules)
+            .with_context(|| format!("Failed to hash {}", dotfile_path.abs_target.display()))?
+    });
+    entry.modes = config.capture_modes(&dotfile_path, &entry)?;
 
     config.add(&dotfile_path, entry)?;
     config.save()?;
@@ -80,3 +243,138 @@ pub fn add_dotfile(mut config: Config, dotfile_path: DotPath) -> Result<()> {
     println!("{} Added dotfile: {}", "✓".green(), dotfile_path);
     Ok(())
 }
+
+/// The tracked unfolded directory holding `dotfile_path`, the innermost one if nested
+fn unfolded_parent(config: &Config, dotfile_path: &DotPath) -> Option<(DotPath, DotfileEntry)> {
+    config
+        .get()
+        .into_iter()
+        .filter(|(dotpath, entry)| {
+            entry.deploy == DeployMode::Unfold
+                && dotfile_path.abs_path != dotpath.abs_path
+                && dotfile_path.abs_path.starts_with(&dotpath.abs_path)
+        })
+        .max_by_key(|(dotpath, _)| dotpath.abs_path.components().count())
+}
+
+/// Track a file inside an unfolded directory: copy it into the directory's repository
+/// copy and replace it with a link to that copy
+fn add_to_unfolded(
+    mut config: Config,
+    dotfile_path: &DotPath,
+    parent: &DotPath,
+    mut parent_entry: DotfileEntry,
+    options: AddOptions,
+    dry_run: bool,
+) -> Result<()> {
+    if !options.when.is_empty()
+        || options.template
+        || options.encrypt
+        || options.deploy.is_some()
+        || !options.ignore.is_empty()
+        || options.symlinks.is_some()
+    {
+        return Err(anyhow!(
+            "{} is inside the unfolded directory {} and shares its settings, change them by adding {} again",
+            dotfile_path,
+            parent,
+            parent
+        ));
+    }
+
+    if dotfile_path.abs_path.is_dir() {
+        return Err(anyhow!(
+            "{} is a directory inside the unfolded directory {}, add the files in it one by one",
+            dotfile_path,
+            parent
+        ));
+    }
+
+    let ignore = config.entry_rules(&parent_entry)?;
+    let file = dotfile_path.abs_path.strip_prefix(&parent.abs_path)?;
+    if ignore.is_ignored(file, false) {
+        return Err(anyhow!(
+            "{} is left out by the ignore patterns of {}",
+            dotfile_path,
+            parent
+        ));
+    }
+
+    if path_compare::linked_paths(&dotfile_path.abs_path, &dotfile_path.abs_target).unwrap_or(false)
+    {
+        println!(
+            "{} Already tracked in {}: {}",
+            "✓".green(),
+            parent,
+            dotfile_path
+        );
+        return Ok(());
+    }
+
+    let mut plan = Plan::new();
+    if let Some(dir) = dotfile_path.abs_target.parent()
+        && !dir.exists()
+    {
+        plan.push(Operation::CreateDir(dir.to_path_buf()));
+    }
+    plan.push(Operation::Copy {
+        from: dotfile_path.abs_path.clone(),
+        to: dotfile_path.abs_target.clone(),
+        ignore: IgnoreRules::default(),
+    });
+    plan.push(Operation::Delete(dotfile_path.abs_path.clone()));
+    plan.push(Operation::Symlink {
+        link: dotfile_path.abs_path.clone(),
+        target: dotfile_path.abs_target.clone(),
+        relative: config.relative_links(),
+    });
+
+    if let Some(command) = config.escalation_for(&parent_entry) {
+        plan.escalate(config.repo_path(), command);
+    }
+
+    if dry_run {
+        plan.print();
+        return Ok(());
+    }
+    plan.execute()?;
+
+    parent_entry.hash = Some(
+        hash_path(&parent.abs_target, &ignore)
+            .with_context(|| format!("Failed to hash {}", parent.abs_target.display()))?,
+    );
+    parent_entry.modes = config.capture_modes(parent, &parent_entry)?;
+    config.add(parent, parent_entry)?;
+    config.save()?;
+
+    println!(
+        "{} Added {} to the unfolded directory {}",
+        "✓".green(),
+        dotfile_path,
+        parent
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::testing;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_add_writable_system_file_is_not_privileged() {
+        // Local files of the test repositories live outside the home directory
+        let root = Path::new("/tmp/test_dotzilla_add_privileged");
+        let config = testing::repo(root);
+        let dotpath =
+            testing::tracked_file(&config, "hosts", "127.0.0.1", 0o644, AddOptions::default());
+        assert!(dotpath.is_system());
+
+        let config = testing::reload(&config);
+        assert!(!config.get_dotfile(&dotpath).unwrap().privileged);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
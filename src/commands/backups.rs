@@ -0,0 +1,216 @@
+use std::fs;
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{Duration, Local};
+use colored::*;
+
+use crate::models::{BackupKind, BackupStore, Config, DotPath};
+use crate::utils::escalate;
+use crate::utils::path_compare;
+
+pub fn list_backups(config: &Config) -> Result<()> {
+    let backups = BackupStore::new(config.repo_path()).list()?;
+
+    if backups.is_empty() {
+        println!("No backups. Files replaced by 'dotzilla link' are backed up here.");
+        return Ok(());
+    }
+
+    for backup in backups {
+        let kind = match backup.kind {
+            BackupKind::File => "file",
+            BackupKind::Directory => "directory",
+            BackupKind::Symlink => "symlink",
+        };
+        let encrypted = if backup.encrypted { ", encrypted" } else { "" };
+
+        println!(
+            "{} {} {} {}",
+            backup.id.yellow(),
+            backup.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            backup.name.display(),
+            format!("({}{})", kind, encrypted).dimmed()
+        );
+    }
+
+    Ok(())
+}
+
+/// Put a backup back in place
+///
+/// A symlink into the repository at the original path is simply removed, anything
+/// else there is backed up first so the restore never loses data either.
+pub fn restore_backup(config: &Config, id: &str) -> Result<()> {
+    let store = BackupStore::new(config.repo_path());
+    let backup = store.get(id)?;
+    let dotpath = DotPath::new(config, &backup.name.to_string_lossy());
+    let privileged = backup.privileged
+        || config
+            .get_dotfile(&dotpath)
+            .is_ok_and(|entry| entry.privileged);
+    let escalation = (privileged && !escalate::is_root()).then(|| config.escalation_command());
+
+    if fs::symlink_metadata(&backup.original).is_ok() {
+        let linked = backup.original.is_symlink()
+            && path_compare::linked_paths(&backup.original, &dotpath.abs_target)
+                .map_err(|err| anyhow!("Failed to resolve symlink: {}", err))?;
+
+        if linked {
+            match escalation {
+                Some(command) => {
+                    escalate::run(command, &[&"rm", &"-f", &"--", &backup.original], None)?;
+                }
+                None => fs::remove_file(&backup.original).with_context(|| {
+                    format!("Failed to remove symlink {}", backup.original.display())
+                })?,
+            }
+            println!(
+                "{} Removed symlink at {}",
+                "✓".green(),
+                backup.original.display()
+            );
+        } else {
+            let encrypted = config
+                .get_dotfile(&dotpath)
+                .is_ok_and(|entry| entry.encrypted);
+            let current = store.backup(&dotpath, encrypted, escalation)?;
+            println!(
+                "{} Backed up current {} [{}]",
+                "✓".green(),
+                backup.original.display(),
+                current.id
+            );
+        }
+    }
+
+    store.restore(&backup, escalation)?;
+
+    println!(
+        "{} Restored {} from backup {}",
+        "✓".green(),
+        backup.original.display(),
+        backup.id
+    );
+    Ok(())
+}
+
+/// Delete backups beyond the newest `keep`, optionally only those older than `older_than` days
+pub fn prune_backups(config: &Config, keep: Option<usize>, older_than: Option<u32>) -> Result<()> {
+    if keep.is_none() && older_than.is_none() {
+        return Err(anyhow!(
+            "Specify --keep <n> or --older-than <days> to choose which backups to prune"
+        ));
+    }
+
+    let store = BackupStore::new(config.repo_path());
+    let cutoff = older_than.map(|days| Local::now() - Duration::days(days.into()));
+
+    let pruned: Vec<_> = store
+        .list()?
+        .into_iter()
+        .skip(keep.unwrap_or(0))
+        .filter(|backup| cutoff.is_none_or(|cutoff| backup.timestamp < cutoff))
+        .collect();
+
+    for backup in &pruned {
+        store.remove(&backup.id)?;
+        println!(
+            "{} Removed backup {} of {}",
+            "✓".green(),
+            backup.id,
+            backup.name.display()
+        );
+    }
+
+    println!("{} backups pruned", pruned.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::testing;
+    use std::os::unix::fs::{PermissionsExt, symlink};
+    use std::path::Path;
+
+    #[test]
+    fn test_backup_restore_and_prune() {
+        let root = Path::new("/tmp/test_dotzilla_backups");
+        let config = testing::repo(root);
+        let store = BackupStore::new(config.repo_path());
+        let backups_path = config.repo_path().join(".dotzilla/backups");
+
+        let file = testing::local(&config, "token");
+        fs::write(&file.abs_path, "secret").unwrap();
+        let dir = testing::local(&config, "app");
+        fs::create_dir_all(dir.abs_path.join("sub")).unwrap();
+        fs::write(dir.abs_path.join("sub/app.conf"), "key = 1").unwrap();
+        let link = testing::local(&config, "current");
+        symlink("token", &link.abs_path).unwrap();
+
+        let file_backup = store.backup(&file, false, None).unwrap();
+        let dir_backup = store.backup(&dir, false, None).unwrap();
+        let link_backup = store.backup(&link, false, None).unwrap();
+        assert_eq!(file_backup.kind, BackupKind::File);
+        assert_eq!(dir_backup.kind, BackupKind::Directory);
+        assert_eq!(link_backup.kind, BackupKind::Symlink);
+        for dotpath in [&file, &dir, &link] {
+            assert!(fs::symlink_metadata(&dotpath.abs_path).is_err());
+        }
+
+        // Only the user can read the copies and git never picks them up
+        let mode = fs::metadata(&backups_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        assert_eq!(
+            fs::read_to_string(backups_path.join(".gitignore")).unwrap(),
+            "*\n"
+        );
+        assert_eq!(
+            fs::read_to_string(backups_path.join(&file_backup.id).join("token")).unwrap(),
+            "secret"
+        );
+
+        assert_eq!(store.list().unwrap().len(), 3);
+        assert_eq!(store.get(&dir_backup.id).unwrap().original, dir.abs_path);
+        assert!(store.get("missing").is_err());
+
+        // A restore never overwrites what is in the way
+        fs::write(&file.abs_path, "new").unwrap();
+        assert!(store.restore(&file_backup, None).is_err());
+        fs::remove_file(&file.abs_path).unwrap();
+
+        store.restore(&file_backup, None).unwrap();
+        store.restore(&dir_backup, None).unwrap();
+        assert_eq!(fs::read_to_string(&file.abs_path).unwrap(), "secret");
+        assert_eq!(
+            fs::read_to_string(dir.abs_path.join("sub/app.conf")).unwrap(),
+            "key = 1"
+        );
+        assert!(!backups_path.join(&file_backup.id).exists());
+        assert_eq!(store.list().unwrap().len(), 1);
+
+        // Restoring through the command backs up what took the original's place
+        symlink("elsewhere", &link.abs_path).unwrap();
+        restore_backup(&config, &link_backup.id).unwrap();
+        assert_eq!(fs::read_link(&link.abs_path).unwrap(), Path::new("token"));
+        let backups = store.list().unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_ne!(backups[0].id, link_backup.id);
+
+        store.backup(&file, false, None).unwrap();
+        store.backup(&dir, false, None).unwrap();
+        assert!(prune_backups(&config, None, None).is_err());
+        prune_backups(&config, None, Some(1)).unwrap();
+        assert_eq!(store.list().unwrap().len(), 3);
+        prune_backups(&config, Some(1), None).unwrap();
+        let backups = store.list().unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].original, dir.abs_path);
+        assert_eq!(
+            fs::read_dir(&backups_path).unwrap().count(),
+            3 // `.gitignore`, `manifest.json` and the one backup left
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,382 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use colored::*;
+
+use super::link::{ConflictPolicy, LinkOutcome, plan_link};
+use super::stage::apply_stage;
+use crate::models::{Activation, Config, DotPath, DotfileEntry, Operation, Plan, RepoLock};
+use crate::utils;
+use crate::utils::git;
+use crate::utils::ignore::IgnoreRules;
+
+/// State of the source repository that only belongs to its machine, left out when
+/// copying a plain directory
+const LOCAL_STATE: &[&str] = &[
+    "/.staging/",
+    "/.dotzilla.lock",
+    "/.dotzilla/backups/",
+    "/.dotzilla/lock",
+    "/.dotzilla/journal.json",
+    "/.dotzilla/rendered/",
+];
+
+/// Bootstrap a machine from an existing dotfiles repository
+///
+/// `source` is a git URL or a local path; a local directory that isn't a git repository,
+/// bare or not, is copied. The cloned config is validated, then the plan for linking every active
+/// dotfile is shown and applied after confirmation. Conflicts under `ask` are planned
+/// as backups, so the plan shown is exactly what runs.
+pub fn clone_repo(
+    source: &str,
+    path: PathBuf,
+    profile: Option<&str>,
+    on_conflict: ConflictPolicy,
+    yes: bool,
+) -> Result<()> {
+    if path.exists() && fs::read_dir(&path)?.next().is_some() {
+        return Err(anyhow!(
+            "{} already exists and is not empty, pick another path",
+            path.display()
+        ));
+    }
+
+    let created = !path.exists();
+    if let Err(err) = fetch_repo(source, &path) {
+        remove_clone(&path, created)?;
+        return Err(err);
+    }
+
+    let config = match validate_repo(&path, profile) {
+        Ok(config) => config,
+        Err(err) => {
+            // Nothing but the clone lives there, so don't leave a broken repository behind
+            remove_clone(&path, created)?;
+            return Err(err.context(format!(
+                "{} is not a valid dotzilla repository, removed the clone",
+                source
+            )));
+        }
+    };
+
+    let _lock = RepoLock::acquire(&path)?;
+    link_cloned(config, on_conflict, yes)?;
+
+    if path != utils::expand_tilde("~/dotfiles") {
+        println!(
+            "{} Pass '--repo {}' or set DOTZILLA_REPO to use this repository",
+            "-".dimmed(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Clone a git repository, or copy a plain local directory
+fn fetch_repo(source: &str, path: &Path) -> Result<()> {
+    let local = utils::expand_tilde(source);
+
+    if local.is_dir() && !git::is_repo(&local) && !git::is_bare_repo(&local) {
+        Operation::Copy {
+            from: local.clone(),
+            to: path.to_path_buf(),
+            ignore: IgnoreRules::new(LOCAL_STATE)?,
+        }
+        .apply()?;
+        println!(
+            "{} Copied repository from {} to {}",
+            "✓".green(),
+            local.display(),
+            path.display()
+        );
+        return Ok(());
+    }
+
+    let source = if local.is_dir() {
+        local.to_string_lossy().to_string()
+    } else {
+        source.to_string()
+    };
+    git::clone(&source, path)?;
+    println!("{} Cloned {} into {}", "✓".green(), source, path.display());
+    Ok(())
+}
+
+/// Remove what a failed clone left at `path`
+///
+/// The directory was empty before, so all of its contents came from this run; it is only
+/// removed itself when this run created it.
+fn remove_clone(path: &Path, created: bool) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    if created {
+        return fs::remove_dir_all(path)
+            .with_context(|| format!("Failed to remove {}", path.display()));
+    }
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Load the cloned config and check that every tracked dotfile has a repository copy
+fn validate_repo(path: &Path, profile: Option<&str>) -> Result<Config> {
+    let mut config = Config::load(path)?;
+    config.select_profile(profile)?;
+
+    let staging_path = path.join(".staging");
+    fs::create_dir_all(&staging_path).with_context(|| {
+        format!(
+            "Failed to create staging directory at {}",
+            staging_path.display()
+        )
+    })?;
+
+    let dotfiles = config.get();
+    let mut missing: Vec<&DotPath> = dotfiles
+        .keys()
+        .filter(|dotpath| fs::symlink_metadata(&dotpath.abs_target).is_err())
+        .collect();
+    missing.sort_by_key(|dotpath| dotpath.to_name());
+
+    println!(
+        "{} Found {} tracked dotfile(s) in {}",
+        "✓".green(),
+        dotfiles.len(),
+        config.format().file_name()
+    );
+    for dotpath in missing {
+        println!(
+            "{} {}: the repository copy is missing, it won't be linked",
+            "!".yellow(),
+            dotpath.to_name().display()
+        );
+    }
+    if let Some(profile) = config.active_profile() {
+        println!("Profile: {}", profile.bold());
+    }
+
+    Ok(config)
+}
+
+/// Plan linking every active dotfile, show the plan and apply it once confirmed
+fn link_cloned(mut config: Config, on_conflict: ConflictPolicy, yes: bool) -> Result<()> {
+    let relative = config.relative_links();
+    let mut active: Vec<(DotPath, DotfileEntry)> = config.get_active().into_iter().collect();
+    active.sort_by_key(|(dotpath, _)| dotpath.to_name().clone());
+
+    let mut planned: Vec<(DotPath, Plan, LinkOutcome)> = Vec::new();
+    let mut error_count = 0;
+    let mut skipped_count = 0;
+    for (dotpath, entry) in &active {
+        if let Activation::Inactive(reason) = entry.activation() {
+            println!(
+                "{} Skipped {}: condition not met: {}",
+                "-".dimmed(),
+                dotpath.to_name().display(),
+                reason
+            );
+            skipped_count += 1;
+            continue;
+        }
+        if fs::symlink_metadata(&dotpath.abs_target).is_err() {
+            skipped_count += 1;
+            continue;
+        }
+
+        // Planned as a dry run, which resolves `ask` to a backup without prompting
+        match plan_link(&config, dotpath, entry, relative, on_conflict, true) {
+            Ok((_, LinkOutcome::Skipped)) => skipped_count += 1,
+            Ok((plan, outcome)) => planned.push((dotpath.clone(), plan, outcome)),
+            Err(e) => {
+                println!(
+                    "{} Can't link {}: {:#}",
+                    "✗".red(),
+                    dotpath.to_name().display(),
+                    e
+                );
+                error_count += 1;
+            }
+        }
+    }
+
+    planned.retain(|(_, plan, outcome)| {
+        !plan.is_empty() || matches!(outcome, LinkOutcome::Adopted(_))
+    });
+    if planned.is_empty() {
+        println!("{} Nothing to link", "✓".green());
+        return Ok(());
+    }
+
+    println!("{}", "Planned operations:".bold());
+    for (_, plan, _) in &planned {
+        plan.print_operations();
+    }
+
+    if !yes && !utils::confirm("Apply this plan?", Some(false)) {
+        println!("Nothing was linked. Run 'dotzilla link' when ready.");
+        return Ok(());
+    }
+
+    let mut success_count = 0;
+    let mut adopted_count = 0;
+    for (dotpath, plan, outcome) in planned {
+        let result = match &outcome {
+            LinkOutcome::Adopted(staged_entry) => {
+                apply_stage(&mut config, &dotpath, &plan, staged_entry.clone())
+            }
+            _ => plan.execute(),
+        };
+
+        match (result, outcome) {
+            (Err(e), _) => {
+                println!(
+                    "{} Failed to link {}: {:#}",
+                    "✗".red(),
+                    dotpath.to_name().display(),
+                    e
+                );
+                error_count += 1;
+            }
+            (Ok(()), LinkOutcome::Adopted(_)) => adopted_count += 1,
+            (Ok(()), _) => success_count += 1,
+        }
+    }
+
+    println!(
+        "{} linked successfully, {} failed, {} skipped, {} adopted",
+        success_count, error_count, skipped_count, adopted_count
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn run_git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=Dotzilla Test"])
+            .args(["-c", "user.email=test@dotzilla.invalid"])
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    /// A bare repository holding a single committed `bashrc`
+    fn bare_repo(root: &Path) -> PathBuf {
+        let work = root.join("work");
+        fs::create_dir_all(&work).unwrap();
+        run_git(&work, &["init", "--quiet"]);
+        fs::write(work.join("bashrc"), "one").unwrap();
+        run_git(&work, &["add", "bashrc"]);
+        run_git(&work, &["commit", "--quiet", "-m", "Add bashrc"]);
+
+        let bare = root.join("src.git");
+        run_git(
+            root,
+            &["clone", "--quiet", "--bare", "work", bare.to_str().unwrap()],
+        );
+        bare
+    }
+
+    #[test]
+    fn test_fetch_bare_repo_clones() {
+        let root = Path::new("/tmp/test_dotzilla_clone_bare");
+        let _ = fs::remove_dir_all(root);
+        let bare = bare_repo(root);
+
+        let dest = root.join("dots");
+        fetch_repo(bare.to_str().unwrap(), &dest).unwrap();
+        assert!(git::is_repo(&dest));
+        assert_eq!(fs::read_to_string(dest.join("bashrc")).unwrap(), "one");
+        assert!(!dest.join("HEAD").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_fetch_plain_directory_leaves_out_local_state() {
+        let root = Path::new("/tmp/test_dotzilla_clone_plain");
+        let _ = fs::remove_dir_all(root);
+        let source = root.join("src");
+        for dir in [".staging", ".dotzilla/history", ".dotzilla/backups/1"] {
+            fs::create_dir_all(source.join(dir)).unwrap();
+        }
+        for file in [
+            ".bashrc",
+            ".dotzilla.json",
+            ".staging/.bashrc",
+            ".dotzilla/history/1.json",
+            ".dotzilla/backups/1/.bashrc",
+            ".dotzilla/lock",
+            ".dotzilla/journal.json",
+            ".dotzilla.lock",
+        ] {
+            fs::write(source.join(file), file).unwrap();
+        }
+
+        let dest = root.join("dots");
+        fetch_repo(source.to_str().unwrap(), &dest).unwrap();
+        for kept in [".bashrc", ".dotzilla.json", ".dotzilla/history/1.json"] {
+            assert!(dest.join(kept).exists(), "{} should be copied", kept);
+        }
+        for left_out in [
+            ".staging",
+            ".dotzilla/backups",
+            ".dotzilla/lock",
+            ".dotzilla/journal.json",
+            ".dotzilla.lock",
+        ] {
+            assert!(!dest.join(left_out).exists(), "{} was copied", left_out);
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_failed_clone_keeps_existing_directory() {
+        let root = Path::new("/tmp/test_dotzilla_clone_invalid");
+        let _ = fs::remove_dir_all(root);
+        let bare = bare_repo(root);
+
+        // The repository has no manifest, so validating the clone fails
+        let existing = root.join("existing");
+        fs::create_dir_all(&existing).unwrap();
+        let result = clone_repo(
+            bare.to_str().unwrap(),
+            existing.clone(),
+            None,
+            ConflictPolicy::Skip,
+            true,
+        );
+        assert!(result.is_err());
+        assert!(existing.is_dir());
+        assert_eq!(fs::read_dir(&existing).unwrap().count(), 0);
+
+        let created = root.join("created");
+        let result = clone_repo(
+            bare.to_str().unwrap(),
+            created.clone(),
+            None,
+            ConflictPolicy::Skip,
+            true,
+        );
+        assert!(result.is_err());
+        assert!(!created.exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
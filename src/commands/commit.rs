@@ -1,97 +1,423 @@
-use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
 use colored::*;
-use std::fs;
 
-use crate::models::{Config, DotfileStatus};
+use super::stage::{apply_stage, plan_stage};
+use crate::models::{
+    Activation, CommitRecord, Config, DeployMode, DotPath, DotfileEntry, DotfileStatus, GitMode,
+    History, Journal, Plan, decrypt_dotfile, secret_digest,
+};
+use crate::utils;
+use crate::utils::git;
+use crate::utils::hash::hash_path;
+use crate::utils::mode;
+use crate::utils::path_compare::{self, ChangeResult};
+
+pub fn commit_dotfiles(config: &mut Config, message: Option<String>, dry_run: bool) -> Result<()> {
+    let (mut plan, pulled) = pull_local_edits(config, dry_run)?;
 
-pub fn commit_dotfiles(config: &mut Config) -> Result<()> {
-    if config.get().is_empty() {
+    if config.get_staged().is_empty() && pulled.is_empty() {
         return Err(anyhow!(
             "No dotfiles staged for commit. Use 'dotzilla stage <name>' to stage dotfiles."
         ));
     }
 
-    let mut commit_count = 0;
+    let mut dotpaths: Vec<DotPath> = config.get_staged().into_keys().collect();
+    dotpaths.extend(pulled.iter().cloned());
+    dotpaths.sort_by_key(|dotpath| dotpath.to_name().clone());
 
-    for (dotpath, mut entry_staged) in std::mem::take(&mut config.get_staged()) {
-        if let Some(parent) = dotpath.abs_target.parent() {
-            if !parent.exists() {
-                fs::create_dir_all(parent)?;
-                println!("{} Created directory: {}", "✓".green(), parent.display());
+    if dry_run {
+        // Local edits are only pulled into staging by the real run
+        let mut journal = Journal::new(config.repo_path(), &dotpaths)?;
+        for step in &mut journal.steps {
+            if pulled.iter().any(|dotpath| *dotpath.to_name() == step.name) {
+                step.has_staged = true;
             }
         }
 
-        if dotpath.target_staged.exists() {
-            let mut bpk_path = dotpath.abs_target.clone();
-            bpk_path.set_file_name(format!(
-                "{}-dz-bpk",
-                bpk_path.file_name().unwrap_or_default().to_string_lossy()
-            ));
-
-            if dotpath.abs_target.exists() {
-                fs::rename(&dotpath.abs_target, &bpk_path).map_err(|err| {
-                    anyhow!(
-                        "Failed to back up existing file: {}: {}",
-                        dotpath.abs_target.display(),
-                        err
-                    )
-                })?;
+        plan.extend(journal.plan());
+        plan.print();
+        match config.git_mode() {
+            GitMode::Off => {}
+            GitMode::Commit => {
+                println!("{} The commit would also be recorded in git", "-".dimmed())
             }
+            GitMode::Push => println!(
+                "{} The commit would also be recorded in git and pushed",
+                "-".dimmed()
+            ),
+        }
+        return Ok(());
+    }
+
+    let mut journal = Journal::begin(config.repo_path(), &dotpaths)?;
+
+    let record_id = match apply_commit(config, &mut journal, &dotpaths, message.clone()) {
+        Ok(record_id) => record_id,
+        Err(err) => {
+            roll_back(config.repo_path(), journal)
+                .context("Failed to roll back the commit, run dotzilla again to recover it")?;
+            return Err(err.context("Commit failed, all changes were rolled back"));
+        }
+    };
+
+    journal.finish()?;
+
+    for dotpath in &dotpaths {
+        println!(
+            "{} Committed dotfile: {}",
+            "✓".green(),
+            dotpath.to_name().display()
+        );
+    }
+
+    if dotpaths.len() == 1 {
+        println!("{} Committed 1 dotfile [{}]", "✓".green(), record_id);
+    } else {
+        println!(
+            "{} Committed {} dotfiles [{}]",
+            "✓".green(),
+            dotpaths.len(),
+            record_id
+        );
+    }
+
+    if !config.git_mode().is_off() {
+        // The dotzilla commit already stands, a failing git step only warns
+        if let Err(err) = record_in_git(config, &dotpaths, &record_id, message.as_deref()) {
+            println!("{} {:#}", "!".yellow(), err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Commit the repository copies, the config and the history snapshot to git,
+/// pushing them when the repository asks for it
+fn record_in_git(
+    config: &Config,
+    dotpaths: &[DotPath],
+    record_id: &str,
+    message: Option<&str>,
+) -> Result<()> {
+    let repo_path = config.repo_path();
+    if !git::is_repo(repo_path) {
+        return Err(anyhow!(
+            "{} is not a git repository, skipped the git commit",
+            repo_path.display()
+        ));
+    }
+
+    let mut paths: Vec<PathBuf> = dotpaths
+        .iter()
+        .map(|dotpath| dotpath.abs_target.clone())
+        .collect();
+    paths.push(repo_path.join(config.format().file_name()));
+    paths.push(History::new(repo_path).commit_path(record_id));
+
+    let message = git_message(dotpaths, record_id, message);
+    match git::commit_paths(repo_path, &paths, &message)? {
+        Some(hash) => println!("{} Created git commit {}", "✓".green(), hash),
+        None => println!("{} Nothing changed for git", "-".dimmed()),
+    }
+
+    if config.git_mode() == GitMode::Push {
+        git::push(repo_path).context("Failed to push the git commit")?;
+        match git::upstream(repo_path) {
+            Some(upstream) => println!("{} Pushed to {}", "✓".green(), upstream.name),
+            None => println!("{} Pushed the git commit", "✓".green()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Git commit message listing the committed dotfiles, under the user's message if given
+fn git_message(dotpaths: &[DotPath], record_id: &str, message: Option<&str>) -> String {
+    let subject = match (message, dotpaths) {
+        (Some(message), _) => message.to_string(),
+        (None, [dotpath]) => format!("Update {}", dotpath.to_name().display()),
+        (None, _) => format!("Update {} dotfiles", dotpaths.len()),
+    };
+
+    let files: Vec<String> = dotpaths
+        .iter()
+        .map(|dotpath| format!("- {}", dotpath.to_name().display()))
+        .collect();
+
+    format!(
+        "{}\n\n{}\n\nDotzilla-Commit: {}\n",
+        subject,
+        files.join("\n"),
+        record_id
+    )
+}
+
+fn apply_commit(
+    config: &mut Config,
+    journal: &mut Journal,
+    dotpaths: &[DotPath],
+    message: Option<String>,
+) -> Result<String> {
+    journal.apply()?;
+
+    let mut next = config.clone();
+    promote_staged(&mut next, dotpaths)?;
+
+    let record_id = record_commit(&next, journal, message, dotpaths)?;
+
+    next.save()
+        .context("Failed to save config after committing dotfiles")?;
+    *config = next;
 
-            fs::rename(&dotpath.abs_target_staged, &dotpath.abs_target)?;
-            println!(
-                "{} Copied file from staging to target: {} -> {}",
-                "✓".green(),
-                dotpath.target_staged.display(),
-                dotpath.abs_target.display()
-            );
-
-            if bpk_path.exists() {
-                if bpk_path.is_dir() {
-                    fs::remove_dir_all(&bpk_path).map_err(|err| {
-                        anyhow!(
-                            "Failed to remove backup directory: {}: {}",
-                            bpk_path.display(),
-                            err
-                        )
-                    })?;
-                } else {
-                    fs::remove_file(&bpk_path).map_err(|err| {
-                        anyhow!(
-                            "Failed to remove backup file: {}: {}",
-                            bpk_path.display(),
-                            err
-                        )
-                    })?;
+    Ok(record_id)
+}
+
+/// Snapshot the committed dotfiles into the history, noting the snapshot in the journal
+/// first so a rollback finds it whenever a crash lands
+fn record_commit(
+    config: &Config,
+    journal: &mut Journal,
+    message: Option<String>,
+    dotpaths: &[DotPath],
+) -> Result<String> {
+    let history = History::new(config.repo_path());
+    // What an interrupted run left of its snapshot may be incomplete
+    if let Some(commit_id) = &journal.commit_id {
+        history.remove(commit_id)?;
+    }
+
+    let record = CommitRecord::new(message, config, dotpaths);
+    journal.mark_recording(&record.id)?;
+    history.record(&record, dotpaths)?;
+    journal.mark_committed()?;
+
+    Ok(record.id)
+}
+
+/// Undo a commit together with whatever of its history snapshot was written
+fn roll_back(repo_path: &Path, journal: Journal) -> Result<()> {
+    if let Some(commit_id) = &journal.commit_id {
+        History::new(repo_path).remove(commit_id)?;
+    }
+    journal.rollback()
+}
+
+/// Stage copy-deployed dotfiles whose local copy was edited since the last commit,
+/// so the commit brings those edits back into the repository
+///
+/// A dry run stages nothing and returns the staging plan and the dotfiles it covers.
+fn pull_local_edits(config: &mut Config, dry_run: bool) -> Result<(Plan, Vec<DotPath>)> {
+    let staged = config.get_staged();
+    let mut candidates: Vec<(DotPath, DotfileEntry)> = config
+        .get_active()
+        .into_iter()
+        .filter(|(dotpath, entry)| {
+            !entry.deploy.uses_symlinks()
+                && !entry.template
+                && !staged.contains_key(dotpath)
+                && entry.activation() == Activation::Active
+        })
+        .collect();
+    candidates.sort_by_key(|(dotpath, _)| dotpath.to_name().clone());
+
+    let mut plan = Plan::new();
+    let mut pulled = Vec::new();
+    for (dotpath, entry) in candidates {
+        let Some(baseline) = entry.hash.as_deref() else {
+            continue;
+        };
+
+        // A symlink isn't a deployed copy, and edits through a hard link already
+        // landed in the repository
+        if !dotpath.abs_path.exists()
+            || !dotpath.abs_target.exists()
+            || dotpath.abs_path.is_symlink()
+            || (entry.deploy == DeployMode::Hardlink
+                && path_compare::hardlinked_paths(&dotpath.abs_path, &dotpath.abs_target))
+        {
+            continue;
+        }
+
+        let ignore = config.entry_rules(&entry)?;
+        let local_hash = if entry.encrypted {
+            let local = std::fs::read(&dotpath.abs_path)
+                .with_context(|| format!("Failed to read {}", dotpath.abs_path.display()))?;
+            secret_digest(dotpath.to_name(), &local)?
+        } else {
+            hash_path(&dotpath.abs_path, &ignore)
+                .with_context(|| format!("Failed to hash {}", dotpath.abs_path.display()))?
+        };
+        if local_hash == baseline {
+            continue;
+        }
+
+        let repo_hash = if entry.encrypted {
+            secret_digest(dotpath.to_name(), &decrypt_dotfile(&dotpath.abs_target)?)?
+        } else {
+            hash_path(&dotpath.abs_target, &ignore)
+                .with_context(|| format!("Failed to hash {}", dotpath.abs_target.display()))?
+        };
+
+        match path_compare::change_from_hashes(&local_hash, &repo_hash, baseline) {
+            ChangeResult::LocalChanged => {
+                let (stage_plan, staged_entry) = plan_stage(config, &dotpath)?;
+                if dry_run {
+                    plan.extend(stage_plan);
+                    pulled.push(dotpath);
+                    continue;
                 }
+
+                apply_stage(config, &dotpath, &stage_plan, staged_entry)?;
                 println!(
-                    "{} Removed backup file: {}",
+                    "{} Pulled local edits of {}",
                     "✓".green(),
-                    bpk_path.display()
+                    dotpath.to_name().display()
                 );
             }
+            ChangeResult::BothChanged => println!(
+                "{} {} changed both locally and in the repository, stage it to keep the local copy",
+                "!".yellow(),
+                dotpath.to_name().display()
+            ),
+            ChangeResult::Unchanged | ChangeResult::RepoChanged => {}
         }
+    }
 
-        config.unstage(&dotpath)?;
+    Ok((plan, pulled))
+}
 
-        entry_staged.status = DotfileStatus::Tracked;
+fn promote_staged(config: &mut Config, dotpaths: &[DotPath]) -> Result<()> {
+    let staged = config.get_staged();
 
-        config.add(&dotpath, entry_staged)?;
+    for dotpath in dotpaths {
+        let Some(mut entry) = staged.get(dotpath).cloned() else {
+            continue;
+        };
 
-        println!(
-            "{} Committed dotfile: {}",
-            "✓".green(),
-            dotpath.to_name().display()
+        entry.status = DotfileStatus::Tracked;
+        // Encrypted entries keep the plaintext digest taken when they were staged
+        if dotpath.abs_target.exists() && !entry.encrypted {
+            let ignore = config.entry_rules(&entry)?;
+            entry.hash = Some(hash_path(&dotpath.abs_target, &ignore).map_err(|err| {
+                anyhow!(
+                    "Failed to hash committed file: {}: {}",
+                    dotpath.abs_target.display(),
+                    err
+                )
+            })?);
+        }
+
+        // The staged copy may have lost its modes on the way, e.g. when it was read
+        // through the escalation command; the repository copy stays owned by the user
+        if dotpath.abs_target.exists() && !entry.encrypted {
+            mode::restore(&dotpath.abs_target, &mode::without_owners(&entry.modes)).with_context(
+                || {
+                    format!(
+                        "Failed to restore the modes of {}",
+                        dotpath.abs_target.display()
+                    )
+                },
+            )?;
+        }
+
+        config.promote_staged(dotpath, entry);
+    }
+
+    Ok(())
+}
+
+/// Detect a commit interrupted by a crash and offer to finish or roll it back
+pub fn recover_commit(repo_path: &Path) -> Result<()> {
+    let Some(mut journal) = Journal::load(repo_path)? else {
+        return Ok(());
+    };
+
+    println!(
+        "{} Found an interrupted commit started at {}",
+        "!".yellow(),
+        journal.started.format("%Y-%m-%d %H:%M:%S")
+    );
+
+    let finish = journal.committed
+        || utils::confirm(
+            "Finish the interrupted commit? Answer 'n' to roll it back",
+            Some(true),
         );
 
-        commit_count += 1;
+    if !finish {
+        roll_back(repo_path, journal)?;
+        println!("{} Rolled back the interrupted commit", "✓".green());
+        return Ok(());
     }
 
-    if commit_count == 1 {
-        println!("{} Committed 1 dotfile", "✓".green());
-    } else {
-        println!("{} Committed {} dotfiles", "✓".green(), commit_count);
+    journal.apply()?;
+
+    let mut config = Config::load(repo_path)?;
+    let dotpaths: Vec<DotPath> = journal
+        .steps
+        .iter()
+        .map(|step| DotPath::from_path(&config, step.name.clone()))
+        .collect();
+    promote_staged(&mut config, &dotpaths)?;
+
+    if !journal.committed {
+        record_commit(&config, &mut journal, None, &dotpaths)?;
     }
 
+    config.save()?;
+    journal.finish()?;
+
+    println!(
+        "{} Finished the interrupted commit of {} dotfile(s)",
+        "✓".green(),
+        dotpaths.len()
+    );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{AddOptions, stage_dotfile, testing};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_roll_back_after_snapshot_before_committed() {
+        let root = Path::new("/tmp/test_dotzilla_commit_roll_back");
+        let config = testing::repo(root);
+        let dotpath = testing::tracked_file(&config, "bashrc", "old", 0o600, AddOptions::default());
+        let mut config = testing::reload(&config);
+        fs::write(&dotpath.abs_path, "new").unwrap();
+        stage_dotfile(&mut config, &dotpath, false).unwrap();
+        // The staged copy lost its recorded mode on the way, committing puts it back
+        let staged = &dotpath.abs_target_staged;
+        fs::set_permissions(staged, fs::Permissions::from_mode(0o640)).unwrap();
+
+        // Crash right after the snapshot is written, before the commit is marked done
+        let dotpaths = vec![dotpath.clone()];
+        let mut journal = Journal::begin(config.repo_path(), &dotpaths).unwrap();
+        journal.apply().unwrap();
+        let mut next = config.clone();
+        promote_staged(&mut next, &dotpaths).unwrap();
+        assert_eq!(testing::mode(&dotpath.abs_target), 0o600);
+        let record = CommitRecord::new(None, &next, &dotpaths);
+        journal.mark_recording(&record.id).unwrap();
+        let history = History::new(config.repo_path());
+        history.record(&record, &dotpaths).unwrap();
+
+        let journal = Journal::load(config.repo_path()).unwrap().unwrap();
+        roll_back(config.repo_path(), journal).unwrap();
+
+        assert!(!history.commit_path(&record.id).exists());
+        assert!(history.list().unwrap().is_empty());
+        assert_eq!(fs::read_to_string(&dotpath.abs_target).unwrap(), "old");
+        assert_eq!(fs::read_to_string(staged).unwrap(), "new");
+        assert_eq!(testing::mode(staged), 0o640);
+        assert!(Journal::load(config.repo_path()).unwrap().is_none());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs;
+
+use anyhow::{Context, Result, anyhow};
+use colored::*;
+
+use crate::models::{Config, ConfigFormat, GitMode};
+
+pub fn convert_config(config: &mut Config, to: Option<ConfigFormat>) -> Result<()> {
+    let from = config.format();
+    let to = to.unwrap_or_else(|| from.other());
+
+    if from == to {
+        println!(
+            "{} Config is already stored as {}",
+            "✓".green(),
+            to.file_name()
+        );
+        return Ok(());
+    }
+
+    config.set_format(to);
+    config.save()?;
+
+    let old_path = config.repo_path().join(from.file_name());
+    fs::remove_file(&old_path)
+        .with_context(|| format!("Failed to remove old config {}", old_path.display()))?;
+
+    println!(
+        "{} Converted config from {} to {}",
+        "✓".green(),
+        from.file_name(),
+        to.file_name()
+    );
+    Ok(())
+}
+
+pub fn set_relative_links(config: &mut Config, enabled: bool) -> Result<()> {
+    config.set_relative_links(enabled);
+    config.save()?;
+
+    if enabled {
+        println!(
+            "{} Relative symlinks are now the default for link",
+            "✓".green()
+        );
+    } else {
+        println!(
+            "{} Absolute symlinks are now the default for link",
+            "✓".green()
+        );
+    }
+    Ok(())
+}
+
+pub fn set_git_mode(config: &mut Config, mode: GitMode) -> Result<()> {
+    config.set_git_mode(mode);
+    config.save()?;
+
+    match mode {
+        GitMode::Off => println!("{} Commit no longer records changes in git", "✓".green()),
+        GitMode::Commit => println!("{} Commit now creates a matching git commit", "✓".green()),
+        GitMode::Push => println!(
+            "{} Commit now creates a matching git commit and pushes it",
+            "✓".green()
+        ),
+    }
+    Ok(())
+}
+
+pub fn set_escalation_command(config: &mut Config, command: String) -> Result<()> {
+    if command.trim().is_empty() {
+        return Err(anyhow!("The escalation command can't be empty"));
+    }
+
+    config.set_escalation_command(command.trim().to_string());
+    config.save()?;
+
+    println!(
+        "{} Privileged dotfiles are now changed through '{}'",
+        "✓".green(),
+        config.escalation_command()
+    );
+    Ok(())
+}
@@ -5,58 +5,468 @@ use std::process::Command;
 
 use anyhow::{Context, Result, anyhow};
 use colored::*;
+use diffy::{DiffOptions, Line, Patch};
+use serde::Serialize;
 
-use crate::models::DotPath;
+use super::OutputFormat;
+use super::status::ModeRecord;
+use crate::models::{Config, DotPath, DotfileEntry, decrypt_dotfile, render_dotfile};
 use crate::utils::diff_tools;
+use crate::utils::ignore::IgnoreRules;
+use crate::utils::mode;
+use crate::utils::walk::{EntryKind, walk};
 
+#[derive(Debug, Clone, Copy, Serialize)]
 enum DiffStatus {
     Added,
     Modified,
     Removed,
 }
 
-pub fn show_diff(dotfile_path: DotPath, tool: Option<String>, word_diff: bool) -> Result<()> {
-    if !dotfile_path.abs_path.exists() {
+impl DiffStatus {
+    /// The word `diff --porcelain` prints for a changed file
+    fn token(self) -> &'static str {
+        match self {
+            DiffStatus::Added => "added",
+            DiffStatus::Modified => "modified",
+            DiffStatus::Removed => "removed",
+        }
+    }
+}
+
+/// How a dotfile compares, as reported by `diff --format json`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+enum DiffState {
+    Identical,
+    Modified,
+    MissingLocal,
+    MissingRepo,
+    TypeMismatch,
+    ModeChanged,
+}
+
+impl DiffState {
+    /// The word `diff --porcelain` prints for the state
+    fn token(self) -> &'static str {
+        match self {
+            DiffState::Identical => "identical",
+            DiffState::Modified => "modified",
+            DiffState::MissingLocal => "missing-local",
+            DiffState::MissingRepo => "missing-repo",
+            DiffState::TypeMismatch => "type-mismatch",
+            DiffState::ModeChanged => "mode-changed",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HunkRecord {
+    old_start: usize,
+    old_lines: usize,
+    new_start: usize,
+    new_lines: usize,
+    /// Lines prefixed with ' ', '-' or '+' like in a unified diff
+    lines: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct FileChange {
+    path: String,
+    change: DiffStatus,
+    /// Hunks turning the repository copy of the file into the local one
+    hunks: Vec<HunkRecord>,
+    #[serde(skip)]
+    patch: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DiffReport {
+    path: String,
+    staged: bool,
+    state: DiffState,
+    /// Hunks turning the repository copy of a file into the local one
+    hunks: Vec<HunkRecord>,
+    /// Changed files of a directory
+    files: Vec<FileChange>,
+    /// Files whose mode or owner differs from the recorded one
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    modes: Vec<ModeRecord>,
+    #[serde(skip)]
+    patch: Option<String>,
+}
+
+/// Diff a dotfile as JSON or porcelain output, returning whether it differs
+pub fn report_diff(
+    config: &Config,
+    dotfile_path: DotPath,
+    staged: bool,
+    format: OutputFormat,
+) -> Result<bool> {
+    let report = diff_report(config, &dotfile_path, staged)?;
+
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for line in porcelain_lines(&report) {
+            println!("{}", line);
+        }
+    }
+
+    Ok(report.state != DiffState::Identical)
+}
+
+/// The lines of `diff --porcelain`
+///
+/// Mode lines come first. A changed file is then printed as a plain unified patch,
+/// anything else as a `<state> <path>` line followed, for a directory, by a
+/// `<change> <path>` line and the patch of each changed file.
+fn porcelain_lines(report: &DiffReport) -> Vec<String> {
+    if report.state == DiffState::Identical {
+        return Vec::new();
+    }
+
+    let mut lines = ModeRecord::porcelain_lines(&report.modes, &report.path);
+    if let Some(patch) = &report.patch {
+        lines.extend(patch.lines().map(str::to_string));
+        return lines;
+    }
+
+    lines.push(format!("{} {}", report.state.token(), report.path));
+    for file in &report.files {
+        let path = Path::new(&report.path).join(&file.path);
+        lines.push(format!("{} {}", file.change.token(), path.display()));
+        if let Some(patch) = &file.patch {
+            lines.extend(patch.lines().map(str::to_string));
+        }
+    }
+    lines
+}
+
+fn diff_report(config: &Config, dotfile_path: &DotPath, staged: bool) -> Result<DiffReport> {
+    let mut report = diff_contents(config, dotfile_path, staged)?;
+
+    // Staged copies aren't deployed, their modes are recorded when they're staged
+    if let Ok(entry) = config.get_dotfile(dotfile_path)
+        && !staged
+    {
+        report.modes = ModeRecord::from_changes(mode::changes(
+            &dotfile_path.abs_path,
+            &entry.deployed_modes(),
+        ));
+        if !report.modes.is_empty() && report.state == DiffState::Identical {
+            report.state = DiffState::ModeChanged;
+        }
+    }
+
+    Ok(report)
+}
+
+fn diff_contents(config: &Config, dotfile_path: &DotPath, staged: bool) -> Result<DiffReport> {
+    let local_path = if staged {
+        &dotfile_path.abs_target_staged
+    } else {
+        &dotfile_path.abs_path
+    };
+    let repo_path = &dotfile_path.abs_target;
+    let entry = config.get_dotfile(dotfile_path).ok();
+    let encrypted = entry.is_some_and(|entry| entry.encrypted);
+    let template = entry.is_some_and(|entry| entry.template) && !staged;
+    let ignore = match entry {
+        Some(entry) => config.entry_rules(entry)?,
+        None => config.ignore_rules(&[])?,
+    };
+
+    let mut report = DiffReport {
+        path: dotfile_path.to_name().display().to_string(),
+        staged,
+        state: DiffState::Identical,
+        hunks: Vec::new(),
+        files: Vec::new(),
+        modes: Vec::new(),
+        patch: None,
+    };
+
+    if !local_path.exists() {
+        report.state = DiffState::MissingLocal;
+        return Ok(report);
+    }
+    if !repo_path.exists() {
+        report.state = DiffState::MissingRepo;
+        return Ok(report);
+    }
+    if local_path.is_dir() != repo_path.is_dir() {
+        report.state = DiffState::TypeMismatch;
+        return Ok(report);
+    }
+
+    if local_path.is_dir() {
+        let mut differences: Vec<(PathBuf, DiffStatus)> =
+            compare_directories(repo_path, local_path, &ignore)?
+                .into_iter()
+                .collect();
+        differences.sort_by(|a, b| a.0.cmp(&b.0));
+
+        if !differences.is_empty() {
+            report.state = DiffState::Modified;
+        }
+        report.files = differences
+            .into_iter()
+            .map(|(path, change)| {
+                // Only regular files have content to diff, a missing side is empty
+                let content = |dir: &Path| {
+                    let file = dir.join(&path);
+                    match fs::symlink_metadata(&file) {
+                        Ok(metadata) if metadata.is_file() => fs::read(&file)
+                            .map(|content| String::from_utf8_lossy(&content).into_owned())
+                            .with_context(|| format!("Failed to read {}", file.display())),
+                        _ => Ok(String::new()),
+                    }
+                };
+                let repo_content = content(repo_path)?;
+                let local_content = content(local_path)?;
+
+                let name = Path::new(&report.path).join(&path);
+                let patch = DiffOptions::new()
+                    .set_original_filename(format!("a/{}", name.display()))
+                    .set_modified_filename(format!("b/{}", name.display()))
+                    .create_patch(&repo_content, &local_content);
+                let hunks = hunk_records(&patch);
+                Ok(FileChange {
+                    path: path.display().to_string(),
+                    change,
+                    patch: (!hunks.is_empty()).then(|| patch.to_string()),
+                    hunks,
+                })
+            })
+            .collect::<Result<_>>()?;
+        return Ok(report);
+    }
+
+    let local_content = if encrypted && staged {
+        decrypt_dotfile(local_path)?
+    } else {
+        fs::read(local_path)
+            .with_context(|| format!("Failed to read local file: {}", local_path.display()))?
+    };
+    let repo_content = if encrypted {
+        decrypt_dotfile(repo_path)?
+    } else if template {
+        render_dotfile(config.repo_path(), dotfile_path)?.into_bytes()
+    } else {
+        fs::read(repo_path)
+            .with_context(|| format!("Failed to read repository file: {}", repo_path.display()))?
+    };
+
+    let local_content = String::from_utf8_lossy(&local_content);
+    let repo_content = String::from_utf8_lossy(&repo_content);
+    if local_content == repo_content {
+        return Ok(report);
+    }
+
+    let patch = diffy::create_patch(&repo_content, &local_content);
+    report.state = DiffState::Modified;
+    report.hunks = hunk_records(&patch);
+    report.patch = Some(patch.to_string());
+
+    Ok(report)
+}
+
+fn hunk_records(patch: &Patch<'_, str>) -> Vec<HunkRecord> {
+    patch
+        .hunks()
+        .iter()
+        .map(|hunk| HunkRecord {
+            old_start: hunk.old_range().start(),
+            old_lines: hunk.old_range().len(),
+            new_start: hunk.new_range().start(),
+            new_lines: hunk.new_range().len(),
+            lines: hunk
+                .lines()
+                .iter()
+                .map(|line| {
+                    let (prefix, text) = match line {
+                        Line::Context(text) => (' ', text),
+                        Line::Delete(text) => ('-', text),
+                        Line::Insert(text) => ('+', text),
+                    };
+                    format!("{}{}", prefix, text.trim_end_matches('\n'))
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+pub fn show_diff(
+    config: &Config,
+    dotfile_path: DotPath,
+    tool: Option<String>,
+    word_diff: bool,
+    staged: bool,
+) -> Result<()> {
+    let (local_path, local_label) = if staged {
+        (&dotfile_path.abs_target_staged, "Staged path")
+    } else {
+        (&dotfile_path.abs_path, "Local path")
+    };
+
+    let entry = config.get_dotfile(&dotfile_path).ok();
+    if let Some(entry) = entry
+        && !staged
+    {
+        print_mode_changes(&dotfile_path, entry);
+    }
+    if entry.is_some_and(|entry| entry.encrypted) {
+        return diff_secret(&dotfile_path, local_path, local_label, staged, tool);
+    }
+
+    let is_template = entry.is_some_and(|entry| entry.template);
+    if !is_template || staged {
+        let ignore = match entry {
+            Some(entry) => config.entry_rules(entry)?,
+            None => config.ignore_rules(&[])?,
+        };
+        return diff_paths(
+            &dotfile_path,
+            local_path,
+            local_label,
+            &dotfile_path.abs_target,
+            tool,
+            word_diff,
+            &ignore,
+        );
+    }
+
+    // Templates are compared against their rendered output, written to a scratch file
+    // so the generated copy a symlink may point at is left untouched
+    let rendered = render_dotfile(config.repo_path(), &dotfile_path)?;
+    let preview_path = std::env::temp_dir().join(format!(
+        "dotzilla-{}-{}",
+        std::process::id(),
+        dotfile_path
+            .abs_target
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+    ));
+    fs::write(&preview_path, rendered)
+        .with_context(|| format!("Failed to write {}", preview_path.display()))?;
+
+    let result = diff_paths(
+        &dotfile_path,
+        local_path,
+        local_label,
+        &preview_path,
+        tool,
+        word_diff,
+        &IgnoreRules::default(),
+    );
+    let _ = fs::remove_file(&preview_path);
+    result
+}
+
+/// Print the files whose mode or owner differs from the recorded one, like git's
+/// `old mode` and `new mode` lines
+fn print_mode_changes(dotfile_path: &DotPath, entry: &DotfileEntry) {
+    for change in mode::changes(&dotfile_path.abs_path, &entry.deployed_modes()) {
+        let path = mode::resolve(dotfile_path.to_name(), &change.path);
+        println!("{} {}", "✦".cyan(), path.display());
+        println!("{}", format!("old mode {}", change.expected).red());
+        println!("{}", format!("new mode {}", change.actual).green());
+    }
+}
+
+/// Diff an encrypted dotfile without ever writing its plaintext to disk
+fn diff_secret(
+    dotfile_path: &DotPath,
+    local_path: &Path,
+    local_label: &str,
+    staged: bool,
+    tool: Option<String>,
+) -> Result<()> {
+    if tool.is_some() {
+        return Err(anyhow!(
+            "External diff tools are not supported for encrypted dotfiles, {} is only decrypted in memory",
+            dotfile_path
+        ));
+    }
+
+    if !local_path.exists() {
         println!(
-            "{} Local path does not exist: {}",
+            "{} {} does not exist: {}",
             "✗".red(),
-            dotfile_path.abs_path.display()
+            local_label,
+            local_path.display()
         );
         return Ok(());
     }
 
-    if !&dotfile_path.abs_target.exists() {
+    let local_content = if staged {
+        decrypt_dotfile(local_path)?
+    } else {
+        fs::read(local_path)
+            .with_context(|| format!("Failed to read local file: {}", local_path.display()))?
+    };
+    let repo_content = decrypt_dotfile(&dotfile_path.abs_target)?;
+
+    print_patch(
+        dotfile_path,
+        &String::from_utf8_lossy(&local_content),
+        &String::from_utf8_lossy(&repo_content),
+    );
+    Ok(())
+}
+
+fn diff_paths(
+    dotfile_path: &DotPath,
+    local_path: &Path,
+    local_label: &str,
+    repo_path: &Path,
+    tool: Option<String>,
+    word_diff: bool,
+    ignore: &IgnoreRules,
+) -> Result<()> {
+    if !local_path.exists() {
+        println!(
+            "{} {} does not exist: {}",
+            "✗".red(),
+            local_label,
+            local_path.display()
+        );
+        return Ok(());
+    }
+
+    if !repo_path.exists() {
         println!(
             "{} Repository path does not exist: {}",
             "✗".red(),
-            &dotfile_path.abs_target.display()
+            repo_path.display()
         );
         return Ok(());
     }
 
-    if dotfile_path.abs_path.is_dir() && dotfile_path.abs_target.is_dir() {
-        return diff_directories(dotfile_path, tool);
+    if local_path.is_dir() && repo_path.is_dir() {
+        return diff_directories(dotfile_path, local_path, repo_path, tool, ignore);
     }
 
     // If an external diff tool is specified, use it for both files and directories
     if let Some(tool_name) = tool {
-        return use_external_diff_tool(tool_name, &dotfile_path.abs_path, &dotfile_path.abs_target);
+        return use_external_diff_tool(tool_name, local_path, repo_path);
     }
 
-    if !dotfile_path.abs_path.is_dir() && !&dotfile_path.abs_target.is_dir() {
-        return diff_files(dotfile_path, word_diff);
+    if !local_path.is_dir() && !repo_path.is_dir() {
+        return diff_files(dotfile_path, local_path, repo_path, word_diff);
     }
 
     println!(
         "{} Cannot compare: {} is a directory and {} is a file",
         "✗".red(),
-        if dotfile_path.abs_target.is_dir() {
+        if repo_path.is_dir() {
             "Repository path"
         } else {
-            "Local path"
+            local_label
         },
-        if dotfile_path.abs_target.is_dir() {
-            "Local path"
+        if repo_path.is_dir() {
+            local_label
         } else {
             "Repository path"
         }
@@ -65,18 +475,25 @@ pub fn show_diff(dotfile_path: DotPath, tool: Option<String>, word_diff: bool) -
     Ok(())
 }
 
-fn diff_files(dotfile_path: DotPath, _word_diff: bool) -> Result<()> {
-    let repo_file = &dotfile_path.abs_target;
-    let local_file = &dotfile_path.abs_path;
-
+fn diff_files(
+    dotfile_path: &DotPath,
+    local_file: &Path,
+    repo_file: &Path,
+    _word_diff: bool,
+) -> Result<()> {
     let local_content = fs::read_to_string(local_file)
         .with_context(|| format!("Failed to read local file: {}", local_file.display()))?;
     let repo_content = fs::read_to_string(repo_file)
         .with_context(|| format!("Failed to read repository file: {}", repo_file.display()))?;
 
+    print_patch(dotfile_path, &local_content, &repo_content);
+    Ok(())
+}
+
+fn print_patch(dotfile_path: &DotPath, local_content: &str, repo_content: &str) {
     if local_content == repo_content {
         println!("{} Files are identical", "✓".green());
-        return Ok(());
+        return;
     }
 
     println!(
@@ -90,18 +507,19 @@ fn diff_files(dotfile_path: DotPath, _word_diff: bool) -> Result<()> {
     //     let patch = diffy::create_patch(&repo_content, &local_content);
     //     print!("{}", patch);
     // } else {
-    let patch = diffy::create_patch(&repo_content, &local_content);
+    let patch = diffy::create_patch(repo_content, local_content);
 
     print!("{}", patch);
     // }
-
-    Ok(())
 }
 
-fn diff_directories(dotfile_path: DotPath, tool: Option<String>) -> Result<()> {
-    let repo_dir = &dotfile_path.abs_target;
-    let local_dir = &dotfile_path.abs_path;
-
+fn diff_directories(
+    dotfile_path: &DotPath,
+    local_dir: &Path,
+    repo_dir: &Path,
+    tool: Option<String>,
+    ignore: &IgnoreRules,
+) -> Result<()> {
     if let Some(tool_name) = tool {
         return use_external_diff_tool(tool_name, repo_dir, local_dir);
     }
@@ -112,7 +530,7 @@ fn diff_directories(dotfile_path: DotPath, tool: Option<String>) -> Result<()> {
         dotfile_path.to_name().display()
     );
 
-    let differences = compare_directories(repo_dir, local_dir)?;
+    let differences = compare_directories(repo_dir, local_dir, ignore)?;
 
     if differences.is_empty() {
         println!("{} Directories are identical", "✓".green());
@@ -143,30 +561,39 @@ fn diff_directories(dotfile_path: DotPath, tool: Option<String>) -> Result<()> {
     Ok(())
 }
 
-fn compare_directories(repo_dir: &Path, local_dir: &Path) -> Result<HashMap<PathBuf, DiffStatus>> {
+fn compare_directories(
+    repo_dir: &Path,
+    local_dir: &Path,
+    ignore: &IgnoreRules,
+) -> Result<HashMap<PathBuf, DiffStatus>> {
     let mut differences = HashMap::new();
-    let repo_files = collect_files(repo_dir, repo_dir)?;
-    let local_files = collect_files(local_dir, local_dir)?;
-
-    for (rel_path, repo_path) in &repo_files {
-        let local_path = local_dir.join(rel_path);
-
-        if !local_path.exists() {
-            differences.insert(rel_path.clone(), DiffStatus::Removed);
-        } else if local_path.is_file() && repo_path.is_file() {
-            let repo_content = fs::read(repo_path)?;
-            let local_content = fs::read(&local_path)?;
-
-            if repo_content != local_content {
-                differences.insert(rel_path.clone(), DiffStatus::Modified);
+    let repo_files = collect_files(repo_dir, ignore)?;
+    let local_files = collect_files(local_dir, ignore)?;
+
+    for (rel_path, repo_kind) in &repo_files {
+        let modified = match (repo_kind, local_files.get(rel_path)) {
+            (_, None) => {
+                differences.insert(rel_path.clone(), DiffStatus::Removed);
+                continue;
+            }
+            (EntryKind::File, Some(EntryKind::File)) => {
+                fs::read(repo_dir.join(rel_path))? != fs::read(local_dir.join(rel_path))?
             }
+            (EntryKind::Symlink(repo_value), Some(EntryKind::Symlink(local_value))) => {
+                repo_value != local_value
+            }
+            (EntryKind::Dir, Some(EntryKind::Dir)) => false,
+            // A file replaced by a link or the other way around
+            _ => true,
+        };
+
+        if modified {
+            differences.insert(rel_path.clone(), DiffStatus::Modified);
         }
     }
 
     for rel_path in local_files.keys() {
-        let repo_path = repo_dir.join(rel_path);
-
-        if !repo_path.exists() {
+        if !repo_files.contains_key(rel_path) {
             differences.insert(rel_path.clone(), DiffStatus::Added);
         }
     }
@@ -174,38 +601,26 @@ fn compare_directories(repo_dir: &Path, local_dir: &Path) -> Result<HashMap<Path
     Ok(differences)
 }
 
-fn collect_files(base_dir: &Path, current_dir: &Path) -> Result<HashMap<PathBuf, PathBuf>> {
-    let mut files = HashMap::new();
-
-    if !current_dir.exists() || !current_dir.is_dir() {
-        return Ok(files);
+/// Files and preserved symlinks of a directory tree by relative path, leaving out hidden
+/// files and directories
+fn collect_files(dir: &Path, ignore: &IgnoreRules) -> Result<HashMap<PathBuf, EntryKind>> {
+    if !dir.is_dir() {
+        return Ok(HashMap::new());
     }
 
-    for entry in fs::read_dir(current_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        let metadata = fs::metadata(&path)?;
-
-        // Skip hidden files and directories
-        if path
-            .file_name()
-            .and_then(|name| name.to_str())
-            .map(|name| name.starts_with("."))
-            .unwrap_or(false)
-        {
-            continue;
-        }
-
-        let relative_path = path.strip_prefix(base_dir)?.to_path_buf();
-
-        if metadata.is_file() {
-            files.insert(relative_path, path);
-        } else if metadata.is_dir() {
-            // Recursively collect files from subdirectories
-            let mut subdir_files = collect_files(base_dir, &path)?;
-            files.extend(subdir_files.drain());
-        }
-    }
+    let files = walk(dir, ignore)?
+        .into_iter()
+        .filter(|entry| {
+            !entry.relative.components().any(|component| {
+                component
+                    .as_os_str()
+                    .to_str()
+                    .is_some_and(|name| name.starts_with('.'))
+            })
+        })
+        .filter(|entry| entry.kind != EntryKind::Dir)
+        .map(|entry| (entry.relative, entry.kind))
+        .collect();
 
     Ok(files)
 }
@@ -253,3 +668,102 @@ fn diff_command(file1: &Path, file2: &Path) -> Result<(String, Vec<String>)> {
 
     Ok(("diff".to_string(), args))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::testing;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_diff_reports_mode_change() {
+        let root = Path::new("/tmp/test_dotzilla_diff_modes");
+        let config = testing::repo(root);
+        let dotpath =
+            testing::tracked_file(&config, "app.conf", "key = 1", 0o640, Default::default());
+        let config = testing::reload(&config);
+
+        let report = diff_report(&config, &dotpath, false).unwrap();
+        assert_eq!(report.state, DiffState::Identical);
+
+        fs::set_permissions(&dotpath.abs_path, fs::Permissions::from_mode(0o600)).unwrap();
+        let report = diff_report(&config, &dotpath, false).unwrap();
+        assert_eq!(report.state, DiffState::ModeChanged);
+        assert!(report.hunks.is_empty());
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["state"], "ModeChanged");
+        assert_eq!(json["modes"][0]["path"], ".");
+        assert_eq!(json["modes"][0]["expected"]["mode"], "0640");
+        assert_eq!(json["modes"][0]["actual"]["mode"], "0600");
+
+        // Staged copies aren't deployed, so their modes aren't compared
+        let report = diff_report(&config, &dotpath, true).unwrap();
+        assert!(report.modes.is_empty());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_diff_directory_reports() {
+        let root = Path::new("/tmp/test_dotzilla_diff_dir");
+        let config = testing::repo(root);
+        let dotpath = testing::local(&config, "app");
+        fs::create_dir_all(&dotpath.abs_path).unwrap();
+        fs::write(dotpath.abs_path.join("main.conf"), "a = 1\nb = 2\n").unwrap();
+        fs::write(dotpath.abs_path.join("old.conf"), "gone\n").unwrap();
+        crate::commands::add_dotfile(config.clone(), dotpath.clone(), Default::default(), false)
+            .unwrap();
+        let config = testing::reload(&config);
+
+        let report = diff_report(&config, &dotpath, false).unwrap();
+        assert!(porcelain_lines(&report).is_empty());
+        assert_eq!(OutputFormat::Porcelain.exit_code(false), None);
+
+        fs::write(dotpath.abs_path.join("main.conf"), "a = 1\nb = 3\n").unwrap();
+        fs::remove_file(dotpath.abs_path.join("old.conf")).unwrap();
+        fs::write(dotpath.abs_path.join("new.conf"), "fresh\n").unwrap();
+
+        let report = diff_report(&config, &dotpath, false).unwrap();
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["state"], "Modified");
+        let files = json["files"].as_array().unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0]["path"], "main.conf");
+        assert_eq!(files[0]["change"], "Modified");
+        assert_eq!(
+            files[0]["hunks"][0]["lines"],
+            serde_json::json!([" a = 1", "-b = 2", "+b = 3"])
+        );
+        assert_eq!(files[1]["path"], "new.conf");
+        assert_eq!(files[1]["hunks"][0]["lines"], serde_json::json!(["+fresh"]));
+        assert_eq!(files[2]["path"], "old.conf");
+        assert_eq!(files[2]["change"], "Removed");
+        assert_eq!(files[2]["hunks"][0]["lines"], serde_json::json!(["-gone"]));
+
+        let name = &report.path;
+        let lines = porcelain_lines(&report);
+        assert_eq!(lines[0], format!("modified {}", name));
+        assert_eq!(lines[1], format!("modified {}/main.conf", name));
+        assert_eq!(lines[2], format!("--- a/{}/main.conf", name));
+        assert_eq!(lines[3], format!("+++ b/{}/main.conf", name));
+        assert_eq!(lines[4], "@@ -1,2 +1,2 @@");
+        assert!(lines.contains(&format!("added {}/new.conf", name)));
+        assert!(lines.contains(&format!("removed {}/old.conf", name)));
+        assert!(lines.contains(&"-gone".to_string()));
+
+        // Drift in a machine-readable format exits with 2, text output never does
+        let drift = report.state != DiffState::Identical;
+        assert_eq!(OutputFormat::Porcelain.exit_code(drift), Some(2));
+        assert_eq!(OutputFormat::Json.exit_code(drift), Some(2));
+        assert_eq!(OutputFormat::Text.exit_code(drift), None);
+
+        fs::remove_dir_all(&dotpath.abs_path).unwrap();
+        let report = diff_report(&config, &dotpath, false).unwrap();
+        assert_eq!(
+            porcelain_lines(&report),
+            vec![format!("missing-local {}", name)]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,533 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use colored::*;
+
+use super::link::{ConflictPolicy, plan_link};
+use crate::models::{
+    Activation, Config, DeployMode, DotPath, Journal, Operation, Plan, rendered_path,
+};
+use crate::utils::path_compare;
+
+/// Files and directories dotzilla itself keeps at the top of the repository
+const REPO_INTERNALS: &[&str] = &[
+    ".git",
+    ".staging",
+    ".dotzilla",
+    ".dotzilla.json",
+    "dotzilla.toml",
+    // Where the lock was kept before it moved under `.dotzilla`
+    ".dotzilla.lock",
+    ".dotzillaignore",
+];
+
+/// Files git itself reads at the top of the repository
+const GIT_FILES: &[&str] = &[".gitignore", ".gitattributes", ".gitmodules", ".github"];
+
+/// A change `doctor --fix` may apply because it can't lose any data
+enum Fix {
+    /// Run file operations
+    Apply(Plan),
+    /// Drop a staged entry whose staged copy is gone
+    Unstage(DotPath),
+    /// Link a dotfile whose local path is free
+    Relink(DotPath),
+}
+
+struct Problem {
+    message: String,
+    suggestion: String,
+    fix: Option<Fix>,
+}
+
+impl Problem {
+    fn new(message: String, suggestion: String) -> Self {
+        Problem {
+            message,
+            suggestion,
+            fix: None,
+        }
+    }
+
+    fn fixable(message: String, suggestion: String, fix: Fix) -> Self {
+        Problem {
+            message,
+            suggestion,
+            fix: Some(fix),
+        }
+    }
+}
+
+/// Audit the repository, the staging area and the deployed dotfiles
+///
+/// Every problem comes with a suggestion; `fix` applies the ones that can't lose data.
+pub fn run_doctor(config: &mut Config, fix: bool) -> Result<()> {
+    println!("{} Dotzilla Doctor", "✦".cyan());
+    println!("{}", "=================".cyan());
+
+    let mut problems = Vec::new();
+    check_dotfiles(config, &mut problems)?;
+    check_staging(config, &mut problems)?;
+    check_repository(config, &mut problems)?;
+
+    if problems.is_empty() {
+        println!("{} No problems found", "✓".green());
+        return Ok(());
+    }
+
+    let mut fixable = 0;
+    let mut fixed = 0;
+    let mut failed = 0;
+    for problem in problems.iter_mut() {
+        println!("{} {}", "!".yellow(), problem.message);
+        let safe = if problem.fix.is_some() {
+            fixable += 1;
+            " (safe, applied by --fix)"
+        } else {
+            ""
+        };
+        println!("  {} {}{}", "→".dimmed(), problem.suggestion, safe.dimmed());
+
+        if !fix {
+            continue;
+        }
+        if let Some(problem_fix) = problem.fix.take() {
+            match apply_fix(config, problem_fix) {
+                Ok(_) => fixed += 1,
+                Err(e) => {
+                    println!("  {} Failed to fix: {:#}", "✗".red(), e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    println!();
+    if fix {
+        println!(
+            "{} problems found, {} fixed, {} failed to fix",
+            problems.len(),
+            fixed,
+            failed
+        );
+    } else {
+        println!(
+            "{} problems found, {} can be fixed with 'dotzilla doctor --fix'",
+            problems.len(),
+            fixable
+        );
+    }
+
+    Ok(())
+}
+
+fn apply_fix(config: &mut Config, fix: Fix) -> Result<()> {
+    match fix {
+        Fix::Apply(plan) => plan.execute(),
+        Fix::Unstage(dotpath) => {
+            config.unstage(&dotpath)?;
+            println!(
+                "{} Unstaged dotfile: {}",
+                "✓".green(),
+                dotpath.to_name().display()
+            );
+            Ok(())
+        }
+        Fix::Relink(dotpath) => {
+            let entry = config.get_dotfile(&dotpath)?.clone();
+            let (plan, _) = plan_link(
+                config,
+                &dotpath,
+                &entry,
+                config.relative_links(),
+                ConflictPolicy::Skip,
+                false,
+            )?;
+            plan.execute()
+        }
+    }
+}
+
+/// Check every tracked dotfile for a missing repository copy, and every dotfile
+/// active on this machine for a missing, broken or foreign local path
+fn check_dotfiles(config: &Config, problems: &mut Vec<Problem>) -> Result<()> {
+    let active = config.get_active();
+    let mut dotfiles: Vec<_> = config.get().into_iter().collect();
+    dotfiles.sort_by_key(|(dotpath, _)| dotpath.to_name().clone());
+
+    for (dotpath, entry) in dotfiles {
+        let name = dotpath.to_name().display().to_string();
+
+        if fs::symlink_metadata(&dotpath.abs_target).is_err() {
+            problems.push(Problem::new(
+                format!(
+                    "{}: repository copy is missing at {}",
+                    name,
+                    dotpath.abs_target.display()
+                ),
+                format!(
+                    "add it again with 'dotzilla add {}' or stop tracking it with 'dotzilla remove {} --keep'",
+                    name, name
+                ),
+            ));
+            continue;
+        }
+
+        if !active.contains_key(&dotpath) || entry.activation() != Activation::Active {
+            continue;
+        }
+
+        let local = &dotpath.abs_path;
+        if fs::symlink_metadata(local).is_err() {
+            problems.push(Problem::fixable(
+                format!("{}: local path {} is missing", name, local.display()),
+                format!("link it with 'dotzilla link {}'", name),
+                Fix::Relink(dotpath.clone()),
+            ));
+            continue;
+        }
+
+        if !local.is_symlink() {
+            continue;
+        }
+
+        if !local.exists() {
+            problems.push(Problem::fixable(
+                format!("{}: {} is a broken symlink", name, local.display()),
+                format!("replace it with 'dotzilla link {}'", name),
+                Fix::Relink(dotpath.clone()),
+            ));
+            continue;
+        }
+
+        let source = if entry.template {
+            rendered_path(config.repo_path(), &dotpath)
+        } else {
+            dotpath.abs_target.clone()
+        };
+        let ours = [&source, &dotpath.abs_target]
+            .iter()
+            .any(|path| path_compare::linked_paths(local, path).unwrap_or(false));
+
+        if !ours {
+            let target = fs::read_link(local)
+                .with_context(|| format!("Failed to read symlink at {}", local.display()))?;
+            problems.push(Problem::new(
+                format!(
+                    "{}: {} is a symlink to {}, not to the repository",
+                    name,
+                    local.display(),
+                    target.display()
+                ),
+                format!(
+                    "replace it with 'dotzilla link {} --on-conflict backup'",
+                    name
+                ),
+            ));
+        } else if entry.deploy != DeployMode::Symlink {
+            problems.push(Problem::new(
+                format!(
+                    "{}: {} is a symlink but the dotfile is deployed as a {}",
+                    name,
+                    local.display(),
+                    entry.deploy
+                ),
+                format!("deploy it again with 'dotzilla link {}'", name),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that staged entries and files in `.staging` match up
+fn check_staging(config: &Config, problems: &mut Vec<Problem>) -> Result<()> {
+    let mut staged: Vec<_> = config.get_staged().into_keys().collect();
+    staged.sort_by_key(|dotpath| dotpath.to_name().clone());
+
+    for dotpath in &staged {
+        if fs::symlink_metadata(&dotpath.abs_target_staged).is_err() {
+            problems.push(Problem::fixable(
+                format!(
+                    "{}: staged, but {} is missing",
+                    dotpath,
+                    dotpath.abs_target_staged.display()
+                ),
+                format!("unstage it with 'dotzilla unstage {}'", dotpath),
+                Fix::Unstage(dotpath.clone()),
+            ));
+        }
+    }
+
+    let staging_path = config.repo_path().join(".staging");
+    let known: Vec<PathBuf> = staged
+        .iter()
+        .map(|dotpath| dotpath.abs_target_staged.clone())
+        .collect();
+
+    // The staged copy may be the only one left of an edit, so it is never deleted here
+    for orphan in unknown_paths(&staging_path, &known, &|_| false)? {
+        problems.push(Problem::new(
+            format!("{} is in staging without a staged entry", orphan.display()),
+            "compare it with the local and repository copies and delete it once nothing in it is needed".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check the repository for untracked files and for backups left by interrupted commits
+fn check_repository(config: &Config, problems: &mut Vec<Problem>) -> Result<()> {
+    let repo_path = config.repo_path();
+
+    if Journal::load(repo_path)?.is_some() {
+        problems.push(Problem::new(
+            "an interrupted commit is pending".to_string(),
+            "run any command that changes the repository, e.g. 'dotzilla commit', to finish or roll it back".to_string(),
+        ));
+        // The leftover backups belong to that commit
+        return Ok(());
+    }
+
+    let known: Vec<PathBuf> = config
+        .get()
+        .into_keys()
+        .chain(config.get_staged().into_keys())
+        .map(|dotpath| dotpath.abs_target)
+        .collect();
+
+    let skip = |path: &Path| {
+        is_commit_backup(path)
+            || (path.parent() == Some(repo_path)
+                && !known.iter().any(|known| known.starts_with(path))
+                && path.file_name().is_some_and(|name| {
+                    let name = name.to_string_lossy();
+                    REPO_INTERNALS.contains(&name.as_ref())
+                        || is_config_backup(&name)
+                        || is_repo_file(&name)
+                }))
+    };
+
+    for untracked in unknown_paths(repo_path, &known, &skip)? {
+        problems.push(Problem::new(
+            format!(
+                "{} is in the repository but not tracked",
+                untracked.display()
+            ),
+            "track it with 'dotzilla add' or delete it".to_string(),
+        ));
+    }
+
+    for backup in commit_backups(repo_path)? {
+        let original = PathBuf::from(
+            backup
+                .to_string_lossy()
+                .trim_end_matches("-dz-bpk")
+                .to_string(),
+        );
+
+        if fs::symlink_metadata(&original).is_ok() {
+            problems.push(Problem::new(
+                format!(
+                    "{} is a leftover backup from an interrupted commit",
+                    backup.display()
+                ),
+                format!(
+                    "compare it with {} and delete it once nothing in it is needed",
+                    original.display()
+                ),
+            ));
+        } else {
+            let mut plan = Plan::new();
+            plan.push(Operation::Rename {
+                from: backup.clone(),
+                to: original.clone(),
+            });
+            problems.push(Problem::fixable(
+                format!(
+                    "{} is a leftover backup from an interrupted commit and {} is missing",
+                    backup.display(),
+                    original.display()
+                ),
+                format!("move it back to {}", original.display()),
+                Fix::Apply(plan),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// A copy of the config kept by a schema migration, e.g. `.dotzilla.json.v1.bak`
+fn is_config_backup(name: &str) -> bool {
+    (name.starts_with(".dotzilla.json.v") || name.starts_with("dotzilla.toml.v"))
+        && name.ends_with(".bak")
+}
+
+/// A file of the repository itself rather than a dotfile: a readme, a license or one
+/// of the [`GIT_FILES`]
+fn is_repo_file(name: &str) -> bool {
+    GIT_FILES.contains(&name) || name.starts_with("README") || name.starts_with("LICENSE")
+}
+
+fn is_commit_backup(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().ends_with("-dz-bpk"))
+}
+
+/// Paths under `dir` that are neither one of `known` nor a directory leading to one
+fn unknown_paths(
+    dir: &Path,
+    known: &[PathBuf],
+    skip: &dyn Fn(&Path) -> bool,
+) -> Result<Vec<PathBuf>> {
+    let mut unknown = Vec::new();
+    if !dir.is_dir() {
+        return Ok(unknown);
+    }
+
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        if skip(&path) || known.contains(&path) {
+            continue;
+        }
+
+        let is_dir = entry.file_type()?.is_dir();
+        if is_dir && known.iter().any(|known| known.starts_with(&path)) {
+            unknown.extend(unknown_paths(&path, known, skip)?);
+        } else if !is_dir || contains_files(&path)? {
+            // Empty directories, e.g. left in staging by a commit, don't matter
+            unknown.push(path);
+        }
+    }
+
+    Ok(unknown)
+}
+
+fn contains_files(dir: &Path) -> Result<bool> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() || contains_files(&entry.path())? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Every `-dz-bpk` backup in the repository, outside dotzilla's own directories
+fn commit_backups(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut backups = Vec::new();
+
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        if is_commit_backup(&path) {
+            backups.push(path);
+        } else if entry.file_type()?.is_dir()
+            && !matches!(
+                entry.file_name().to_str(),
+                Some(".git" | ".staging" | ".dotzilla")
+            )
+        {
+            backups.extend(commit_backups(&path)?);
+        }
+    }
+
+    Ok(backups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::testing;
+
+    fn messages(config: &Config) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_dotfiles(config, &mut problems).unwrap();
+        check_staging(config, &mut problems).unwrap();
+        check_repository(config, &mut problems).unwrap();
+        problems
+            .into_iter()
+            .map(|problem| {
+                let fix = if problem.fix.is_some() { "fix" } else { "" };
+                format!("{} {}", fix, problem.message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_doctor_audit_and_fix() {
+        let root = Path::new("/tmp/test_dotzilla_doctor");
+        let config = testing::repo(root);
+        let dotpath =
+            testing::tracked_file(&config, "app.conf", "key = 1", 0o644, Default::default());
+        let mut config = testing::reload(&config);
+        let repo = config.repo_path().to_path_buf();
+        assert!(messages(&config).is_empty());
+
+        fs::remove_file(&dotpath.abs_path).unwrap();
+        fs::write(repo.join("README.md"), "my dotfiles").unwrap();
+        fs::write(repo.join(".gitignore"), ".staging").unwrap();
+        fs::write(repo.join(".oldrc"), "left over").unwrap();
+        fs::write(repo.join("stray.txt"), "left over").unwrap();
+        fs::write(repo.join(".staging/.oldrc"), "unsaved edit").unwrap();
+        let backup = dotpath.abs_target.with_file_name("app.conf-dz-bpk");
+        fs::rename(&dotpath.abs_target, &backup).unwrap();
+
+        let name = dotpath.to_name().display().to_string();
+        assert_eq!(
+            messages(&config),
+            vec![
+                format!(
+                    " {}: repository copy is missing at {}",
+                    name,
+                    dotpath.abs_target.display()
+                ),
+                format!(
+                    " {} is in staging without a staged entry",
+                    repo.join(".staging/.oldrc").display()
+                ),
+                format!(
+                    " {} is in the repository but not tracked",
+                    repo.join(".oldrc").display()
+                ),
+                format!(
+                    " {} is in the repository but not tracked",
+                    repo.join("stray.txt").display()
+                ),
+                format!(
+                    "fix {} is a leftover backup from an interrupted commit and {} is missing",
+                    backup.display(),
+                    dotpath.abs_target.display()
+                ),
+            ]
+        );
+
+        // The backup goes back first, the local path is linked on the next run
+        run_doctor(&mut config, true).unwrap();
+        assert!(dotpath.abs_target.exists());
+        run_doctor(&mut config, true).unwrap();
+        assert!(dotpath.abs_path.is_symlink());
+        assert_eq!(fs::read_to_string(&dotpath.abs_path).unwrap(), "key = 1");
+
+        // Only what can't lose data was touched
+        assert_eq!(
+            fs::read_to_string(repo.join(".staging/.oldrc")).unwrap(),
+            "unsaved edit"
+        );
+        assert!(repo.join(".oldrc").exists());
+        assert_eq!(messages(&config).len(), 3);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
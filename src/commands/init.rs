@@ -4,9 +4,9 @@ use std::path::PathBuf;
 use anyhow::{Context, Result};
 use colored::*;
 
-use crate::models::Config;
+use crate::models::{Config, ConfigFormat};
 
-pub fn init_repo(path: PathBuf) -> Result<()> {
+pub fn init_repo(path: PathBuf, format: ConfigFormat) -> Result<()> {
     if !path.exists() {
         fs::create_dir_all(&path)
             .with_context(|| format!("Failed to create directory at {}", path.display()))?;
@@ -37,7 +37,8 @@ pub fn init_repo(path: PathBuf) -> Result<()> {
         );
     }
 
-    let config = Config::new(path.clone());
+    let mut config = Config::new(path.clone());
+    config.set_format(format);
     config.save()?;
 
     println!(
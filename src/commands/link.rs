@@ -1,22 +1,71 @@
 use std::fs;
-use std::os::unix::fs as unix_fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
 use colored::*;
 use log::error;
 
-use crate::models::Config;
+use super::show_diff;
+use super::stage::{apply_stage, plan_stage};
+use crate::models::{
+    Activation, Config, DeployMode, DotPath, DotfileEntry, Operation, Plan, decrypt_dotfile,
+    render_dotfile, rendered_path, symlink_value,
+};
 use crate::utils::filter::filter_dotfiles_exact;
+use crate::utils::mode;
+use crate::utils::path_compare::{self, CompareResult};
 
 use crate::utils;
 
-pub fn link_dotfiles(config: &Config, name: Option<String>) -> Result<()> {
-    if config.get().is_empty() {
+/// What `link` does with a local file that is in the way and differs from the repository
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum ConflictPolicy {
+    /// Stage the local version so it can be reviewed and committed
+    Adopt,
+    /// Move the local version into the backup store
+    Backup,
+    /// Leave the local version alone and don't link
+    Skip,
+    /// Delete the local version
+    Overwrite,
+    /// Ask for each conflict, backing up when there is no terminal
+    #[default]
+    Ask,
+}
+
+/// What linking a single dotfile comes down to
+pub(super) enum LinkOutcome {
+    /// Put the dotfile in place
+    Deploy,
+    /// Already in place, a template may still be re-rendered
+    InPlace,
+    /// A conflicting local version is left alone
+    Skipped,
+    /// The conflicting local version is staged instead, as this entry
+    Adopted(DotfileEntry),
+}
+
+/// Link tracked dotfiles
+///
+/// `relative` overrides the repository's `relative_links` default, and `on_conflict`
+/// decides what happens to local files that are in the way and differ from the repository.
+/// A dry run prints the planned operations, resolving `ask` to a backup.
+pub fn link_dotfiles(
+    config: &mut Config,
+    name: Option<String>,
+    relative: Option<bool>,
+    on_conflict: ConflictPolicy,
+    dry_run: bool,
+) -> Result<()> {
+    let relative = relative.unwrap_or(config.relative_links());
+
+    if config.get_active().is_empty() {
         println!("No dotfiles linking. Use 'dotzilla add <n>' to add dotfiles.");
         return Ok(());
     }
 
-    if name.is_none() {
+    if name.is_none() && !dry_run {
         println!("No dotfiles specified. I will link all dotfiles.");
 
         // confirm
@@ -29,131 +78,681 @@ pub fn link_dotfiles(config: &Config, name: Option<String>) -> Result<()> {
 
     let mut success_count = 0;
     let mut error_count = 0;
+    let mut skipped_count = 0;
+    let mut adopted_count = 0;
+    let mut planned = Plan::new();
 
-    let dotfiles = config.get();
+    let dotfiles = config.get_active();
     let filtered_dotfiles = filter_dotfiles_exact(dotfiles.iter(), name.as_deref());
 
-    for (dotfile_path, _) in filtered_dotfiles {
-        let source = &dotfile_path.abs_target;
-        let target_path = &dotfile_path.abs_path;
+    for (dotfile_path, entry) in filtered_dotfiles {
+        if let Activation::Inactive(reason) = entry.activation() {
+            println!(
+                "{} Skipped {}: condition not met: {}",
+                "-".dimmed(),
+                dotfile_path.to_name().display(),
+                reason
+            );
+            skipped_count += 1;
+            continue;
+        }
+
+        let (plan, outcome) =
+            match plan_link(config, dotfile_path, entry, relative, on_conflict, dry_run) {
+                Ok(planned) => planned,
+                Err(e) => {
+                    println!(
+                        "{} Failed to link {}: {:#}",
+                        "✗".red(),
+                        dotfile_path.to_name().display(),
+                        e
+                    );
+                    error_count += 1;
+                    continue;
+                }
+            };
+
+        let result = if dry_run {
+            planned.extend(plan);
+            Ok(())
+        } else if let LinkOutcome::Adopted(staged_entry) = &outcome {
+            apply_stage(config, dotfile_path, &plan, staged_entry.clone())
+        } else {
+            plan.execute()
+        };
+
+        if let Err(e) = result {
+            error!(
+                "Failed to deploy {} as a {}: {:#}",
+                dotfile_path.abs_path.display(),
+                entry.deploy,
+                e
+            );
+            println!(
+                "{} Failed to link {}: {:#}",
+                "✗".red(),
+                dotfile_path.to_name().display(),
+                e
+            );
+            error_count += 1;
+            continue;
+        }
+
+        match outcome {
+            LinkOutcome::Deploy => success_count += 1,
+            LinkOutcome::InPlace => {}
+            LinkOutcome::Skipped => skipped_count += 1,
+            LinkOutcome::Adopted(_) => {
+                if !dry_run {
+                    println!(
+                        "{} Adopted local {}, review it with 'dotzilla diff --staged {}' and commit it before linking",
+                        "✓".green(),
+                        dotfile_path.to_name().display(),
+                        dotfile_path.to_name().display()
+                    );
+                }
+                adopted_count += 1;
+            }
+        }
+    }
+
+    if dry_run {
+        planned.print();
+        println!(
+            "Would link {} and adopt {}, {} failed, {} skipped",
+            success_count, adopted_count, error_count, skipped_count
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{} linked successfully, {} failed, {} skipped, {} adopted",
+        success_count, error_count, skipped_count, adopted_count
+    );
+    Ok(())
+}
+
+/// Plan putting a single dotfile in place, including what happens to anything in the way
+///
+/// Recorded modes are restored once the dotfile is in place. Changes to the local path
+/// of a privileged dotfile go through the escalation command.
+pub(super) fn plan_link(
+    config: &Config,
+    dotfile_path: &DotPath,
+    entry: &DotfileEntry,
+    relative: bool,
+    on_conflict: ConflictPolicy,
+    dry_run: bool,
+) -> Result<(Plan, LinkOutcome)> {
+    let (mut plan, outcome) =
+        plan_deploy(config, dotfile_path, entry, relative, on_conflict, dry_run)?;
+
+    // A fresh clone or a copy loses modes git doesn't keep, put the recorded ones back
+    let modes = entry.deployed_modes();
+    let restore_modes = match outcome {
+        LinkOutcome::Deploy => !modes.is_empty(),
+        LinkOutcome::InPlace => !mode::changes(&dotfile_path.abs_path, &modes).is_empty(),
+        LinkOutcome::Skipped | LinkOutcome::Adopted(_) => false,
+    };
+    if restore_modes {
+        plan.push(Operation::SetModes {
+            path: dotfile_path.abs_path.clone(),
+            modes,
+        });
+    }
+
+    if let Some(command) = config.escalation_for(entry) {
+        plan.escalate(config.repo_path(), command);
+    }
+    Ok((plan, outcome))
+}
+
+fn plan_deploy(
+    config: &Config,
+    dotfile_path: &DotPath,
+    entry: &DotfileEntry,
+    relative: bool,
+    on_conflict: ConflictPolicy,
+    dry_run: bool,
+) -> Result<(Plan, LinkOutcome)> {
+    let mut plan = Plan::new();
+    let target_path = &dotfile_path.abs_path;
+    let ignore = config.entry_rules(entry)?;
+
+    // Templates are rendered and encrypted dotfiles decrypted in memory. Encrypted
+    // dotfiles can't be symlinked to their ciphertext, they are deployed as a private
+    // copy instead.
+    let (source, content) = if entry.template {
+        let rendered = render_dotfile(config.repo_path(), dotfile_path)?.into_bytes();
+        let path = rendered_path(config.repo_path(), dotfile_path);
+        if fs::read(&path).ok().as_ref() != Some(&rendered) {
+            plan.push(Operation::Render {
+                repo: config.repo_path().to_path_buf(),
+                dotpath: dotfile_path.clone(),
+                contents: rendered.clone(),
+            });
+        }
+        (path, Some(rendered))
+    } else if entry.encrypted {
+        let plaintext = decrypt_dotfile(&dotfile_path.abs_target)?;
+        (dotfile_path.abs_target.clone(), Some(plaintext))
+    } else {
+        (dotfile_path.abs_target.clone(), None)
+    };
+    let source = &source;
+
+    // Nothing is lost when the local copy matches what replaces it
+    let local_matches = target_path.exists()
+        && !target_path.is_symlink()
+        && match &content {
+            Some(content) => fs::read(target_path).is_ok_and(|local| local == *content),
+            None => matches!(
+                path_compare::compare_paths(source, target_path, &ignore),
+                Ok(CompareResult::Equal)
+            ),
+        };
+
+    let in_place = match entry.deploy {
+        // An existing symlink is checked below, together with foreign symlinks
+        DeployMode::Symlink => false,
+        DeployMode::Copy => local_matches,
+        DeployMode::Hardlink => path_compare::hardlinked_paths(source, target_path),
+        // Checked file by file below
+        DeployMode::Unfold => false,
+    };
+
+    if in_place {
+        println!(
+            "{} Already deployed as a {}: {}",
+            "✓".green(),
+            entry.deploy,
+            dotfile_path.to_name().display()
+        );
+        return Ok((plan, LinkOutcome::InPlace));
+    }
 
-        if target_path.exists() {
-            if target_path.is_symlink() {
-                let target_link = fs::read_link(target_path).with_context(|| {
-                    format!("Failed to read symlink at {}", target_path.display())
-                })?;
+    let mut conflict = None;
+    if target_path.exists() {
+        if target_path.is_symlink() {
+            let target_link = fs::read_link(target_path)
+                .with_context(|| format!("Failed to read symlink at {}", target_path.display()))?;
 
-                if target_link == *source {
+            let same_file = path_compare::linked_paths(target_path, source).map_err(|err| {
+                anyhow!(
+                    "Failed to resolve symlink at {}: {}",
+                    target_path.display(),
+                    err
+                )
+            })?;
+
+            if same_file {
+                if entry.deploy.is_symlink()
+                    && target_link == symlink_value(target_path, source, relative)?
+                {
                     println!(
                         "{} Symlink already exists: {} -> {}",
                         "✓".green(),
                         dotfile_path.to_name().display(),
                         source.display()
                     );
-                    continue;
+                    return Ok((plan, LinkOutcome::InPlace));
                 }
 
+                // Our own link, in the other style or for another deploy mode
+                plan.push(Operation::Delete(target_path.clone()));
+            } else {
                 println!(
                     "{} Existing symlink points to a different target: {} -> {}",
-                    "✗".red(),
+                    "!".yellow(),
                     target_path.display(),
                     target_link.display()
                 );
+                conflict = Some("symlink");
+            }
+        } else if entry.deploy == DeployMode::Unfold && target_path.is_dir() {
+            // The directory stays, its files are linked one by one below
+        } else if local_matches {
+            plan.push(Operation::Delete(target_path.clone()));
+        } else if target_path.is_dir() {
+            conflict = Some("directory");
+        } else {
+            conflict = Some("file");
+        }
+    } else if target_path.is_symlink() {
+        // A broken symlink, `exists` follows it
+        plan.push(Operation::Delete(target_path.clone()));
+    }
 
-                fs::remove_file(target_path).with_context(|| {
-                    format!(
-                        "Failed to remove existing symlink at {}",
-                        target_path.display()
-                    )
-                })?;
+    if let Some(kind) = conflict {
+        let policy = if dry_run && on_conflict == ConflictPolicy::Ask {
+            ConflictPolicy::Backup
+        } else {
+            resolve_conflict(config, dotfile_path, on_conflict)?
+        };
 
+        match policy {
+            ConflictPolicy::Skip => {
                 println!(
-                    "{} Removed existing symlink at {}",
-                    "✓".green(),
-                    target_path.display()
+                    "{} Skipped {}: a different {} is in the way",
+                    "-".dimmed(),
+                    dotfile_path.to_name().display(),
+                    kind
                 );
-            } else if target_path.is_dir() {
-                fs::remove_dir_all(target_path).with_context(|| {
-                    format!(
-                        "Failed to remove existing directory at {}",
-                        target_path.display()
-                    )
-                })?;
+                return Ok((plan, LinkOutcome::Skipped));
+            }
+            ConflictPolicy::Adopt => {
+                let (stage_plan, staged_entry) = plan_stage(config, dotfile_path)?;
+                return Ok((stage_plan, LinkOutcome::Adopted(staged_entry)));
+            }
+            ConflictPolicy::Overwrite => plan.push(Operation::Delete(target_path.clone())),
+            ConflictPolicy::Backup | ConflictPolicy::Ask => plan.push(Operation::Backup {
+                repo: config.repo_path().to_path_buf(),
+                dotpath: dotfile_path.clone(),
+                encrypted: entry.encrypted,
+            }),
+        }
+    }
 
-                println!(
-                    "{} Removed existing directory at {}",
-                    "✓".green(),
-                    target_path.display()
-                );
-            } else {
-                fs::remove_file(target_path).with_context(|| {
-                    format!(
-                        "Failed to remove existing file at {}",
-                        target_path.display()
-                    )
-                })?;
+    // Encrypted dotfiles create their own private parent directories, and unfolded
+    // ones create the local directory together with its parents
+    if let Some(parent) = target_path.parent()
+        && !parent.exists()
+        && !entry.encrypted
+        && entry.deploy != DeployMode::Unfold
+    {
+        plan.push(Operation::CreateDir(parent.to_path_buf()));
+    }
 
-                println!(
-                    "{} Removed existing file at {}",
-                    "✓".green(),
-                    target_path.display()
-                );
+    let operation = match (entry.deploy, content) {
+        (_, Some(plaintext)) if entry.encrypted => Operation::Write {
+            path: target_path.clone(),
+            contents: plaintext,
+            private: true,
+        },
+        (DeployMode::Symlink, _) => Operation::Symlink {
+            link: target_path.clone(),
+            target: source.clone(),
+            relative,
+        },
+        (DeployMode::Copy, _) => Operation::Copy {
+            from: source.clone(),
+            to: target_path.clone(),
+            ignore,
+        },
+        (DeployMode::Hardlink, _) => Operation::HardLink {
+            link: target_path.clone(),
+            target: source.clone(),
+        },
+        (DeployMode::Unfold, _) => {
+            let files = config.unfolded_files(dotfile_path, entry)?;
+            return plan_unfold(
+                config,
+                dotfile_path,
+                &files,
+                plan,
+                relative,
+                on_conflict,
+                dry_run,
+            );
+        }
+    };
+    plan.push(operation);
+
+    Ok((plan, LinkOutcome::Deploy))
+}
+
+/// Plan symlinking each file of an unfolded directory into the real local directory
+///
+/// Files in the way are resolved one by one with `on_conflict`, while files the
+/// application keeps next to the tracked ones are left alone.
+fn plan_unfold(
+    config: &Config,
+    dotfile_path: &DotPath,
+    files: &[PathBuf],
+    mut plan: Plan,
+    relative: bool,
+    on_conflict: ConflictPolicy,
+    dry_run: bool,
+) -> Result<(Plan, LinkOutcome)> {
+    let target_path = &dotfile_path.abs_path;
+    // Anything else at the local path is replaced by then, so every file is new
+    let keep_dir = target_path.is_dir() && !target_path.is_symlink();
+    if !keep_dir {
+        plan.push(Operation::CreateDir(target_path.clone()));
+    }
+
+    let mut created_dirs: Vec<PathBuf> = Vec::new();
+    let mut skipped_count = 0;
+    for file in files {
+        let local = target_path.join(file);
+        let source = dotfile_path.abs_target.join(file);
+
+        let mut conflict = None;
+        if keep_dir && local.is_symlink() {
+            let ours = path_compare::linked_paths(&local, &source).map_err(|err| {
+                anyhow!("Failed to resolve symlink at {}: {}", local.display(), err)
+            })?;
+
+            if ours {
+                let target_link = fs::read_link(&local)
+                    .with_context(|| format!("Failed to read symlink at {}", local.display()))?;
+                if target_link == symlink_value(&local, &source, relative)? {
+                    continue;
+                }
+                plan.push(Operation::Delete(local.clone()));
+            } else if local.exists() {
+                conflict = Some("symlink");
+            } else {
+                // A broken symlink, `exists` follows it
+                plan.push(Operation::Delete(local.clone()));
             }
-        } else if let Ok(metadata) = fs::symlink_metadata(target_path) {
-            if metadata.file_type().is_symlink() && fs::metadata(target_path).is_err() {
-                fs::remove_file(target_path).with_context(|| {
-                    format!(
-                        "Failed to remove broken symlink at {}",
-                        target_path.display()
-                    )
-                })?;
-                println!(
-                    "{} Removed broken symlink at {}",
-                    "✓".green(),
-                    target_path.display()
-                );
+        } else if keep_dir && local.is_dir() {
+            conflict = Some("directory");
+        } else if keep_dir && local.exists() {
+            if path_compare::compare_file(&local, &source).unwrap_or(false) {
+                plan.push(Operation::Delete(local.clone()));
+            } else {
+                conflict = Some("file");
             }
         }
 
-        if let Some(parent) = target_path.parent() {
-            if !parent.exists() {
-                fs::create_dir_all(parent).with_context(|| {
-                    format!("Failed to create parent directory at {}", parent.display())
-                })?;
+        if let Some(kind) = conflict {
+            let file_path = DotPath::from_path(config, local.clone());
+            let policy = if dry_run && on_conflict == ConflictPolicy::Ask {
+                ConflictPolicy::Backup
+            } else {
+                resolve_conflict(config, &file_path, on_conflict)?
+            };
+
+            match policy {
+                ConflictPolicy::Skip => {
+                    println!(
+                        "{} Skipped {}: a different {} is in the way",
+                        "-".dimmed(),
+                        file_path.to_name().display(),
+                        kind
+                    );
+                    skipped_count += 1;
+                    continue;
+                }
+                ConflictPolicy::Adopt => {
+                    let (stage_plan, staged_entry) = plan_stage(config, dotfile_path)?;
+                    return Ok((stage_plan, LinkOutcome::Adopted(staged_entry)));
+                }
+                ConflictPolicy::Overwrite => plan.push(Operation::Delete(local.clone())),
+                ConflictPolicy::Backup | ConflictPolicy::Ask => plan.push(Operation::Backup {
+                    repo: config.repo_path().to_path_buf(),
+                    dotpath: file_path,
+                    encrypted: false,
+                }),
             }
         }
 
-        match unix_fs::symlink(source, target_path) {
-            Ok(_) => {
-                println!(
-                    "{} Linked: {} -> {}",
-                    "✓".green(),
-                    dotfile_path.to_name().display(),
-                    source.display()
-                );
-                success_count += 1;
-            }
-            Err(e) => {
-                error!(
-                    "Failed to create symlink from {} to {}: {}",
-                    source.display(),
-                    target_path.display(),
-                    e
-                );
-                println!(
-                    "{} Failed to link {}: {}",
-                    "✗".red(),
-                    dotfile_path.to_name().display(),
-                    e
-                );
-                error_count += 1;
-            }
+        if let Some(parent) = local.parent()
+            && parent != target_path
+            && !(keep_dir && parent.is_dir())
+            && !created_dirs.iter().any(|dir| dir == parent)
+        {
+            created_dirs.push(parent.to_path_buf());
+            plan.push(Operation::CreateDir(parent.to_path_buf()));
         }
+
+        plan.push(Operation::Symlink {
+            link: local,
+            target: source,
+            relative,
+        });
     }
 
-    println!(
-        "{} linked successfully, {} failed",
-        success_count, error_count
+    if plan.is_empty() {
+        if skipped_count > 0 {
+            return Ok((plan, LinkOutcome::Skipped));
+        }
+
+        println!(
+            "{} Already unfolded, {} file(s) linked: {}",
+            "✓".green(),
+            files.len(),
+            dotfile_path.to_name().display()
+        );
+        return Ok((plan, LinkOutcome::InPlace));
+    }
+
+    Ok((plan, LinkOutcome::Deploy))
+}
+
+/// Turn `ask` into a concrete action by prompting, offering a diff and a merge first
+///
+/// Without a terminal to ask on, conflicting files are backed up.
+fn resolve_conflict(
+    config: &Config,
+    dotfile_path: &DotPath,
+    policy: ConflictPolicy,
+) -> Result<ConflictPolicy> {
+    if policy != ConflictPolicy::Ask {
+        return Ok(policy);
+    }
+
+    if !io::stdin().is_terminal() {
+        return Ok(ConflictPolicy::Backup);
+    }
+
+    let question = format!(
+        "{} differs from the repository copy. What should happen to the local version?",
+        dotfile_path.to_name().display()
     );
-    Ok(())
+
+    loop {
+        let choice = utils::choose(
+            &question,
+            &["adopt", "backup", "overwrite", "skip", "diff", "merge"],
+            "backup",
+        );
+
+        let result = match choice {
+            "adopt" => return Ok(ConflictPolicy::Adopt),
+            "backup" => return Ok(ConflictPolicy::Backup),
+            "overwrite" => return Ok(ConflictPolicy::Overwrite),
+            "skip" => return Ok(ConflictPolicy::Skip),
+            "diff" => show_diff(config, dotfile_path.clone(), None, false, false),
+            _ => {
+                let tool =
+                    std::env::var("DOTZILLA_MERGE_TOOL").unwrap_or_else(|_| "vimdiff".to_string());
+                show_diff(config, dotfile_path.clone(), Some(tool), false, false)
+            }
+        };
+
+        if let Err(e) = result {
+            println!("{} {:#}", "✗".red(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::testing;
+    use std::path::Path;
+
+    #[test]
+    fn test_plan_unfold() {
+        let root = Path::new("/tmp/test_dotzilla_link_unfold");
+        let config = testing::repo(root);
+        let dotpath = testing::unfolded_fish(&config);
+        let config = testing::reload(&config);
+        let entry = config.get_dotfile(&dotpath).unwrap().clone();
+        let files = config.unfolded_files(&dotpath, &entry).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("config.fish"),
+                PathBuf::from("functions/ll.fish")
+            ]
+        );
+
+        // `config.fish` matches the repository copy, `ll.fish` was edited locally
+        fs::write(dotpath.abs_path.join("functions/ll.fish"), "ls -la").unwrap();
+        let unfold = |on_conflict| {
+            plan_unfold(
+                &config,
+                &dotpath,
+                &files,
+                Plan::new(),
+                false,
+                on_conflict,
+                false,
+            )
+            .unwrap()
+        };
+
+        let (plan, outcome) = unfold(ConflictPolicy::Skip);
+        assert!(matches!(outcome, LinkOutcome::Deploy));
+        plan.execute().unwrap();
+        assert_eq!(
+            fs::read_link(dotpath.abs_path.join("config.fish")).unwrap(),
+            dotpath.abs_target.join("config.fish")
+        );
+        assert!(!dotpath.abs_path.join("functions/ll.fish").is_symlink());
+        assert_eq!(
+            fs::read_to_string(dotpath.abs_path.join("fish_history")).unwrap(),
+            "- cmd: ls"
+        );
+
+        let (plan, outcome) = unfold(ConflictPolicy::Skip);
+        assert!(plan.is_empty());
+        assert!(matches!(outcome, LinkOutcome::Skipped));
+
+        let (plan, outcome) = unfold(ConflictPolicy::Overwrite);
+        assert!(matches!(outcome, LinkOutcome::Deploy));
+        plan.execute().unwrap();
+        assert!(dotpath.abs_path.join("functions/ll.fish").is_symlink());
+
+        let (plan, outcome) = unfold(ConflictPolicy::Skip);
+        assert!(plan.is_empty());
+        assert!(matches!(outcome, LinkOutcome::InPlace));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_link_restores_modes() {
+        use crate::commands::AddOptions;
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = Path::new("/tmp/test_dotzilla_link_modes");
+        let config = testing::repo(root);
+        let copied = testing::tracked_file(
+            &config,
+            "copied.conf",
+            "copied",
+            0o600,
+            AddOptions {
+                deploy: Some(DeployMode::Copy),
+                ..Default::default()
+            },
+        );
+        let linked = testing::tracked_file(
+            &testing::reload(&config),
+            "linked.conf",
+            "linked",
+            0o640,
+            Default::default(),
+        );
+        let config = testing::reload(&config);
+
+        // What a fresh clone leaves behind: the default mode and nothing deployed
+        for dotpath in [&copied, &linked] {
+            fs::remove_file(&dotpath.abs_path).unwrap();
+            fs::set_permissions(&dotpath.abs_target, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+
+        let link = |dotpath: &DotPath| {
+            let entry = config.get_dotfile(dotpath).unwrap();
+            let (plan, _) =
+                plan_link(&config, dotpath, entry, false, ConflictPolicy::Skip, false).unwrap();
+            plan.execute().unwrap();
+        };
+        link(&copied);
+        link(&linked);
+
+        assert!(!copied.abs_path.is_symlink());
+        assert_eq!(testing::mode(&copied.abs_path), 0o600);
+        assert!(linked.abs_path.is_symlink());
+        assert_eq!(testing::mode(&linked.abs_path), 0o640);
+
+        // A dotfile in place only gets its drifted modes put back
+        fs::set_permissions(&copied.abs_path, fs::Permissions::from_mode(0o666)).unwrap();
+        link(&copied);
+        assert_eq!(testing::mode(&copied.abs_path), 0o600);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_link_keeps_rendered_templates_private() {
+        use crate::commands::AddOptions;
+
+        let root = Path::new("/tmp/test_dotzilla_link_rendered");
+        let config = testing::repo(root);
+        let dotpath = testing::tracked_file(
+            &config,
+            "gitconfig",
+            "[user]\n    name = {{ user }}\n",
+            0o644,
+            AddOptions {
+                template: true,
+                ..Default::default()
+            },
+        );
+        let config = testing::reload(&config);
+        fs::remove_file(&dotpath.abs_path).unwrap();
+
+        let entry = config.get_dotfile(&dotpath).unwrap();
+        let (plan, _) =
+            plan_link(&config, &dotpath, entry, false, ConflictPolicy::Skip, false).unwrap();
+        plan.execute().unwrap();
+
+        let rendered = rendered_path(config.repo_path(), &dotpath);
+        assert!(path_compare::linked_paths(&dotpath.abs_path, &rendered).unwrap());
+        let internals = config.repo_path().join(".dotzilla");
+        assert_eq!(testing::mode(&internals.join("rendered")), 0o700);
+        let gitignore = fs::read_to_string(internals.join(".gitignore")).unwrap();
+        assert!(gitignore.lines().any(|line| line == "/rendered"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_link_keeps_secrets_private() {
+        use crate::commands::AddOptions;
+
+        crate::models::use_test_key();
+        let root = Path::new("/tmp/test_dotzilla_link_secret");
+        let config = testing::repo(root);
+        let dotpath = testing::tracked_file(
+            &config,
+            "netrc",
+            "machine example.com password hunter2",
+            0o644,
+            AddOptions {
+                encrypt: true,
+                ..Default::default()
+            },
+        );
+        let config = testing::reload(&config);
+        fs::remove_file(&dotpath.abs_path).unwrap();
+
+        let entry = config.get_dotfile(&dotpath).unwrap();
+        let (plan, _) =
+            plan_link(&config, &dotpath, entry, false, ConflictPolicy::Skip, false).unwrap();
+        plan.execute().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&dotpath.abs_path).unwrap(),
+            "machine example.com password hunter2"
+        );
+        assert_eq!(testing::mode(&dotpath.abs_path), 0o600);
+
+        fs::remove_dir_all(root).unwrap();
+    }
 }
@@ -1,25 +1,116 @@
+use std::path::PathBuf;
+
 use anyhow::Result;
 use colored::*;
+use serde::Serialize;
+
+use super::OutputFormat;
+use crate::models::{Activation, Config, DeployMode, DotPath, DotfileEntry};
+use crate::utils::tree::tree_lines;
+
+/// A dotfile as reported by `list --format json`
+#[derive(Serialize)]
+struct ListRecord {
+    path: String,
+    staged: bool,
+    active: bool,
+    template: bool,
+    encrypted: bool,
+    privileged: bool,
+    deploy: DeployMode,
+    when: Vec<String>,
+    /// Files linked one by one into an unfolded directory
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    files: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ListReport {
+    profile: Option<String>,
+    dotfiles: Vec<ListRecord>,
+}
+
+pub fn list_dotfiles(config: &Config, format: OutputFormat) -> Result<()> {
+    let staged = config.get_staged();
+    let mut dotfiles: Vec<(DotPath, DotfileEntry, Vec<PathBuf>)> = config
+        .get_active()
+        .into_iter()
+        .map(|(dotpath, entry)| {
+            let files = if entry.deploy == DeployMode::Unfold {
+                config.unfolded_files(&dotpath, &entry).unwrap_or_default()
+            } else {
+                Vec::new()
+            };
+            (dotpath, entry, files)
+        })
+        .collect();
+    dotfiles.sort_by_key(|(dotpath, _, _)| dotpath.to_name().clone());
 
-use crate::models::Config;
+    match format {
+        OutputFormat::Json => {
+            let report = ListReport {
+                profile: config.active_profile().map(str::to_string),
+                dotfiles: dotfiles
+                    .into_iter()
+                    .map(|(dotpath, entry, files)| ListRecord {
+                        path: dotpath.to_name().display().to_string(),
+                        staged: staged.contains_key(&dotpath),
+                        active: entry.activation() == Activation::Active,
+                        template: entry.template,
+                        encrypted: entry.encrypted,
+                        privileged: entry.privileged,
+                        deploy: entry.deploy,
+                        when: entry.when,
+                        files: files
+                            .iter()
+                            .map(|file| file.display().to_string())
+                            .collect(),
+                    })
+                    .collect(),
+            };
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+        OutputFormat::Porcelain => {
+            for (dotpath, _, files) in dotfiles {
+                let state = if staged.contains_key(&dotpath) {
+                    "staged"
+                } else {
+                    "tracked"
+                };
+                println!("{} {}", state, dotpath);
+                for file in files {
+                    println!("file {}", dotpath.to_name().join(file).display());
+                }
+            }
+            return Ok(());
+        }
+        OutputFormat::Text => {}
+    }
 
-pub fn list_dotfiles(config: &Config) -> Result<()> {
     println!("{} Dotfiles List", "✦".cyan());
     println!("{}", "===============".cyan());
 
-    if config.get().is_empty() {
+    if let Some(profile) = config.active_profile() {
+        println!("Profile: {}", profile.bold());
+    }
+
+    if dotfiles.is_empty() {
         println!("No dotfiles tracked. Use 'dotzilla add <path>' to add dotfiles.");
         return Ok(());
     }
 
-    for (dotfile_path, _) in config.get() {
-        let staged = if config.get_staged().contains_key(&dotfile_path) {
+    for (dotfile_path, _, files) in dotfiles {
+        let staged = if staged.contains_key(&dotfile_path) {
             "(staged)".blue()
         } else {
             "".normal()
         };
 
         println!("{} {} {}", "•".cyan(), dotfile_path, staged);
+        for (line, _) in tree_lines(&files) {
+            println!("    {}", line.dimmed());
+        }
     }
 
     Ok(())
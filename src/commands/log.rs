@@ -0,0 +1,31 @@
+use anyhow::Result;
+use colored::*;
+
+use crate::models::{Config, History};
+
+pub fn show_log(config: &Config, limit: Option<usize>) -> Result<()> {
+    let commits = History::new(config.repo_path()).list()?;
+
+    if commits.is_empty() {
+        println!("No commits recorded. Use 'dotzilla commit' to commit staged dotfiles.");
+        return Ok(());
+    }
+
+    for commit in commits.iter().take(limit.unwrap_or(usize::MAX)) {
+        println!("{} {}", "commit".yellow(), commit.id.yellow());
+        println!("Date: {}", commit.timestamp.format("%Y-%m-%d %H:%M:%S %z"));
+
+        if let Some(message) = &commit.message {
+            println!();
+            println!("    {}", message);
+        }
+
+        println!();
+        for file in &commit.files {
+            println!("    {} {}", "•".cyan(), file.display());
+        }
+        println!();
+    }
+
+    Ok(())
+}
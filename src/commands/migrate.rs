@@ -0,0 +1,67 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use colored::*;
+
+use crate::models::{CONFIG_VERSION, Config, ConfigFormat, config_version, pending_migrations};
+
+pub fn migrate_config(repo_path: &Path, check: bool) -> Result<()> {
+    let format = ConfigFormat::detect(repo_path).ok_or_else(|| {
+        anyhow!(
+            "No dotzilla repository found at {}, please run 'dotzilla init'",
+            repo_path.display()
+        )
+    })?;
+    let config_path = repo_path.join(format.file_name());
+
+    let config_str = fs::read_to_string(&config_path)
+        .with_context(|| format!("Failed to read config from {}", config_path.display()))?;
+    let value = format
+        .parse(&config_str)
+        .with_context(|| format!("Failed to parse config from {}", config_path.display()))?;
+
+    let version = config_version(&value);
+    let pending = pending_migrations(&value)?;
+
+    if pending.is_empty() {
+        println!(
+            "{} Config is up to date (schema version {})",
+            "✓".green(),
+            version
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{} Config schema version {} is behind the current version {}:",
+        "!".yellow(),
+        version,
+        CONFIG_VERSION
+    );
+    for migration in &pending {
+        println!(
+            "  {} v{} -> v{}: {}",
+            "•".cyan(),
+            migration.from,
+            migration.from + 1,
+            migration.description
+        );
+    }
+
+    if check {
+        return Err(anyhow!("{} pending migration(s)", pending.len()));
+    }
+
+    let config = Config::load(repo_path)?;
+    config.save()?;
+
+    println!(
+        "{} Migrated config to schema version {}, the original was kept as {}.v{}.bak",
+        "✓".green(),
+        CONFIG_VERSION,
+        format.file_name(),
+        version
+    );
+    Ok(())
+}
@@ -1,27 +1,52 @@
 use clap::{Parser, Subcommand};
 
+use crate::models::{ConfigFormat, DeployMode, GitMode};
+use crate::utils::walk::SymlinkPolicy;
+
 mod add;
+mod backups;
+mod clone;
 mod commit;
+mod config;
 mod diff;
+mod doctor;
 mod init;
 mod link;
 mod list;
+mod log;
+mod migrate;
+mod profile;
 mod remove;
+mod render;
+mod restore;
 mod stage;
 mod status;
+mod sync;
+#[cfg(test)]
+mod testing;
 mod unlink;
 mod unstage;
 
-pub use add::add_dotfile;
+pub use add::{AddOptions, add_dotfile};
+pub use backups::{list_backups, prune_backups, restore_backup};
 use clap_complete::Shell;
-pub use commit::commit_dotfiles;
-pub use diff::show_diff;
+pub use clone::clone_repo;
+pub use commit::{commit_dotfiles, recover_commit};
+pub use config::{convert_config, set_escalation_command, set_git_mode, set_relative_links};
+pub use diff::{report_diff, show_diff};
+pub use doctor::run_doctor;
 pub use init::init_repo;
-pub use link::link_dotfiles;
+pub use link::{ConflictPolicy, link_dotfiles};
 pub use list::list_dotfiles;
+pub use log::show_log;
+pub use migrate::migrate_config;
+pub use profile::{list_profiles, remove_profile, set_profile};
 pub use remove::remove_dotfile;
+pub use render::render_templates;
+pub use restore::restore_dotfiles;
 pub use stage::stage_dotfile;
 pub use status::show_status;
+pub use sync::sync_repo;
 pub use unlink::unlink_dotfiles;
 pub use unstage::unstage_dotfile;
 
@@ -34,6 +59,62 @@ pub struct Cli {
     /// Path to the dotfiles repository
     #[arg(short, long, default_value = "~/dotfiles", env = "DOTZILLA_REPO")]
     pub repo: String,
+
+    /// Profile to use instead of the one matching this host
+    #[arg(short, long, global = true, env = "DOTZILLA_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Print the file operations a command would perform without performing them
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+}
+
+/// Output format of `status`, `list` and `diff`
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Colored text for humans
+    #[default]
+    Text,
+    /// Structured JSON records
+    Json,
+    /// Stable, uncolored lines for scripts
+    Porcelain,
+}
+
+impl OutputFormat {
+    /// Whether the output is meant for scripts, which also get drift exit codes
+    pub fn is_machine_readable(self) -> bool {
+        self != OutputFormat::Text
+    }
+
+    /// The code to exit with after a report, `None` to exit normally
+    pub fn exit_code(self, drift: bool) -> Option<i32> {
+        (drift && self.is_machine_readable()).then_some(DRIFT_EXIT_CODE)
+    }
+}
+
+/// Exit code of `status` and `diff` in a machine-readable format when drift is found
+pub const DRIFT_EXIT_CODE: i32 = 2;
+
+#[derive(clap::Args)]
+pub struct OutputArgs {
+    /// Output format
+    #[arg(long, value_enum, default_value = "text")]
+    format: OutputFormat,
+
+    /// Shorthand for '--format porcelain'
+    #[arg(long, conflicts_with = "format")]
+    porcelain: bool,
+}
+
+impl OutputArgs {
+    pub fn format(&self) -> OutputFormat {
+        if self.porcelain {
+            OutputFormat::Porcelain
+        } else {
+            self.format
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -43,12 +124,58 @@ pub enum Commands {
         /// Path to initialize the dotfiles repository
         #[arg(default_value = "~/dotfiles", env = "DOTZILLA_REPO")]
         path: String,
+
+        /// Manifest format of the new repository
+        #[arg(short, long, value_enum, default_value = "json")]
+        format: ConfigFormat,
+    },
+
+    /// Clone a dotfiles repository onto this machine and link its dotfiles
+    Clone {
+        /// Git URL or local path of the repository
+        source: String,
+
+        /// Path to clone the dotfiles repository into
+        #[arg(default_value = "~/dotfiles", env = "DOTZILLA_REPO")]
+        path: String,
+
+        /// What to do with local files that are in the way and differ from the repository
+        #[arg(long, value_enum, default_value = "ask")]
+        on_conflict: ConflictPolicy,
+
+        /// Apply the plan without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
     },
 
     /// Add a dotfile to tracking
     Add {
         /// Path to the dotfile
         path: String,
+
+        /// Only activate the dotfile when the condition holds, e.g. 'os = linux' (repeatable)
+        #[arg(short, long)]
+        when: Vec<String>,
+
+        /// Render the dotfile as a template with '{{ variable }}' placeholders
+        #[arg(short, long)]
+        template: bool,
+
+        /// Store the dotfile encrypted in the repository and deploy it as a private copy
+        #[arg(short, long)]
+        encrypt: bool,
+
+        /// How to put the dotfile in place on link
+        #[arg(short, long, value_enum)]
+        deploy: Option<DeployMode>,
+
+        /// Leave out paths inside a directory matching a gitignore-style pattern (repeatable)
+        #[arg(short, long)]
+        ignore: Vec<String>,
+
+        /// What to do with symlinks inside a directory
+        #[arg(long, value_enum)]
+        symlinks: Option<SymlinkPolicy>,
     },
 
     /// Remove a dotfile from tracking
@@ -74,12 +201,58 @@ pub enum Commands {
     },
 
     /// Commit staged dotfiles to make them permanent
-    Commit,
+    Commit {
+        /// Message describing the commit
+        #[arg(short, long)]
+        message: Option<String>,
+    },
+
+    /// Show the commit history, newest first
+    Log {
+        /// Limit the number of commits shown
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+
+    /// Restore dotfiles into staging from an earlier commit
+    Restore {
+        /// Name of the dotfile to restore (all tracked dotfiles if omitted)
+        name: Option<String>,
+
+        /// Commit id (or prefix) to restore from
+        #[arg(long)]
+        to: String,
+    },
+
+    /// Render template dotfiles into the generated directory
+    Render {
+        /// Name of the dotfile to render (all templates if omitted)
+        name: Option<String>,
+    },
 
     /// Link all staged dotfiles to their target locations
     Link {
         /// Name of the dotfile to link
         name: Option<String>,
+
+        /// Create symlinks relative to the link location
+        #[arg(long, conflicts_with = "absolute")]
+        relative: bool,
+
+        /// Create absolute symlinks, overriding the repository default
+        #[arg(long)]
+        absolute: bool,
+
+        /// What to do with local files that are in the way and differ from the repository
+        #[arg(long, value_enum, default_value = "ask")]
+        on_conflict: ConflictPolicy,
+    },
+
+    /// Pull the git repository and re-link the dotfiles that changed upstream
+    Sync {
+        /// What to do with local files that are in the way and differ from the repository
+        #[arg(long, value_enum, default_value = "ask")]
+        on_conflict: ConflictPolicy,
     },
 
     /// Unlink all tracked dotfiles
@@ -89,10 +262,16 @@ pub enum Commands {
     },
 
     /// Show the status of tracked and staged dotfiles
-    Status,
+    Status {
+        #[command(flatten)]
+        output: OutputArgs,
+    },
 
     /// List all tracked dotfiles
-    List,
+    List {
+        #[command(flatten)]
+        output: OutputArgs,
+    },
 
     /// Show differences between tracked and local dotfiles
     Diff {
@@ -106,6 +285,45 @@ pub enum Commands {
         /// Show a word-by-word diff instead of line-by-line
         #[arg(short, long)]
         word: bool,
+
+        /// Compare the staged copy against the repository instead of the local file
+        #[arg(short, long)]
+        staged: bool,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Check the repository, the staging area and the links for problems
+    Doctor {
+        /// Apply the fixes that can't lose data
+        #[arg(long)]
+        fix: bool,
+    },
+
+    /// Upgrade the repository config to the current schema version
+    Migrate {
+        /// Only report pending migrations without writing anything
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Manage the repository manifest
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommands,
+    },
+
+    /// Manage per-host and per-profile dotfile sets
+    Profile {
+        #[command(subcommand)]
+        action: ProfileCommands,
+    },
+
+    /// Manage backups of files replaced by link
+    Backups {
+        #[command(subcommand)]
+        action: BackupCommands,
     },
 
     Completion {
@@ -116,3 +334,125 @@ pub enum Commands {
     /// Launch the Terminal User Interface
     Tui,
 }
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Convert the manifest between .dotzilla.json and dotzilla.toml
+    Convert {
+        /// Target format (defaults to the format not currently in use)
+        #[arg(long, value_enum)]
+        to: Option<ConfigFormat>,
+    },
+
+    /// Set whether link creates relative symlinks by default
+    RelativeLinks {
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+
+    /// Set whether commit also commits, and pushes, the dotfiles to git
+    Git {
+        #[arg(value_enum)]
+        mode: GitMode,
+    },
+
+    /// Set the command privileged dotfiles are changed through, e.g. sudo or doas
+    Escalation {
+        /// Command to prefix with, it may carry its own arguments like 'sudo -n'
+        command: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ProfileCommands {
+    /// List the defined profiles
+    List,
+
+    /// Create or replace a profile
+    Set {
+        /// Name of the profile
+        name: String,
+
+        /// Hostname that selects this profile automatically (repeatable)
+        #[arg(long = "host")]
+        hosts: Vec<String>,
+
+        /// Dotfile or directory to include, everything when omitted (repeatable)
+        #[arg(long)]
+        include: Vec<String>,
+
+        /// Dotfile or directory to exclude (repeatable)
+        #[arg(long)]
+        exclude: Vec<String>,
+    },
+
+    /// Remove a profile
+    Remove {
+        /// Name of the profile
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BackupCommands {
+    /// List backups, newest first
+    List,
+
+    /// Move a backup back to its original location
+    Restore {
+        /// Id of the backup, or an unambiguous prefix
+        id: String,
+    },
+
+    /// Delete old backups
+    Prune {
+        /// Number of most recent backups to keep
+        #[arg(long)]
+        keep: Option<usize>,
+
+        /// Only delete backups older than this many days
+        #[arg(long)]
+        older_than: Option<u32>,
+    },
+}
+
+impl Commands {
+    /// Whether the command modifies the repository and must hold its lock
+    pub fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Commands::Add { .. }
+                | Commands::Remove { .. }
+                | Commands::Stage { .. }
+                | Commands::Unstage { .. }
+                | Commands::Commit { .. }
+                | Commands::Restore { .. }
+                | Commands::Render { .. }
+                | Commands::Link { .. }
+                | Commands::Sync { .. }
+                | Commands::Unlink { .. }
+                | Commands::Migrate { check: false }
+                | Commands::Doctor { fix: true }
+                | Commands::Config { .. }
+                | Commands::Profile {
+                    action: ProfileCommands::Set { .. } | ProfileCommands::Remove { .. }
+                }
+                | Commands::Backups {
+                    action: BackupCommands::Restore { .. } | BackupCommands::Prune { .. }
+                }
+        )
+    }
+
+    /// Whether the command can print its plan instead of running it
+    pub fn supports_dry_run(&self) -> bool {
+        matches!(
+            self,
+            Commands::Add { .. }
+                | Commands::Remove { .. }
+                | Commands::Stage { .. }
+                | Commands::Commit { .. }
+                | Commands::Link { .. }
+                | Commands::Unlink { .. }
+        )
+    }
+}
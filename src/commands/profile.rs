@@ -0,0 +1,67 @@
+use anyhow::Result;
+use colored::*;
+
+use crate::models::{Config, DotPath, Profile};
+
+pub fn list_profiles(config: &Config) -> Result<()> {
+    println!("{} Profiles", "✦".cyan());
+    println!("{}", "==========".cyan());
+
+    if config.profiles().is_empty() {
+        println!("No profiles defined. Use 'dotzilla profile set <name>' to define one.");
+        return Ok(());
+    }
+
+    for (name, profile) in config.profiles() {
+        let active = if config.active_profile() == Some(name.as_str()) {
+            "(active)".green()
+        } else {
+            "".normal()
+        };
+
+        println!("{} {} {}", "•".cyan(), name.bold(), active);
+        if !profile.hosts.is_empty() {
+            println!("    hosts:   {}", profile.hosts.join(", "));
+        }
+        for path in &profile.include {
+            println!("    include: {}", path.display());
+        }
+        for path in &profile.exclude {
+            println!("    exclude: {}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+pub fn set_profile(
+    config: &mut Config,
+    name: &str,
+    hosts: Vec<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+) -> Result<()> {
+    let profile = Profile {
+        hosts,
+        include: include
+            .iter()
+            .map(|path| DotPath::new(config, path).to_name().clone())
+            .collect(),
+        exclude: exclude
+            .iter()
+            .map(|path| DotPath::new(config, path).to_name().clone())
+            .collect(),
+    };
+
+    config.set_profile(name, profile)?;
+
+    println!("{} Saved profile: {}", "✓".green(), name);
+    Ok(())
+}
+
+pub fn remove_profile(config: &mut Config, name: &str) -> Result<()> {
+    config.remove_profile(name)?;
+
+    println!("{} Removed profile: {}", "✓".green(), name);
+    Ok(())
+}
@@ -1,15 +1,39 @@
 use std::fs;
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use colored::*;
 
-use crate::models::{Config, DotPath};
+use crate::models::{Config, DotPath, Operation, Plan};
 use crate::utils;
 
-pub fn remove_dotfile(mut config: Config, dotfile_path: DotPath, keep_file: bool) -> Result<()> {
+pub fn remove_dotfile(
+    mut config: Config,
+    dotfile_path: DotPath,
+    keep_file: bool,
+    dry_run: bool,
+) -> Result<()> {
     // Check if the dotfile is tracked
     let _entry = config.get_dotfile(&dotfile_path)?;
 
+    let mut plan = Plan::new();
+    if !keep_file {
+        if fs::symlink_metadata(&dotfile_path.abs_target).is_ok() {
+            plan.push(Operation::Delete(dotfile_path.abs_target.clone()));
+        } else {
+            println!(
+                "{} File/directory does not exist: {}",
+                "!".yellow(),
+                dotfile_path.abs_target.display()
+            );
+        }
+    }
+
+    if dry_run {
+        println!("Would remove {} from tracking", dotfile_path);
+        plan.print();
+        return Ok(());
+    }
+
     // If keep_file is false, ask for confirmation to delete the file
     if !keep_file {
         let message = format!(
@@ -34,41 +58,5 @@ pub fn remove_dotfile(mut config: Config, dotfile_path: DotPath, keep_file: bool
     );
 
     // If keep_file is false, delete the actual file/directory
-    if !keep_file {
-        if dotfile_path.abs_target.exists() {
-            if dotfile_path.abs_target.is_dir() {
-                fs::remove_dir_all(&dotfile_path.abs_target).with_context(|| {
-                    format!(
-                        "Failed to remove directory at {}",
-                        dotfile_path.abs_target.display()
-                    )
-                })?;
-                println!(
-                    "{} Removed directory: {}",
-                    "✓".green(),
-                    dotfile_path.abs_target.display()
-                );
-            } else {
-                fs::remove_file(&dotfile_path.abs_target).with_context(|| {
-                    format!(
-                        "Failed to remove file at {}",
-                        dotfile_path.abs_target.display()
-                    )
-                })?;
-                println!(
-                    "{} Removed file: {}",
-                    "✓".green(),
-                    dotfile_path.abs_target.display()
-                );
-            }
-        } else {
-            println!(
-                "{} File/directory does not exist: {}",
-                "!".yellow(),
-                dotfile_path.abs_target.display()
-            );
-        }
-    }
-
-    Ok(())
+    plan.execute()
 }
@@ -0,0 +1,32 @@
+use anyhow::Result;
+use colored::*;
+
+use crate::models::{Config, write_rendered};
+use crate::utils::filter::filter_dotfiles_exact;
+
+pub fn render_templates(config: &Config, name: Option<String>) -> Result<()> {
+    let dotfiles = config.get_active();
+    let templates = filter_dotfiles_exact(dotfiles.iter(), name.as_deref())
+        .into_iter()
+        .filter(|(_, entry)| entry.template)
+        .collect::<Vec<_>>();
+
+    if templates.is_empty() {
+        println!(
+            "No template dotfiles to render. Use 'dotzilla add --template <path>' to add one."
+        );
+        return Ok(());
+    }
+
+    for (dotpath, _) in templates {
+        let rendered = write_rendered(config.repo_path(), dotpath)?;
+        println!(
+            "{} Rendered {} -> {}",
+            "✓".green(),
+            dotpath,
+            rendered.display()
+        );
+    }
+
+    Ok(())
+}
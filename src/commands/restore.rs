@@ -0,0 +1,155 @@
+use std::fs;
+
+use anyhow::{Context, Result, anyhow};
+use colored::*;
+
+use crate::models::{
+    Config, DotPath, DotfileStatus, History, copy_snapshot, decrypt_dotfile, secret_digest,
+};
+
+/// Stage dotfiles with their content as of an earlier commit
+///
+/// Without a name, every dotfile tracked at that commit is restored, including the ones
+/// removed since. Restored entries get back the settings they had at the commit.
+pub fn restore_dotfiles(config: &mut Config, name: Option<String>, commit_id: &str) -> Result<()> {
+    let history = History::new(config.repo_path());
+    let commit = history.get(commit_id)?;
+
+    let dotpaths: Vec<DotPath> = match name {
+        Some(name) => {
+            let dotpath = DotPath::new(config, &name);
+            if !commit.entries.contains_key(dotpath.to_name()) {
+                config.get_dotfile(&dotpath)?;
+            }
+            vec![dotpath]
+        }
+        None => {
+            let mut dotpaths: Vec<DotPath> = config.get().into_keys().collect();
+            for name in commit.entries.keys() {
+                let dotpath = DotPath::from_path(config, name.clone());
+                if !dotpaths.contains(&dotpath) {
+                    dotpaths.push(dotpath);
+                }
+            }
+            dotpaths.sort_by_key(|dotpath| dotpath.to_name().clone());
+            dotpaths
+        }
+    };
+
+    let mut restore_count = 0;
+
+    for dotpath in dotpaths {
+        let Some(snapshot) = history.snapshot_at(&commit, &dotpath)? else {
+            println!(
+                "{} No snapshot of {} at commit {}",
+                "!".yellow(),
+                dotpath,
+                commit.id
+            );
+            continue;
+        };
+
+        if dotpath.abs_target_staged.is_dir() {
+            fs::remove_dir_all(&dotpath.abs_target_staged)?;
+        } else if dotpath.abs_target_staged.exists() {
+            fs::remove_file(&dotpath.abs_target_staged)?;
+        }
+
+        copy_snapshot(&snapshot, &dotpath.abs_target_staged)
+            .with_context(|| format!("Failed to restore {} from commit {}", dotpath, commit.id))?;
+
+        // Commits recorded before entries were kept only have the current entry
+        let mut staged_entry = match commit.entries.get(dotpath.to_name()) {
+            Some(entry) => entry.clone(),
+            None => config.get_dotfile(&dotpath)?.clone(),
+        };
+        staged_entry.status = DotfileStatus::Staged;
+        if staged_entry.encrypted {
+            // Decrypted in memory only, to keep tracking the plaintext by its digest
+            let plaintext = decrypt_dotfile(&snapshot)?;
+            staged_entry.hash = Some(secret_digest(dotpath.to_name(), &plaintext)?);
+        }
+        config.stage(&dotpath, staged_entry)?;
+
+        println!(
+            "{} Restored {} from commit {} into staging",
+            "✓".green(),
+            dotpath,
+            commit.id
+        );
+        restore_count += 1;
+    }
+
+    if restore_count == 0 {
+        return Err(anyhow!("Nothing to restore from commit {}", commit.id));
+    }
+
+    println!(
+        "Use 'dotzilla diff --staged <name>' to review and 'dotzilla commit' to apply the restore."
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{AddOptions, commit_dotfiles, remove_dotfile, stage_dotfile, testing};
+    use crate::models::DeployMode;
+    use std::path::Path;
+
+    #[test]
+    fn test_restore_earlier_commit() {
+        let root = Path::new("/tmp/test_dotzilla_restore");
+        let config = testing::repo(root);
+        let vimrc = testing::tracked_file(&config, "vimrc", "one", 0o644, AddOptions::default());
+        let gitrc = testing::tracked_file(
+            &testing::reload(&config),
+            "gitrc",
+            "bee",
+            0o600,
+            AddOptions {
+                deploy: Some(DeployMode::Copy),
+                ..Default::default()
+            },
+        );
+        let mut config = testing::reload(&config);
+        stage_dotfile(&mut config, &vimrc, false).unwrap();
+        stage_dotfile(&mut config, &gitrc, false).unwrap();
+        commit_dotfiles(&mut config, Some("first".to_string()), false).unwrap();
+        let history = History::new(config.repo_path());
+        let first = history.list().unwrap().remove(0);
+
+        // `vimrc` changes and `gitrc` is removed after the first commit
+        fs::write(&vimrc.abs_path, "two").unwrap();
+        stage_dotfile(&mut config, &vimrc, false).unwrap();
+        commit_dotfiles(&mut config, Some("second".to_string()), false).unwrap();
+        remove_dotfile(config.clone(), gitrc.clone(), true, false).unwrap();
+        fs::remove_file(&gitrc.abs_target).unwrap();
+        assert_eq!(fs::read_to_string(&vimrc.abs_target).unwrap(), "two");
+
+        let mut config = testing::reload(&config);
+        restore_dotfiles(&mut config, None, &first.id).unwrap();
+
+        let config = testing::reload(&config);
+        assert_eq!(fs::read_to_string(&vimrc.abs_target_staged).unwrap(), "one");
+        assert_eq!(fs::read_to_string(&gitrc.abs_target_staged).unwrap(), "bee");
+        let staged = config.get_staged();
+        assert_eq!(staged[&vimrc].status, DotfileStatus::Staged);
+        assert_eq!(staged[&gitrc].status, DotfileStatus::Staged);
+        assert_eq!(staged[&gitrc].deploy, DeployMode::Copy);
+        assert!(config.get_dotfile(&gitrc).is_err());
+
+        // Committing the restore tracks the removed entry again
+        let mut config = config;
+        commit_dotfiles(&mut config, None, false).unwrap();
+        let config = testing::reload(&config);
+        assert_eq!(fs::read_to_string(&vimrc.abs_target).unwrap(), "one");
+        assert_eq!(fs::read_to_string(&gitrc.abs_target).unwrap(), "bee");
+        assert_eq!(
+            config.get_dotfile(&gitrc).unwrap().status,
+            DotfileStatus::Tracked
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
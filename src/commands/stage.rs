@@ -1,11 +1,34 @@
+use std::path::PathBuf;
+
 use anyhow::{Context, Result, anyhow};
 use colored::*;
-use std::fs;
 
-use crate::models::{Config, DotPath, DotfileStatus};
+use crate::models::{
+    Config, DeployMode, DotPath, DotfileEntry, DotfileStatus, Operation, Plan, secret_digest,
+};
+use crate::utils::ignore::IgnoreRules;
+
+pub fn stage_dotfile(config: &mut Config, dotfile_path: &DotPath, dry_run: bool) -> Result<()> {
+    let (plan, staged_entry) = plan_stage(config, dotfile_path)?;
+
+    if dry_run {
+        plan.print();
+        return Ok(());
+    }
+
+    apply_stage(config, dotfile_path, &plan, staged_entry)
+}
 
-pub fn stage_dotfile(config: &mut Config, dotfile_path: &DotPath) -> Result<()> {
+/// Plan copying a local dotfile into staging, returning the entry to stage once it ran
+pub(super) fn plan_stage(config: &Config, dotfile_path: &DotPath) -> Result<(Plan, DotfileEntry)> {
     let entry = config.get_dotfile(dotfile_path)?.clone();
+    if entry.template {
+        return Err(anyhow!(
+            "{} is a template, edit the template at {} instead of staging the rendered file",
+            dotfile_path,
+            dotfile_path.abs_target.display()
+        ));
+    }
     let mut staged_entry = entry.clone();
     staged_entry.status = DotfileStatus::Staged;
 
@@ -17,51 +40,83 @@ pub fn stage_dotfile(config: &mut Config, dotfile_path: &DotPath) -> Result<()>
         ));
     }
 
-    if let Some(parent) = dotfile_path.target_staged.parent() {
-        if !parent.exists() {
-            fs::create_dir_all(parent)?;
-            println!(
-                "{} Created staging directory: {}",
-                "✓".green(),
-                parent.display()
-            );
-        }
-    }
+    staged_entry.modes = config.capture_modes(dotfile_path, &entry)?;
 
-    println!(
-        "{} Staging file: {} -> {}",
-        "✓".green(),
-        dotfile_path.abs_path.display(),
-        dotfile_path.target_staged.display()
-    );
+    let mut plan = Plan::new();
+    if let Some(parent) = dotfile_path.abs_target_staged.parent()
+        && !parent.exists()
+    {
+        plan.push(Operation::CreateDir(parent.to_path_buf()));
+    }
 
     // Copy the file to the repo
-    if dotfile_path.abs_path.is_dir() {
-        let mut opts = fs_extra::dir::CopyOptions::new();
-        opts.copy_inside = true;
-        opts.overwrite = true;
-        opts.skip_exist = false;
-
-        fs_extra::dir::copy(
-            &dotfile_path.abs_path,
-            &dotfile_path.abs_target_staged,
-            &opts,
-        )
-        .with_context(|| {
-            format!(
-                "Failed to copy directory from {} to {}",
-                dotfile_path.abs_path.display(),
-                dotfile_path.target_staged.display()
-            )
-        })?;
+    if entry.encrypted {
+        plan.push(Operation::Encrypt {
+            from: dotfile_path.abs_path.clone(),
+            to: dotfile_path.abs_target_staged.clone(),
+        });
+    } else if entry.deploy == DeployMode::Unfold {
+        // Only the tracked files, not what the application keeps next to them. The staged
+        // directory replaces the repository copy, so files missing locally keep theirs.
+        let mut created_dirs: Vec<PathBuf> = Vec::new();
+        for file in config.unfolded_files(dotfile_path, &entry)? {
+            let mut local = dotfile_path.abs_path.join(&file);
+            if !local.exists() {
+                local = dotfile_path.abs_target.join(&file);
+                if !local.exists() {
+                    continue;
+                }
+                println!(
+                    "{} {} is missing locally, keeping the repository copy",
+                    "!".yellow(),
+                    dotfile_path.to_name().join(&file).display()
+                );
+            }
+
+            let staged = dotfile_path.abs_target_staged.join(&file);
+            if let Some(parent) = staged.parent()
+                && !parent.exists()
+                && !created_dirs.iter().any(|dir| dir == parent)
+            {
+                created_dirs.push(parent.to_path_buf());
+                plan.push(Operation::CreateDir(parent.to_path_buf()));
+            }
+            plan.push(Operation::Copy {
+                from: local,
+                to: staged,
+                ignore: IgnoreRules::default(),
+            });
+        }
     } else {
-        fs::copy(&dotfile_path.abs_path, &dotfile_path.abs_target_staged).with_context(|| {
-            format!(
-                "Failed to copy file from {} to {}",
-                dotfile_path.abs_path.display(),
-                dotfile_path.target_staged.display()
-            )
-        })?;
+        plan.push(Operation::Copy {
+            from: dotfile_path.abs_path.clone(),
+            to: dotfile_path.abs_target_staged.clone(),
+            ignore: config.entry_rules(&entry)?,
+        });
+    }
+
+    // Reading a privileged local path may need root too
+    if let Some(command) = config.escalation_for(&entry) {
+        plan.escalate(config.repo_path(), command);
+    }
+
+    Ok((plan, staged_entry))
+}
+
+/// Execute a staging plan and record the staged entry
+pub(super) fn apply_stage(
+    config: &mut Config,
+    dotfile_path: &DotPath,
+    plan: &Plan,
+    mut staged_entry: DotfileEntry,
+) -> Result<()> {
+    plan.execute()?;
+
+    // Encrypted entries are tracked by a keyed digest of their plaintext
+    if staged_entry.encrypted {
+        let plaintext = std::fs::read(&dotfile_path.abs_path)
+            .with_context(|| format!("Failed to read {}", dotfile_path.abs_path.display()))?;
+        staged_entry.hash = Some(secret_digest(dotfile_path.to_name(), &plaintext)?);
     }
 
     // Update the staged collection and save config
@@ -75,3 +130,39 @@ pub fn stage_dotfile(config: &mut Config, dotfile_path: &DotPath) -> Result<()>
     );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{commit_dotfiles, testing};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_unfold_commit_keeps_missing_files() {
+        let root = Path::new("/tmp/test_dotzilla_stage_unfold");
+        let config = testing::repo(root);
+        let dotpath = testing::unfolded_fish(&config);
+        let mut config = testing::reload(&config);
+
+        fs::remove_file(dotpath.abs_path.join("functions/ll.fish")).unwrap();
+        fs::write(dotpath.abs_path.join("config.fish"), "set -x EDITOR hx").unwrap();
+        fs::write(dotpath.abs_path.join("notes.fish"), "untracked").unwrap();
+        stage_dotfile(&mut config, &dotpath, false).unwrap();
+        commit_dotfiles(&mut config, None, false).unwrap();
+
+        let repo = &dotpath.abs_target;
+        assert_eq!(
+            fs::read_to_string(repo.join("config.fish")).unwrap(),
+            "set -x EDITOR hx"
+        );
+        assert_eq!(
+            fs::read_to_string(repo.join("functions/ll.fish")).unwrap(),
+            "ls -l"
+        );
+        assert!(!repo.join("notes.fish").exists());
+        assert!(!repo.join("fish_history").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,59 +1,387 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
 use anyhow::{Result, anyhow};
 use colored::*;
+use serde::Serialize;
 
+use super::OutputFormat;
 use crate::{
-    models::{Config, DotfileStatus},
-    utils::path_compare::{self, CompareResult},
+    models::{
+        Activation, Config, DeployMode, DotPath, DotfileEntry, DotfileStatus, decrypt_dotfile,
+        render_dotfile, rendered_path, secret_digest,
+    },
+    utils::{
+        git::{self, Upstream},
+        ignore::IgnoreRules,
+        mode::{self, FileMode, ModeChange},
+        path_compare::{self, ChangeResult, CompareResult},
+        tree::tree_lines,
+    },
 };
 
-pub fn show_status(config: &Config) -> Result<()> {
+/// What `status` found for a single dotfile
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+enum FileState {
+    Inactive,
+    Tracked,
+    Linked,
+    Deployed,
+    NotDeployed,
+    LocalChanged,
+    RepoChanged,
+    BothChanged,
+    DiffDetected,
+    DecryptFailed,
+    RenderFailed,
+    RenderOutdated,
+    ModeChanged,
+    Modified,
+    Unknown,
+    InStaging,
+    Pending,
+}
+
+impl FileState {
+    fn label(self) -> ColoredString {
+        match self {
+            FileState::Inactive => "[Inactive]".dimmed(),
+            FileState::Tracked => "[Tracked]".green(),
+            FileState::Linked => "[Linked]".blue(),
+            FileState::Deployed => "[Deployed]".blue(),
+            FileState::NotDeployed => "[Not Deployed]".yellow(),
+            FileState::LocalChanged => "[Local Changed]".yellow(),
+            FileState::RepoChanged => "[Repo Changed]".yellow(),
+            FileState::BothChanged => "[Both Changed]".red(),
+            FileState::DiffDetected => "[Diff Detected]".yellow(),
+            FileState::DecryptFailed => "[Decrypt Failed]".red(),
+            FileState::RenderFailed => "[Render Failed]".red(),
+            FileState::RenderOutdated => "[Render Outdated]".yellow(),
+            FileState::ModeChanged => "[Mode Changed]".yellow(),
+            FileState::Modified => "[Modified]".yellow(),
+            FileState::Unknown => "[Unknown]".red(),
+            FileState::InStaging => "[In Staging]".green(),
+            FileState::Pending => "[Pending]".yellow(),
+        }
+    }
+
+    /// The word `status --porcelain` prints for the state
+    fn token(self) -> &'static str {
+        match self {
+            FileState::Inactive => "inactive",
+            FileState::Tracked => "tracked",
+            FileState::Linked => "linked",
+            FileState::Deployed => "deployed",
+            FileState::NotDeployed => "not-deployed",
+            FileState::LocalChanged => "local-changed",
+            FileState::RepoChanged => "repo-changed",
+            FileState::BothChanged => "both-changed",
+            FileState::DiffDetected => "diff-detected",
+            FileState::DecryptFailed => "decrypt-failed",
+            FileState::RenderFailed => "render-failed",
+            FileState::RenderOutdated => "render-outdated",
+            FileState::ModeChanged => "mode-changed",
+            FileState::Modified => "modified",
+            FileState::Unknown => "unknown",
+            FileState::InStaging => "in-staging",
+            FileState::Pending => "pending",
+        }
+    }
+
+    /// Whether the local copy no longer matches what the repository deploys
+    fn is_drift(self) -> bool {
+        !matches!(
+            self,
+            FileState::Inactive
+                | FileState::Tracked
+                | FileState::Linked
+                | FileState::Deployed
+                | FileState::InStaging
+                | FileState::Pending
+        )
+    }
+
+    fn compare(self) -> Option<CompareResult> {
+        match self {
+            FileState::Tracked | FileState::Deployed | FileState::InStaging => {
+                Some(CompareResult::Equal)
+            }
+            FileState::Linked => Some(CompareResult::Linked),
+            FileState::NotDeployed
+            | FileState::LocalChanged
+            | FileState::RepoChanged
+            | FileState::BothChanged
+            | FileState::DiffDetected
+            | FileState::RenderOutdated
+            | FileState::Modified => Some(CompareResult::NotEqual),
+            _ => None,
+        }
+    }
+}
+
+/// A dotfile as reported by `status --format json`
+#[derive(Serialize)]
+struct StatusRecord {
+    path: String,
+    status: DotfileStatus,
+    state: FileState,
+    compare: Option<CompareResult>,
+    staged: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    /// Files linked one by one into an unfolded directory
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    files: Vec<FileRecord>,
+    /// Files whose mode or owner differs from the recorded one
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    modes: Vec<ModeRecord>,
+}
+
+/// A file inside an unfolded directory as reported by `status --format json`
+#[derive(Serialize)]
+struct FileRecord {
+    path: String,
+    state: FileState,
+}
+
+/// A file whose mode differs as reported by `status` and `diff --format json`,
+/// relative to the dotfile with `.` for the dotfile itself
+#[derive(Serialize)]
+pub(super) struct ModeRecord {
+    path: String,
+    expected: FileMode,
+    actual: FileMode,
+}
+
+impl ModeRecord {
+    pub(super) fn from_changes(changes: Vec<ModeChange>) -> Vec<ModeRecord> {
+        changes
+            .into_iter()
+            .map(|change| ModeRecord {
+                path: change.path.display().to_string(),
+                expected: change.expected,
+                actual: change.actual,
+            })
+            .collect()
+    }
+
+    /// `mode <expected> <actual> <path>` and `owner <expected> <actual> <path>` lines
+    /// for scripts
+    pub(super) fn porcelain_lines(records: &[ModeRecord], name: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        for record in records {
+            let path = mode::resolve(Path::new(name), Path::new(&record.path));
+            if record.expected.mode != record.actual.mode {
+                lines.push(format!(
+                    "mode {:04o} {:04o} {}",
+                    record.expected.mode,
+                    record.actual.mode,
+                    path.display()
+                ));
+            }
+            if let Some(owner) = &record.expected.owner
+                && record.actual.owner.as_ref() != Some(owner)
+            {
+                let actual = record.actual.owner.as_deref().unwrap_or("-");
+                lines.push(format!("owner {} {} {}", owner, actual, path.display()));
+            }
+        }
+        lines
+    }
+}
+
+impl StatusRecord {
+    fn new(dotpath: &DotPath, status: DotfileStatus, state: FileState, staged: bool) -> Self {
+        StatusRecord {
+            path: dotpath.to_name().display().to_string(),
+            status,
+            state,
+            compare: state.compare(),
+            staged,
+            reason: None,
+            files: Vec::new(),
+            modes: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StatusReport {
+    profile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstream: Option<Upstream>,
+    dotfiles: Vec<StatusRecord>,
+    staged: Vec<StatusRecord>,
+}
+
+/// Show the status of tracked and staged dotfiles, returning whether any drifted
+pub fn show_status(config: &Config, format: OutputFormat) -> Result<bool> {
+    let report = status_report(config)?;
+    let drift = report
+        .dotfiles
+        .iter()
+        .chain(&report.staged)
+        .any(|record| record.state.is_drift());
+
+    match format {
+        OutputFormat::Text => print_status(&report),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::Porcelain => {
+            for line in porcelain_lines(&report) {
+                println!("{}", line);
+            }
+        }
+    }
+
+    Ok(drift)
+}
+
+/// The lines of `status --porcelain`
+fn porcelain_lines(report: &StatusReport) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(upstream) = &report.upstream {
+        lines.push(format!(
+            "# upstream {} +{} -{}",
+            upstream.name, upstream.ahead, upstream.behind
+        ));
+    }
+    for record in &report.dotfiles {
+        lines.push(format!("tracked {} {}", record.state.token(), record.path));
+        for file in &record.files {
+            lines.push(format!(
+                "file {} {}",
+                file.state.token(),
+                Path::new(&record.path).join(&file.path).display()
+            ));
+        }
+        lines.extend(ModeRecord::porcelain_lines(&record.modes, &record.path));
+    }
+    for record in &report.staged {
+        lines.push(format!("staged {} {}", record.state.token(), record.path));
+    }
+    lines
+}
+
+fn print_status(report: &StatusReport) {
     println!("{} Dotfiles Status", "✦".cyan());
     println!("{}", "=================".cyan());
 
-    let staged = config.get_staged();
+    if let Some(profile) = &report.profile {
+        println!("Profile: {}", profile.bold());
+    }
 
-    if config.get().is_empty() && staged.is_empty() {
+    if let Some(upstream) = &report.upstream {
+        let counts = format!("{} ahead, {} behind", upstream.ahead, upstream.behind);
+        if upstream.ahead > 0 || upstream.behind > 0 {
+            println!("Upstream: {} ({})", upstream.name, counts.yellow());
+        } else {
+            println!("Upstream: {} ({})", upstream.name, counts.dimmed());
+        }
+    }
+
+    if report.dotfiles.is_empty() && report.staged.is_empty() {
         println!("No dotfiles tracked. Use 'dotzilla add <path>' to add dotfiles.");
-        return Ok(());
+        return;
     }
 
     println!("{}", "Tracked dotfiles:".bold());
-    for (dotpath, entry) in config.get() {
-        let status_str = match entry.status {
-            DotfileStatus::Tracked => {
-                let compare_result =
-                    path_compare::compare_paths(&dotpath.abs_path, &dotpath.abs_target).map_err(
-                        |err| {
-                            anyhow!(
-                                "Error comparing files: {}. Please check the paths {}",
-                                err,
-                                dotpath.abs_path.display()
-                            )
-                        },
-                    )?;
-
-                match compare_result {
-                    CompareResult::Equal => "[Tracked]".green(),
-                    CompareResult::NotEqual => "[Diff Detected]".yellow(),
-                    CompareResult::Linked => "[Linked]".blue(),
-                }
-            }
-            DotfileStatus::Modified => "[Modified]".yellow(),
-            _ => "[Unknown]".red(),
-        };
-
-        println!("{} ({})", status_str, dotpath);
+    for record in &report.dotfiles {
+        match &record.reason {
+            Some(reason) => println!(
+                "{} ({}) {}",
+                record.state.label(),
+                record.path,
+                format!("condition not met: {}", reason).dimmed()
+            ),
+            None => println!("{} ({})", record.state.label(), record.path),
+        }
+        print_files(record);
+        print_modes(record);
     }
 
     println!();
     println!("{}", "Staged for linking:".bold());
-    if config.get_staged().is_empty() {
+    if report.staged.is_empty() {
         println!("No dotfiles staged. Use 'dotzilla stage <name>' to stage dotfiles.");
     } else {
-        for dotpath in staged.keys() {
-            let stage_status = if dotpath.target_staged.exists() {
+        for record in &report.staged {
+            println!(
+                "{} {} ({})",
+                "[Staged]".blue(),
+                record.state.label(),
+                record.path
+            );
+        }
+    }
+}
+
+/// Print the files of an unfolded directory as a tree under its line
+fn print_files(record: &StatusRecord) {
+    let paths: Vec<PathBuf> = record
+        .files
+        .iter()
+        .map(|file| PathBuf::from(&file.path))
+        .collect();
+
+    for (line, index) in tree_lines(&paths) {
+        match index {
+            Some(index) => println!("    {} {}", line, record.files[index].state.label()),
+            None => println!("    {}", line),
+        }
+    }
+}
+
+/// Print the files whose mode differs under the line of their dotfile
+fn print_modes(record: &StatusRecord) {
+    for change in &record.modes {
+        let path = mode::resolve(Path::new(&record.path), Path::new(&change.path));
+        println!(
+            "    {} {} is {}, expected {}",
+            "!".yellow(),
+            path.display(),
+            change.actual,
+            change.expected
+        );
+    }
+}
+
+fn status_report(config: &Config) -> Result<StatusReport> {
+    let staged = config.get_staged();
+
+    let mut active: Vec<(DotPath, DotfileEntry)> = config.get_active().into_iter().collect();
+    active.sort_by_key(|(dotpath, _)| dotpath.to_name().clone());
+
+    let mut dotfiles = Vec::new();
+    for (dotpath, entry) in active {
+        let is_staged = staged.contains_key(&dotpath);
+
+        if let Activation::Inactive(reason) = entry.activation() {
+            let mut record =
+                StatusRecord::new(&dotpath, entry.status, FileState::Inactive, is_staged);
+            record.reason = Some(reason);
+            dotfiles.push(record);
+            continue;
+        }
+
+        let mut files = Vec::new();
+        let state = match entry.status {
+            DotfileStatus::Tracked if entry.template => template_status(config, &dotpath, &entry)?,
+            DotfileStatus::Tracked if entry.deploy == DeployMode::Unfold => {
+                let (state, records) = unfolded_status(config, &dotpath, &entry)?;
+                files = records;
+                state
+            }
+            DotfileStatus::Tracked if entry.encrypted => secret_status(&dotpath, &entry),
+            DotfileStatus::Tracked
+                if entry.deploy == DeployMode::Hardlink
+                    && path_compare::hardlinked_paths(&dotpath.abs_path, &dotpath.abs_target) =>
+            {
+                FileState::Linked
+            }
+            DotfileStatus::Tracked => {
+                let ignore = config.entry_rules(&entry)?;
                 let compare_result =
-                    path_compare::compare_paths(&dotpath.abs_path, &dotpath.abs_target_staged)
+                    path_compare::compare_paths(&dotpath.abs_path, &dotpath.abs_target, &ignore)
                         .map_err(|err| {
                             anyhow!(
                                 "Error comparing files: {}. Please check the paths {}",
@@ -62,23 +390,323 @@ pub fn show_status(config: &Config) -> Result<()> {
                             )
                         })?;
 
-                match compare_result {
-                    CompareResult::Equal => "[In Staging]".green(),
-                    CompareResult::NotEqual => "[Diff Detected]".yellow(),
-                    CompareResult::Linked => "[Linked]".blue(),
+                match (compare_result, entry.hash.as_deref()) {
+                    (CompareResult::Equal, _) if entry.deploy == DeployMode::Copy => {
+                        FileState::Deployed
+                    }
+                    (CompareResult::Equal, _) => FileState::Tracked,
+                    (CompareResult::Linked, _) => FileState::Linked,
+                    (CompareResult::NotEqual, Some(baseline))
+                        if dotpath.abs_path.exists() && dotpath.abs_target.exists() =>
+                    {
+                        let change_result = path_compare::compare_with_baseline(
+                            &dotpath.abs_path,
+                            &dotpath.abs_target,
+                            baseline,
+                            &ignore,
+                        )
+                        .map_err(|err| {
+                            anyhow!(
+                                "Error hashing files: {}. Please check the paths {}",
+                                err,
+                                dotpath.abs_path.display()
+                            )
+                        })?;
+
+                        change_state(change_result, FileState::Tracked)
+                    }
+                    (CompareResult::NotEqual, _) => FileState::DiffDetected,
+                }
+            }
+            DotfileStatus::Modified => FileState::Modified,
+            _ => FileState::Unknown,
+        };
+
+        let mode_changes = mode::changes(&dotpath.abs_path, &entry.deployed_modes());
+        let mut record = StatusRecord::new(&dotpath, entry.status, state, is_staged);
+        record.files = files;
+        record.modes = ModeRecord::from_changes(mode_changes);
+        // Content in place, only the modes drifted
+        if !record.modes.is_empty()
+            && matches!(
+                state,
+                FileState::Tracked | FileState::Linked | FileState::Deployed
+            )
+        {
+            record.state = FileState::ModeChanged;
+        }
+        dotfiles.push(record);
+    }
+
+    let mut staged: Vec<(DotPath, DotfileEntry)> = staged.into_iter().collect();
+    staged.sort_by_key(|(dotpath, _)| dotpath.to_name().clone());
+
+    let mut staged_records = Vec::new();
+    for (dotpath, entry) in staged {
+        let state = if entry.encrypted && dotpath.abs_target_staged.exists() {
+            match decrypt_dotfile(&dotpath.abs_target_staged) {
+                Ok(staged) if fs::read(&dotpath.abs_path).is_ok_and(|local| local == staged) => {
+                    FileState::InStaging
+                }
+                Ok(_) => FileState::DiffDetected,
+                Err(err) => {
+                    log::warn!("{:#}", err);
+                    FileState::DecryptFailed
                 }
+            }
+        } else if entry.deploy == DeployMode::Unfold && dotpath.abs_target_staged.is_dir() {
+            // Only the tracked files were staged, not the whole directory
+            let staged_files =
+                path_compare::relative_files(&dotpath.abs_target_staged, &IgnoreRules::default())
+                    .map_err(|err| {
+                    anyhow!(
+                        "Error listing files: {}. Please check the path {}",
+                        err,
+                        dotpath.abs_target_staged.display()
+                    )
+                })?;
+
+            let all_equal = staged_files.iter().all(|file| {
+                path_compare::compare_file(
+                    &dotpath.abs_path.join(file),
+                    &dotpath.abs_target_staged.join(file),
+                )
+                .unwrap_or(false)
+            });
+            if all_equal {
+                FileState::InStaging
             } else {
-                "[Pending]".yellow()
-            };
+                FileState::DiffDetected
+            }
+        } else if dotpath.target_staged.exists() {
+            let ignore = config.entry_rules(&entry)?;
+            let compare_result =
+                path_compare::compare_paths(&dotpath.abs_path, &dotpath.abs_target_staged, &ignore)
+                    .map_err(|err| {
+                        anyhow!(
+                            "Error comparing files: {}. Please check the paths {}",
+                            err,
+                            dotpath.abs_path.display()
+                        )
+                    })?;
 
-            println!(
-                "{} {} ({})",
-                "[Staged]".blue(),
-                stage_status,
-                dotpath.to_name().display()
-            );
+            match compare_result {
+                CompareResult::Equal => FileState::InStaging,
+                CompareResult::NotEqual => FileState::DiffDetected,
+                CompareResult::Linked => FileState::Linked,
+            }
+        } else {
+            FileState::Pending
+        };
+
+        staged_records.push(StatusRecord::new(&dotpath, entry.status, state, true));
+    }
+
+    Ok(StatusReport {
+        profile: config.active_profile().map(str::to_string),
+        upstream: git::upstream(config.repo_path()),
+        dotfiles,
+        staged: staged_records,
+    })
+}
+
+/// The state for a detected change, `unchanged` when the hashes say nothing changed
+fn change_state(change_result: ChangeResult, unchanged: FileState) -> FileState {
+    match change_result {
+        ChangeResult::Unchanged => unchanged,
+        ChangeResult::LocalChanged => FileState::LocalChanged,
+        ChangeResult::RepoChanged => FileState::RepoChanged,
+        ChangeResult::BothChanged => FileState::BothChanged,
+    }
+}
+
+/// Compare each file of an unfolded directory with its repository copy; the directory
+/// as a whole takes the state of its most drifted file
+fn unfolded_status(
+    config: &Config,
+    dotpath: &DotPath,
+    entry: &DotfileEntry,
+) -> Result<(FileState, Vec<FileRecord>)> {
+    if !dotpath.abs_target.is_dir() {
+        return Ok((FileState::DiffDetected, Vec::new()));
+    }
+
+    let mut records = Vec::new();
+    for file in config.unfolded_files(dotpath, entry)? {
+        let local = dotpath.abs_path.join(&file);
+        let repo_file = dotpath.abs_target.join(&file);
+
+        let state = if !local.exists() {
+            FileState::NotDeployed
+        } else if path_compare::linked_paths(&local, &repo_file).map_err(|err| {
+            anyhow!(
+                "Error comparing files: {}. Please check the path {}",
+                err,
+                local.display()
+            )
+        })? {
+            FileState::Linked
+        } else if path_compare::compare_file(&local, &repo_file).unwrap_or(false) {
+            FileState::Tracked
+        } else {
+            FileState::DiffDetected
+        };
+
+        records.push(FileRecord {
+            path: file.display().to_string(),
+            state,
+        });
+    }
+
+    let state = [
+        FileState::DiffDetected,
+        FileState::NotDeployed,
+        FileState::Tracked,
+    ]
+    .into_iter()
+    .find(|state| records.iter().any(|record| record.state == *state))
+    .unwrap_or(FileState::Linked);
+
+    Ok((state, records))
+}
+
+/// Compare the local file of a template dotfile against its freshly rendered output
+fn template_status(config: &Config, dotpath: &DotPath, entry: &DotfileEntry) -> Result<FileState> {
+    let rendered = match render_dotfile(config.repo_path(), dotpath) {
+        Ok(rendered) => rendered,
+        Err(err) => {
+            log::warn!("{:#}", err);
+            return Ok(FileState::RenderFailed);
+        }
+    };
+
+    let rendered_path = rendered_path(config.repo_path(), dotpath);
+    let linked = match entry.deploy {
+        DeployMode::Symlink | DeployMode::Unfold => {
+            path_compare::linked_paths(&dotpath.abs_path, &rendered_path).map_err(|err| {
+                anyhow!(
+                    "Error comparing files: {}. Please check the paths {}",
+                    err,
+                    dotpath.abs_path.display()
+                )
+            })?
+        }
+        DeployMode::Hardlink => path_compare::hardlinked_paths(&dotpath.abs_path, &rendered_path),
+        DeployMode::Copy => false,
+    };
+
+    let local = fs::read(&dotpath.abs_path).ok();
+    let status = match (linked, local) {
+        (true, Some(local)) if local == rendered.as_bytes() => FileState::Linked,
+        (true, _) => FileState::RenderOutdated,
+        (false, Some(local)) if local == rendered.as_bytes() => match entry.deploy {
+            DeployMode::Copy => FileState::Deployed,
+            _ => FileState::Tracked,
+        },
+        (false, _) => FileState::DiffDetected,
+    };
+
+    Ok(status)
+}
+
+/// Compare the local copy of an encrypted dotfile against the repository plaintext,
+/// decrypted in memory only
+fn secret_status(dotpath: &DotPath, entry: &DotfileEntry) -> FileState {
+    let plaintext = match decrypt_dotfile(&dotpath.abs_target) {
+        Ok(plaintext) => plaintext,
+        Err(err) => {
+            log::warn!("{:#}", err);
+            return FileState::DecryptFailed;
+        }
+    };
+
+    let Ok(local) = fs::read(&dotpath.abs_path) else {
+        return FileState::NotDeployed;
+    };
+
+    if local == plaintext {
+        return FileState::Deployed;
+    }
+
+    let Some(baseline) = entry.hash.as_deref() else {
+        return FileState::DiffDetected;
+    };
+
+    let name = dotpath.to_name();
+    match (secret_digest(name, &local), secret_digest(name, &plaintext)) {
+        (Ok(local), Ok(repo)) => change_state(
+            path_compare::change_from_hashes(&local, &repo, baseline),
+            FileState::Deployed,
+        ),
+        (Err(err), _) | (_, Err(err)) => {
+            log::warn!("{:#}", err);
+            FileState::DiffDetected
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::testing;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_status_reports_mode_change() {
+        let root = Path::new("/tmp/test_dotzilla_status_modes");
+        let config = testing::repo(root);
+        let dotpath =
+            testing::tracked_file(&config, "app.conf", "key = 1", 0o640, Default::default());
+        let config = testing::reload(&config);
+
+        let report = status_report(&config).unwrap();
+        assert_eq!(report.dotfiles[0].state, FileState::Tracked);
+        assert!(report.dotfiles[0].modes.is_empty());
+
+        fs::set_permissions(&dotpath.abs_path, fs::Permissions::from_mode(0o644)).unwrap();
+        let report = status_report(&config).unwrap();
+        let record = &report.dotfiles[0];
+        assert_eq!(record.state, FileState::ModeChanged);
+        assert!(record.state.is_drift());
+        assert_eq!(record.modes.len(), 1);
+        assert_eq!(record.modes[0].path, ".");
+        assert_eq!(record.modes[0].expected.mode, 0o640);
+        assert_eq!(record.modes[0].actual.mode, 0o644);
+
+        // A content change is reported as such, the modes come along
+        fs::write(&dotpath.abs_path, "key = 2").unwrap();
+        let report = status_report(&config).unwrap();
+        assert_ne!(report.dotfiles[0].state, FileState::ModeChanged);
+        assert_eq!(report.dotfiles[0].modes.len(), 1);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_status_porcelain_lines() {
+        let root = Path::new("/tmp/test_dotzilla_status_porcelain");
+        let config = testing::repo(root);
+        let dotpath =
+            testing::tracked_file(&config, "app.conf", "key = 1", 0o640, Default::default());
+        let config = testing::reload(&config);
+        let name = dotpath.to_name().display().to_string();
+
+        let report = status_report(&config).unwrap();
+        assert_eq!(
+            porcelain_lines(&report),
+            vec![format!("tracked tracked {}", name)]
+        );
 
-    Ok(())
+        fs::set_permissions(&dotpath.abs_path, fs::Permissions::from_mode(0o600)).unwrap();
+        let report = status_report(&config).unwrap();
+        assert_eq!(
+            porcelain_lines(&report),
+            vec![
+                format!("tracked mode-changed {}", name),
+                format!("mode 0640 0600 {}", name),
+            ]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
 }
@@ -0,0 +1,172 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, anyhow};
+use colored::*;
+
+use super::link::{ConflictPolicy, LinkOutcome, plan_link};
+use super::stage::apply_stage;
+use crate::models::{Activation, Config, DotPath, DotfileEntry, GitMode, secret_digest};
+use crate::utils::git;
+use crate::utils::hash::hash_path;
+
+/// Pull the git repository holding the dotfiles and re-link what changed upstream
+///
+/// Copies that weren't edited locally are simply replaced; anything else in the way
+/// is handled by `on_conflict`, as with `link`. Local commits are pushed afterwards
+/// when the repository is set to push.
+pub fn sync_repo(config: &mut Config, on_conflict: ConflictPolicy) -> Result<()> {
+    let repo_path = config.repo_path().to_path_buf();
+    if !git::is_repo(&repo_path) {
+        return Err(anyhow!(
+            "{} is not a git repository, sync needs one with an upstream branch",
+            repo_path.display()
+        ));
+    }
+
+    let before = git::head(&repo_path)?;
+    git::pull(&repo_path)?;
+    let after = git::head(&repo_path)?;
+
+    match after.filter(|after| before.as_ref() != Some(after)) {
+        Some(after) => {
+            let changed = git::changed_paths(&repo_path, before.as_deref(), &after)?;
+
+            // The pull may have changed the config too
+            let previous = config.clone();
+            let mut next = Config::load(&repo_path)?;
+            next.select_profile(previous.active_profile())?;
+            *config = next;
+
+            relink_changed(config, &previous, &changed, on_conflict)?;
+        }
+        None => println!("{} Already up to date", "✓".green()),
+    }
+
+    if config.git_mode() == GitMode::Push
+        && let Some(upstream) = git::upstream(&repo_path)
+        && upstream.ahead > 0
+    {
+        git::push(&repo_path).context("Failed to push local git commits")?;
+        println!(
+            "{} Pushed {} local commit(s) to {}",
+            "✓".green(),
+            upstream.ahead,
+            upstream.name
+        );
+    }
+
+    Ok(())
+}
+
+/// Re-link every active dotfile whose repository copy or config entry changed
+fn relink_changed(
+    config: &mut Config,
+    previous: &Config,
+    changed: &[PathBuf],
+    on_conflict: ConflictPolicy,
+) -> Result<()> {
+    let repo_path = config.repo_path().to_path_buf();
+
+    let mut updated: Vec<(DotPath, DotfileEntry)> = config
+        .get_active()
+        .into_iter()
+        .filter(|(dotpath, entry)| {
+            let repo_copy = dotpath.abs_target.strip_prefix(&repo_path).ok();
+            previous.get_dotfile(dotpath).ok() != Some(entry)
+                || repo_copy
+                    .is_some_and(|repo_copy| changed.iter().any(|path| path.starts_with(repo_copy)))
+        })
+        .collect();
+    updated.sort_by_key(|(dotpath, _)| dotpath.to_name().clone());
+
+    if updated.is_empty() {
+        println!("{} No tracked dotfiles changed upstream", "✓".green());
+        return Ok(());
+    }
+
+    println!("{}", "Changed upstream:".bold());
+    for (dotpath, _) in &updated {
+        println!("  {}", dotpath.to_name().display());
+    }
+
+    let mut error_count = 0;
+    for (dotpath, entry) in &updated {
+        if let Activation::Inactive(reason) = entry.activation() {
+            println!(
+                "{} Skipped {}: condition not met: {}",
+                "-".dimmed(),
+                dotpath.to_name().display(),
+                reason
+            );
+            continue;
+        }
+
+        let policy = if unedited_copy(previous, dotpath, entry) {
+            ConflictPolicy::Overwrite
+        } else {
+            on_conflict
+        };
+
+        if let Err(e) = relink(config, dotpath, entry, policy) {
+            println!(
+                "{} Failed to link {}: {:#}",
+                "✗".red(),
+                dotpath.to_name().display(),
+                e
+            );
+            error_count += 1;
+        }
+    }
+
+    if error_count > 0 {
+        return Err(anyhow!(
+            "{} dotfile(s) failed to link, fix them and run 'dotzilla link'",
+            error_count
+        ));
+    }
+    Ok(())
+}
+
+fn relink(
+    config: &mut Config,
+    dotpath: &DotPath,
+    entry: &DotfileEntry,
+    on_conflict: ConflictPolicy,
+) -> Result<()> {
+    let (plan, outcome) = plan_link(
+        config,
+        dotpath,
+        entry,
+        config.relative_links(),
+        on_conflict,
+        false,
+    )?;
+
+    match outcome {
+        LinkOutcome::Adopted(staged_entry) => apply_stage(config, dotpath, &plan, staged_entry),
+        _ => plan.execute(),
+    }
+}
+
+/// Whether the local path is a deployed copy still matching the previous repository copy,
+/// so replacing it with the pulled version loses nothing
+fn unedited_copy(previous: &Config, dotpath: &DotPath, entry: &DotfileEntry) -> bool {
+    let Ok(old_entry) = previous.get_dotfile(dotpath) else {
+        return false;
+    };
+
+    !entry.deploy.uses_symlinks()
+        && !entry.template
+        && !dotpath.abs_path.is_symlink()
+        && dotpath.abs_path.exists()
+        && old_entry.hash.as_deref().is_some_and(|hash| {
+            if old_entry.encrypted {
+                return std::fs::read(&dotpath.abs_path).is_ok_and(|local| {
+                    secret_digest(dotpath.to_name(), &local).is_ok_and(|local| local == hash)
+                });
+            }
+            previous.entry_rules(old_entry).is_ok_and(|ignore| {
+                hash_path(&dotpath.abs_path, &ignore).is_ok_and(|local| local == hash)
+            })
+        })
+}
@@ -0,0 +1,73 @@
+//! Repositories for the tests of the commands
+
+use std::fs;
+use std::path::Path;
+
+use super::init_repo;
+use crate::models::{Config, ConfigFormat, DotPath};
+
+/// A fresh repository at `<root>/repo`, next to `<root>/local` for the local files
+///
+/// Local files live outside the home directory, so the tests don't depend on `HOME`.
+pub fn repo(root: &Path) -> Config {
+    let _ = fs::remove_dir_all(root);
+    fs::create_dir_all(root.join("local")).unwrap();
+    init_repo(root.join("repo"), ConfigFormat::Json).unwrap();
+    Config::load(&root.join("repo")).unwrap()
+}
+
+/// The config as the next command loads it
+pub fn reload(config: &Config) -> Config {
+    Config::load(config.repo_path()).unwrap()
+}
+
+/// The dotfile at `<root>/local/<path>`
+pub fn local(config: &Config, path: &str) -> DotPath {
+    let root = config.repo_path().parent().unwrap();
+    DotPath::new(config, root.join("local").join(path).to_str().unwrap())
+}
+
+/// Track `<root>/local/<name>` holding `content` with the permission bits `mode`
+pub fn tracked_file(
+    config: &Config,
+    name: &str,
+    content: &str,
+    mode: u32,
+    options: super::AddOptions,
+) -> DotPath {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dotpath = local(config, name);
+    fs::write(&dotpath.abs_path, content).unwrap();
+    fs::set_permissions(&dotpath.abs_path, fs::Permissions::from_mode(mode)).unwrap();
+    super::add_dotfile(config.clone(), dotpath.clone(), options, false).unwrap();
+    dotpath
+}
+
+/// The permission bits of `path`, following symlinks
+pub fn mode(path: &Path) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path).unwrap().permissions().mode() & 0o7777
+}
+
+/// Track `<root>/local/fish` as an unfolded directory holding `config.fish` and
+/// `functions/ll.fish`, with `fish_history` ignored next to them
+pub fn unfolded_fish(config: &Config) -> DotPath {
+    use super::{AddOptions, add_dotfile};
+    use crate::models::DeployMode;
+
+    let dotpath = local(config, "fish");
+    fs::create_dir_all(dotpath.abs_path.join("functions")).unwrap();
+    fs::write(dotpath.abs_path.join("config.fish"), "set -x EDITOR vim").unwrap();
+    fs::write(dotpath.abs_path.join("functions/ll.fish"), "ls -l").unwrap();
+    fs::write(dotpath.abs_path.join("fish_history"), "- cmd: ls").unwrap();
+
+    let options = AddOptions {
+        deploy: Some(DeployMode::Unfold),
+        ignore: vec!["fish_history".to_string()],
+        ..Default::default()
+    };
+    add_dotfile(config.clone(), dotpath.clone(), options, false).unwrap();
+    dotpath
+}
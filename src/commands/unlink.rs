@@ -1,13 +1,13 @@
-use std::io;
-use std::{fs, path::Path};
-
-use anyhow::{Context, Result};
+use anyhow::{Result, anyhow};
 use colored::*;
 
-use crate::models::Config;
+use crate::models::{Config, DeployMode, Operation, Plan, rendered_path};
 use crate::utils::filter::filter_dotfiles_contains;
+use crate::utils::ignore::IgnoreRules;
+use crate::utils::mode;
+use crate::utils::path_compare;
 
-pub fn unlink_dotfiles(config: &Config, name: Option<String>) -> Result<()> {
+pub fn unlink_dotfiles(config: &Config, name: Option<String>, dry_run: bool) -> Result<()> {
     if config.get().is_empty() {
         println!("No dotfiles unlinking...");
         return Ok(());
@@ -15,102 +15,166 @@ pub fn unlink_dotfiles(config: &Config, name: Option<String>) -> Result<()> {
 
     let mut success_count = 0;
     let mut error_count = 0;
+    let mut planned = Plan::new();
 
     let dotfiles = config.get();
     let filtered_dotfiles = filter_dotfiles_contains(dotfiles.iter(), name.as_deref());
 
-    for (dotfile_path, _) in filtered_dotfiles {
-        let source = &dotfile_path.abs_target;
+    for (dotfile_path, entry) in filtered_dotfiles {
+        let source = if entry.template {
+            rendered_path(config.repo_path(), dotfile_path)
+        } else {
+            dotfile_path.abs_target.clone()
+        };
+        let source = &source;
         let target_path = &dotfile_path.abs_path;
 
-        if target_path.exists() {
-            if !target_path.is_symlink() {
+        let mut plan = Plan::new();
+        let mut copied = false;
+        match entry.deploy {
+            DeployMode::Copy => {
                 println!(
-                    "{} Target path exists but is not a symlink: {}",
-                    "✗".red(),
-                    target_path.display()
+                    "{} {} is deployed as a copy, leaving it in place",
+                    "!".yellow(),
+                    dotfile_path.to_name().display()
                 );
-                error_count += 1;
                 continue;
             }
-
-            let target_link = fs::read_link(target_path)
-                .with_context(|| format!("Failed to read symlink at {}", target_path.display()))?;
-
-            let is_link_to_repo = target_link == *source;
-
-            fs::remove_file(target_path).with_context(|| {
-                format!(
-                    "Failed to remove existing symlink at {}",
-                    target_path.display()
-                )
-            })?;
-
-            println!(
-                "{} Removed symlink at {}",
-                "✓".green(),
-                target_path.display()
-            );
-
-            if is_link_to_repo && source.is_dir() {
-                copy_dir_all(source, target_path).with_context(|| {
-                    format!(
-                        "Failed to copy directory from {} to {}",
-                        source.display(),
-                        target_path.display()
-                    )
-                })?;
-
+            DeployMode::Hardlink if path_compare::hardlinked_paths(source, target_path) => {
+                // Replace the hard link with an independent copy of the same content
+                plan.push(Operation::Delete(target_path.clone()));
+                plan.push(Operation::Copy {
+                    from: source.clone(),
+                    to: target_path.clone(),
+                    ignore: IgnoreRules::default(),
+                });
+                copied = true;
+            }
+            DeployMode::Hardlink => {
                 println!(
-                    "{} Copied directory from {} to {}",
-                    "✓".green(),
-                    source.display(),
+                    "{} No hard link found at {}",
+                    "!".yellow(),
                     target_path.display()
                 );
-            } else if is_link_to_repo && source.is_file() {
-                fs::copy(source, target_path).with_context(|| {
-                    format!(
-                        "Failed to copy file from {} to {}",
-                        source.display(),
+                continue;
+            }
+            DeployMode::Unfold if target_path.is_dir() && !target_path.is_symlink() => {
+                // Replace each file link with an independent copy, the directory stays
+                for file in config.unfolded_files(dotfile_path, entry)? {
+                    let local = target_path.join(&file);
+                    let repo_file = source.join(&file);
+                    let is_link_to_repo = local.is_symlink()
+                        && path_compare::linked_paths(&local, &repo_file).map_err(|err| {
+                            anyhow!("Failed to read symlink at {}: {}", local.display(), err)
+                        })?;
+
+                    if is_link_to_repo {
+                        plan.push(Operation::Delete(local.clone()));
+                        plan.push(Operation::Copy {
+                            from: repo_file,
+                            to: local,
+                            ignore: IgnoreRules::default(),
+                        });
+                        copied = true;
+                    }
+                }
+
+                if plan.is_empty() {
+                    println!(
+                        "{} No file links found in {}",
+                        "!".yellow(),
                         target_path.display()
-                    )
-                })?;
-
+                    );
+                    continue;
+                }
+            }
+            DeployMode::Symlink | DeployMode::Unfold if target_path.exists() => {
+                if !target_path.is_symlink() {
+                    println!(
+                        "{} Target path exists but is not a symlink: {}",
+                        "✗".red(),
+                        target_path.display()
+                    );
+                    error_count += 1;
+                    continue;
+                }
+
+                let is_link_to_repo =
+                    path_compare::linked_paths(target_path, source).map_err(|err| {
+                        anyhow!(
+                            "Failed to read symlink at {}: {}",
+                            target_path.display(),
+                            err
+                        )
+                    })?;
+
+                plan.push(Operation::Delete(target_path.clone()));
+                if is_link_to_repo && (source.is_dir() || source.is_file()) {
+                    plan.push(Operation::Copy {
+                        from: source.clone(),
+                        to: target_path.clone(),
+                        ignore: config.entry_rules(entry)?,
+                    });
+                    copied = true;
+                }
+            }
+            DeployMode::Symlink | DeployMode::Unfold if target_path.is_symlink() => {
+                // A broken symlink, `exists` follows it
+                plan.push(Operation::Delete(target_path.clone()));
+            }
+            DeployMode::Symlink | DeployMode::Unfold => {
                 println!(
-                    "{} Copied file from {} to {}",
-                    "✓".green(),
-                    source.display(),
+                    "{} No symlink found at {}",
+                    "!".yellow(),
                     target_path.display()
                 );
+                continue;
             }
+        }
+
+        // The copies left behind are the user's own files now; owners can only be
+        // restored through the escalation command
+        let escalation = config.escalation_for(entry);
+        if copied && !entry.modes.is_empty() {
+            plan.push(Operation::SetModes {
+                path: target_path.clone(),
+                modes: match escalation {
+                    Some(_) => entry.modes.clone(),
+                    None => mode::without_owners(&entry.modes),
+                },
+            });
+        }
 
+        if let Some(command) = escalation {
+            plan.escalate(config.repo_path(), command);
+        }
+
+        if dry_run {
+            planned.extend(plan);
             success_count += 1;
-        } else if let Ok(metadata) = fs::symlink_metadata(target_path) {
-            if metadata.file_type().is_symlink() && fs::metadata(target_path).is_err() {
-                fs::remove_file(target_path).with_context(|| {
-                    format!(
-                        "Failed to remove broken symlink at {}",
-                        target_path.display()
-                    )
-                })?;
+            continue;
+        }
 
+        match plan.execute() {
+            Ok(_) => success_count += 1,
+            Err(e) => {
                 println!(
-                    "{} Removed broken symlink at {}",
-                    "✓".green(),
-                    target_path.display()
+                    "{} Failed to unlink {}: {:#}",
+                    "✗".red(),
+                    dotfile_path.to_name().display(),
+                    e
                 );
-
-                success_count += 1;
+                error_count += 1;
             }
-        } else {
-            println!(
-                "{} No symlink found at {}",
-                "!".yellow(),
-                target_path.display()
-            );
         }
     }
 
+    if dry_run {
+        planned.print();
+        println!("Would unlink {}, {} failed", success_count, error_count);
+        return Ok(());
+    }
+
     println!(
         "{} unlinked successfully, {} failed",
         success_count, error_count
@@ -118,19 +182,71 @@ pub fn unlink_dotfiles(config: &Config, name: Option<String>) -> Result<()> {
     Ok(())
 }
 
-fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
-    fs::create_dir_all(&dst)?;
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let ty = entry.file_type()?;
-        let src_path = entry.path();
-        let dst_path = dst.as_ref().join(entry.file_name());
-
-        if ty.is_dir() {
-            copy_dir_all(src_path, dst_path)?;
-        } else if ty.is_file() {
-            fs::copy(src_path, dst_path)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{ConflictPolicy, link_dotfiles, testing};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_unfold_unlink_leaves_copies() {
+        let root = Path::new("/tmp/test_dotzilla_unlink_unfold");
+        let config = testing::repo(root);
+        let dotpath = testing::unfolded_fish(&config);
+        let mut config = testing::reload(&config);
+
+        let name = Some(dotpath.to_name().display().to_string());
+        link_dotfiles(&mut config, name, None, ConflictPolicy::Overwrite, false).unwrap();
+        assert!(dotpath.abs_path.join("config.fish").is_symlink());
+
+        unlink_dotfiles(&config, None, false).unwrap();
+        for file in ["config.fish", "functions/ll.fish"] {
+            let local = dotpath.abs_path.join(file);
+            assert!(!local.is_symlink());
+            assert_eq!(
+                fs::read_to_string(&local).unwrap(),
+                fs::read_to_string(dotpath.abs_target.join(file)).unwrap()
+            );
         }
+        assert_eq!(
+            fs::read_to_string(dotpath.abs_path.join("fish_history")).unwrap(),
+            "- cmd: ls"
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_unlink_leaves_recorded_owner() {
+        use std::os::unix::fs::MetadataExt;
+
+        let root = Path::new("/tmp/test_dotzilla_unlink_owner");
+        let config = testing::repo(root);
+        let dotpath = testing::local(&config, "app.conf");
+        fs::write(&dotpath.abs_path, "key = 1").unwrap();
+        // Only root can hand a file to another user
+        if std::os::unix::fs::chown(&dotpath.abs_path, Some(65534), Some(65534)).is_err() {
+            fs::remove_dir_all(root).unwrap();
+            return;
+        }
+        crate::commands::add_dotfile(config.clone(), dotpath.clone(), Default::default(), false)
+            .unwrap();
+        let mut config = testing::reload(&config);
+        let entry = config.get_dotfile(&dotpath).unwrap();
+        assert!(entry.modes[std::path::Path::new(".")].owner.is_some());
+
+        fs::remove_file(&dotpath.abs_path).unwrap();
+        let name = Some(dotpath.to_name().display().to_string());
+        link_dotfiles(&mut config, name.clone(), None, ConflictPolicy::Skip, false).unwrap();
+        unlink_dotfiles(&config, name, false).unwrap();
+
+        // The copy belongs to whoever unlinked it, the recorded mode still applies
+        let metadata = fs::symlink_metadata(&dotpath.abs_path).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.uid(), unsafe { libc::getuid() });
+        assert_eq!(metadata.mode() & 0o7777, 0o644);
+
+        fs::remove_dir_all(root).unwrap();
     }
-    Ok(())
 }
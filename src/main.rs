@@ -4,11 +4,15 @@ mod models;
 mod tui;
 mod utils;
 
-use anyhow::Result;
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
 use clap::{CommandFactory, Parser};
-use commands::{Cli, Commands};
+use commands::{
+    AddOptions, BackupCommands, Cli, Commands, ConfigCommands, OutputFormat, ProfileCommands,
+};
 use generator::print_completions;
-use models::{Config, DotPath};
+use models::{Config, DotPath, RepoLock};
 use utils::expand_tilde;
 
 fn main() -> Result<()> {
@@ -16,56 +20,205 @@ fn main() -> Result<()> {
     let cli = Cli::parse();
 
     let repo_path = expand_tilde(&cli.repo);
+    let profile = cli.profile;
+    let dry_run = cli.dry_run;
+
+    if let Some(command) = &cli.command
+        && dry_run
+        && (command.is_mutating() || matches!(command, Commands::Clone { .. }))
+        && !command.supports_dry_run()
+    {
+        return Err(anyhow!("--dry-run is not supported by this command"));
+    }
+
+    // Hold the repository lock for the whole command so concurrent runs can't lose updates
+    let _lock = match &cli.command {
+        Some(command) if command.is_mutating() && !dry_run => {
+            let lock = RepoLock::acquire(&repo_path)?;
+            commands::recover_commit(&repo_path)?;
+            Some(lock)
+        }
+        _ => None,
+    };
 
     match cli.command {
-        Some(Commands::Init { path }) => {
+        Some(Commands::Init { path, format }) => {
             let init_path = expand_tilde(&path);
-            commands::init_repo(init_path)
+            commands::init_repo(init_path, format)
         }
-        Some(Commands::Add { path }) => {
-            let config = Config::load(&repo_path)?;
+        Some(Commands::Clone {
+            source,
+            path,
+            on_conflict,
+            yes,
+        }) => commands::clone_repo(
+            &source,
+            expand_tilde(&path),
+            profile.as_deref(),
+            on_conflict,
+            yes,
+        ),
+        Some(Commands::Add {
+            path,
+            when,
+            template,
+            encrypt,
+            deploy,
+            ignore,
+            symlinks,
+        }) => {
+            let config = load_config(&repo_path, profile.as_deref())?;
             let dot_path = DotPath::new(&config, &path);
-            commands::add_dotfile(config, dot_path)
+            commands::add_dotfile(
+                config,
+                dot_path,
+                AddOptions {
+                    when,
+                    template,
+                    encrypt,
+                    deploy,
+                    ignore,
+                    symlinks,
+                },
+                dry_run,
+            )
         }
         Some(Commands::Remove { name, keep }) => {
-            let config = Config::load(&repo_path)?;
+            let config = load_config(&repo_path, profile.as_deref())?;
             let dot_path = DotPath::new(&config, &name);
-            commands::remove_dotfile(config, dot_path, keep)
+            commands::remove_dotfile(config, dot_path, keep, dry_run)
         }
         Some(Commands::Stage { name }) => {
-            let mut config = Config::load(&repo_path)?;
+            let mut config = load_config(&repo_path, profile.as_deref())?;
             let dot_path = DotPath::new(&config, &name);
-            commands::stage_dotfile(&mut config, &dot_path)
+            commands::stage_dotfile(&mut config, &dot_path, dry_run)
         }
         Some(Commands::Unstage { name }) => {
-            let mut config = Config::load(&repo_path)?;
+            let mut config = load_config(&repo_path, profile.as_deref())?;
             let dot_path = DotPath::new(&config, &name);
             commands::unstage_dotfile(&mut config, &dot_path)
         }
-        Some(Commands::Commit) => {
-            let mut config = Config::load(&repo_path)?;
-            commands::commit_dotfiles(&mut config)
+        Some(Commands::Commit { message }) => {
+            let mut config = load_config(&repo_path, profile.as_deref())?;
+            commands::commit_dotfiles(&mut config, message, dry_run)
+        }
+        Some(Commands::Log { limit }) => {
+            let config = load_config(&repo_path, profile.as_deref())?;
+            commands::show_log(&config, limit)
+        }
+        Some(Commands::Restore { name, to }) => {
+            let mut config = load_config(&repo_path, profile.as_deref())?;
+            commands::restore_dotfiles(&mut config, name, &to)
         }
-        Some(Commands::Link { name }) => {
-            let config = Config::load(&repo_path)?;
-            commands::link_dotfiles(&config, name)
+        Some(Commands::Render { name }) => {
+            let config = load_config(&repo_path, profile.as_deref())?;
+            commands::render_templates(&config, name)
+        }
+        Some(Commands::Link {
+            name,
+            relative,
+            absolute,
+            on_conflict,
+        }) => {
+            let mut config = load_config(&repo_path, profile.as_deref())?;
+            let relative = match (relative, absolute) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            };
+            commands::link_dotfiles(&mut config, name, relative, on_conflict, dry_run)
+        }
+        Some(Commands::Sync { on_conflict }) => {
+            let mut config = load_config(&repo_path, profile.as_deref())?;
+            commands::sync_repo(&mut config, on_conflict)
         }
         Some(Commands::Unlink { name }) => {
-            let config = Config::load(&repo_path)?;
-            commands::unlink_dotfiles(&config, name)
+            let config = load_config(&repo_path, profile.as_deref())?;
+            commands::unlink_dotfiles(&config, name, dry_run)
         }
-        Some(Commands::Status) => {
-            let config = Config::load(&repo_path)?;
-            commands::show_status(&config)
+        Some(Commands::Status { output }) => {
+            let config = load_config(&repo_path, profile.as_deref())?;
+            let drift = commands::show_status(&config, output.format())?;
+            exit_on_drift(output.format(), drift);
+            Ok(())
         }
-        Some(Commands::List) => {
-            let config = Config::load(&repo_path)?;
-            commands::list_dotfiles(&config)
+        Some(Commands::List { output }) => {
+            let config = load_config(&repo_path, profile.as_deref())?;
+            commands::list_dotfiles(&config, output.format())
         }
-        Some(Commands::Diff { name, tool, word }) => {
-            let config = Config::load(&repo_path)?;
+        Some(Commands::Diff {
+            name,
+            tool,
+            word,
+            staged,
+            output,
+        }) => {
+            let config = load_config(&repo_path, profile.as_deref())?;
             let dot_path = DotPath::new(&config, &name);
-            commands::show_diff(dot_path, tool, word)
+            if !output.format().is_machine_readable() {
+                return commands::show_diff(&config, dot_path, tool, word, staged);
+            }
+            if tool.is_some() {
+                return Err(anyhow!(
+                    "--tool can't be combined with --format json or --porcelain"
+                ));
+            }
+
+            let drift = commands::report_diff(&config, dot_path, staged, output.format())?;
+            exit_on_drift(output.format(), drift);
+            Ok(())
+        }
+        Some(Commands::Doctor { fix }) => {
+            let mut config = load_config(&repo_path, profile.as_deref())?;
+            commands::run_doctor(&mut config, fix)
+        }
+        Some(Commands::Migrate { check }) => commands::migrate_config(&repo_path, check),
+        Some(Commands::Config {
+            action: ConfigCommands::Convert { to },
+        }) => {
+            let mut config = load_config(&repo_path, profile.as_deref())?;
+            commands::convert_config(&mut config, to)
+        }
+        Some(Commands::Config {
+            action: ConfigCommands::RelativeLinks { enabled },
+        }) => {
+            let mut config = load_config(&repo_path, profile.as_deref())?;
+            commands::set_relative_links(&mut config, enabled)
+        }
+        Some(Commands::Config {
+            action: ConfigCommands::Git { mode },
+        }) => {
+            let mut config = load_config(&repo_path, profile.as_deref())?;
+            commands::set_git_mode(&mut config, mode)
+        }
+        Some(Commands::Config {
+            action: ConfigCommands::Escalation { command },
+        }) => {
+            let mut config = load_config(&repo_path, profile.as_deref())?;
+            commands::set_escalation_command(&mut config, command)
+        }
+        Some(Commands::Profile { action }) => {
+            let mut config = load_config(&repo_path, profile.as_deref())?;
+            match action {
+                ProfileCommands::List => commands::list_profiles(&config),
+                ProfileCommands::Set {
+                    name,
+                    hosts,
+                    include,
+                    exclude,
+                } => commands::set_profile(&mut config, &name, hosts, include, exclude),
+                ProfileCommands::Remove { name } => commands::remove_profile(&mut config, &name),
+            }
+        }
+        Some(Commands::Backups { action }) => {
+            let config = load_config(&repo_path, profile.as_deref())?;
+            match action {
+                BackupCommands::List => commands::list_backups(&config),
+                BackupCommands::Restore { id } => commands::restore_backup(&config, &id),
+                BackupCommands::Prune { keep, older_than } => {
+                    commands::prune_backups(&config, keep, older_than)
+                }
+            }
         }
         Some(Commands::Completion { shell }) => {
             let mut cmd = Cli::command();
@@ -82,3 +235,17 @@ fn main() -> Result<()> {
         }
     }
 }
+
+/// Exit with [`commands::DRIFT_EXIT_CODE`] when a machine-readable report found drift
+fn exit_on_drift(format: OutputFormat, drift: bool) {
+    if let Some(code) = format.exit_code(drift) {
+        std::process::exit(code);
+    }
+}
+
+/// Load the repository config and select the active profile
+fn load_config(repo_path: &Path, profile: Option<&str>) -> Result<Config> {
+    let mut config = Config::load(repo_path)?;
+    config.select_profile(profile)?;
+    Ok(config)
+}
@@ -0,0 +1,337 @@
+use std::fs;
+use std::os::unix::fs::{self as unix_fs, DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::plan::copy_dir;
+use super::{DotPath, secret_key};
+use crate::utils::atomic::{write_atomic, write_atomic_private};
+use crate::utils::escalate;
+use crate::utils::ignore::IgnoreRules;
+
+/// What kind of file system entry a backup holds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// A local file or directory moved aside instead of being overwritten
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupRecord {
+    pub id: String,
+    pub timestamp: DateTime<Local>,
+    /// Name of the dotfile the backup was taken for, e.g. `~/.bashrc`
+    pub name: PathBuf,
+    /// Absolute path the backup was moved from
+    pub original: PathBuf,
+    pub kind: BackupKind,
+    /// The backup holds ciphertext, taken for an encrypted dotfile
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted: bool,
+    /// The original path is only writable by root, it was moved through the
+    /// escalation command
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub privileged: bool,
+    /// Numeric `uid:gid` of a privileged original, given back on restore since the backup
+    /// itself is handed to the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BackupManifest {
+    backups: Vec<BackupRecord>,
+}
+
+/// Backup store kept under `.dotzilla/backups` in the repository
+///
+/// Each backup lives in its own `<id>/` directory and is indexed by `manifest.json`.
+/// Backups are plaintext copies of local files, so the store is only accessible by the
+/// user and ignored by git.
+pub struct BackupStore {
+    backups_path: PathBuf,
+}
+
+impl BackupStore {
+    pub fn new(repo_path: &Path) -> Self {
+        BackupStore {
+            backups_path: repo_path.join(".dotzilla").join("backups"),
+        }
+    }
+
+    /// Move whatever is at the local path of a dotfile into the store
+    ///
+    /// Backups of encrypted dotfiles are encrypted too, so no plaintext ends up in the
+    /// repository. With an `escalation` command the local path is read and removed
+    /// through it, and what is moved into the store is handed to the user.
+    pub fn backup(
+        &self,
+        dotpath: &DotPath,
+        encrypted: bool,
+        escalation: Option<&str>,
+    ) -> Result<BackupRecord> {
+        let original = &dotpath.abs_path;
+        let metadata = fs::symlink_metadata(original)
+            .with_context(|| format!("Nothing to back up at {}", original.display()))?;
+
+        let kind = if metadata.file_type().is_symlink() {
+            BackupKind::Symlink
+        } else if metadata.is_dir() {
+            BackupKind::Directory
+        } else {
+            BackupKind::File
+        };
+        let encrypted = encrypted && kind == BackupKind::File;
+
+        let timestamp = Local::now();
+        let record = BackupRecord {
+            id: backup_id(&timestamp, original),
+            timestamp,
+            name: dotpath.to_name().clone(),
+            original: original.clone(),
+            kind,
+            encrypted,
+            privileged: escalation.is_some(),
+            owner: escalation
+                .is_some()
+                .then(|| format!("{}:{}", metadata.uid(), metadata.gid())),
+        };
+
+        self.create_store()?;
+        let stored = self.stored_path(&record);
+        if let Some(parent) = stored.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create backup directory {}", parent.display())
+            })?;
+        }
+
+        match (encrypted, escalation) {
+            (true, Some(command)) => {
+                let plaintext = escalate::run(command, &[&"cat", &"--", original], None)?;
+                write_atomic(&stored, secret_key()?.encrypt(&plaintext)?)
+                    .with_context(|| format!("Failed to write backup {}", stored.display()))?;
+                escalate::run(command, &[&"rm", &"-f", &"--", original], None)?;
+            }
+            (true, None) => {
+                let plaintext = fs::read(original)
+                    .with_context(|| format!("Failed to read {}", original.display()))?;
+                write_atomic(&stored, secret_key()?.encrypt(&plaintext)?)
+                    .with_context(|| format!("Failed to write backup {}", stored.display()))?;
+                fs::remove_file(original)
+                    .with_context(|| format!("Failed to remove {}", original.display()))?;
+            }
+            (false, Some(command)) => {
+                escalate::run(command, &[&"mv", &"--", original, &stored], None)?;
+                let user = unsafe { format!("{}:{}", libc::getuid(), libc::getgid()) };
+                escalate::run(
+                    command,
+                    &[&"chown", &"-R", &"-h", &user, &"--", &stored],
+                    None,
+                )?;
+            }
+            (false, None) => move_path(original, &stored)?,
+        }
+
+        let mut manifest = self.load_manifest()?;
+        manifest.backups.push(record.clone());
+        self.save_manifest(&manifest)?;
+
+        Ok(record)
+    }
+
+    /// List every backup, newest first
+    pub fn list(&self) -> Result<Vec<BackupRecord>> {
+        let mut backups = self.load_manifest()?.backups;
+        backups.sort_by_key(|backup| std::cmp::Reverse(backup.timestamp));
+        Ok(backups)
+    }
+
+    /// Find a backup by its id or an unambiguous id prefix
+    pub fn get(&self, id: &str) -> Result<BackupRecord> {
+        let mut matches = self
+            .list()?
+            .into_iter()
+            .filter(|backup| backup.id.starts_with(id));
+
+        match (matches.next(), matches.next()) {
+            (Some(backup), None) => Ok(backup),
+            (Some(_), Some(_)) => Err(anyhow!("Ambiguous backup id: {}", id)),
+            (None, _) => Err(anyhow!("No such backup: {}", id)),
+        }
+    }
+
+    /// Move a backup back to its original path and drop it from the store
+    ///
+    /// The original path must be free; the caller decides what to do with its occupant.
+    /// Privileged backups are moved back through the `escalation` command, when given.
+    pub fn restore(&self, record: &BackupRecord, escalation: Option<&str>) -> Result<()> {
+        if fs::symlink_metadata(&record.original).is_ok() {
+            return Err(anyhow!(
+                "Cannot restore backup {}, {} already exists",
+                record.id,
+                record.original.display()
+            ));
+        }
+
+        let stored = self.stored_path(record);
+        if record.privileged
+            && let Some(command) = escalation
+        {
+            if let Some(parent) = record.original.parent() {
+                escalate::run(command, &[&"mkdir", &"-p", &"--", &parent], None)?;
+            }
+            if record.encrypted {
+                let ciphertext = fs::read(&stored)
+                    .with_context(|| format!("Failed to read backup {}", stored.display()))?;
+                let plaintext = secret_key()?.decrypt(&ciphertext)?;
+                escalate::run(
+                    command,
+                    &[&"install", &"-m", &"600", &"/dev/null", &record.original],
+                    None,
+                )?;
+                escalate::run(
+                    command,
+                    &[&"tee", &"--", &record.original],
+                    Some(&plaintext),
+                )?;
+            } else {
+                escalate::run(command, &[&"mv", &"--", &stored, &record.original], None)?;
+                if let Some(owner) = &record.owner {
+                    escalate::run(
+                        command,
+                        &[&"chown", &"-R", &"-h", owner, &"--", &record.original],
+                        None,
+                    )?;
+                }
+            }
+            return self.remove(&record.id);
+        }
+
+        if let Some(parent) = record.original.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if record.encrypted {
+            let ciphertext = fs::read(&stored)
+                .with_context(|| format!("Failed to read backup {}", stored.display()))?;
+            write_atomic_private(&record.original, secret_key()?.decrypt(&ciphertext)?)
+                .with_context(|| format!("Failed to write {}", record.original.display()))?;
+        } else {
+            move_path(&stored, &record.original)?;
+        }
+
+        self.remove(&record.id)
+    }
+
+    /// Delete a backup and its manifest entry
+    pub fn remove(&self, id: &str) -> Result<()> {
+        let backup_path = self.backups_path.join(id);
+        if backup_path.exists() {
+            fs::remove_dir_all(&backup_path)
+                .with_context(|| format!("Failed to remove backup {}", backup_path.display()))?;
+        }
+
+        let mut manifest = self.load_manifest()?;
+        manifest.backups.retain(|backup| backup.id != id);
+        self.save_manifest(&manifest)
+    }
+
+    /// Create the store with 0700, tightening one created before, and keep git out of it
+    fn create_store(&self) -> Result<()> {
+        let path = &self.backups_path;
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(path)
+            .and_then(|_| fs::set_permissions(path, fs::Permissions::from_mode(0o700)))
+            .with_context(|| format!("Failed to create backup store {}", path.display()))?;
+
+        let gitignore = path.join(".gitignore");
+        if !gitignore.exists() {
+            write_atomic(&gitignore, "*\n")
+                .with_context(|| format!("Failed to write {}", gitignore.display()))?;
+        }
+        Ok(())
+    }
+
+    fn stored_path(&self, record: &BackupRecord) -> PathBuf {
+        self.backups_path
+            .join(&record.id)
+            .join(record.original.file_name().unwrap_or_default())
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.backups_path.join("manifest.json")
+    }
+
+    fn load_manifest(&self) -> Result<BackupManifest> {
+        let manifest_path = self.manifest_path();
+        if !manifest_path.exists() {
+            return Ok(BackupManifest::default());
+        }
+
+        let manifest_str = fs::read_to_string(&manifest_path)
+            .with_context(|| format!("Failed to read {}", manifest_path.display()))?;
+        serde_json::from_str(&manifest_str)
+            .with_context(|| format!("Failed to parse {}", manifest_path.display()))
+    }
+
+    fn save_manifest(&self, manifest: &BackupManifest) -> Result<()> {
+        self.create_store()?;
+
+        let manifest_path = self.manifest_path();
+        write_atomic(&manifest_path, serde_json::to_string_pretty(manifest)?)
+            .with_context(|| format!("Failed to write {}", manifest_path.display()))
+    }
+}
+
+fn backup_id(timestamp: &DateTime<Local>, original: &Path) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(timestamp.to_rfc3339().as_bytes());
+    hasher.update(original.as_os_str().as_encoded_bytes());
+    format!(
+        "{}-{}",
+        timestamp.format("%Y%m%d%H%M%S"),
+        &hex::encode(hasher.finalize())[..6]
+    )
+}
+
+/// Move a file, directory or symlink, copying when a rename can't cross file systems
+fn move_path(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.file_type().is_symlink() {
+        unix_fs::symlink(fs::read_link(from)?, to)?;
+        fs::remove_file(from)?;
+    } else if metadata.is_dir() {
+        copy_dir(from, to, &IgnoreRules::default()).with_context(|| {
+            format!(
+                "Failed to copy directory from {} to {}",
+                from.display(),
+                to.display()
+            )
+        })?;
+        fs::remove_dir_all(from)?;
+    } else {
+        fs::copy(from, to).with_context(|| {
+            format!(
+                "Failed to copy file from {} to {}",
+                from.display(),
+                to.display()
+            )
+        })?;
+        fs::remove_file(from)?;
+    }
+
+    Ok(())
+}
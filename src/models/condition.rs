@@ -0,0 +1,226 @@
+use std::fmt;
+
+use anyhow::{Result, anyhow};
+use regex::Regex;
+
+use crate::utils::facts;
+
+/// Machine fact a condition can test
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fact {
+    Os,
+    Arch,
+    Hostname,
+    User,
+    Env(String),
+}
+
+impl Fact {
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "os" => Ok(Fact::Os),
+            "arch" => Ok(Fact::Arch),
+            "hostname" => Ok(Fact::Hostname),
+            "user" => Ok(Fact::User),
+            _ => match name.strip_prefix("env.") {
+                Some(var) if !var.is_empty() => Ok(Fact::Env(var.to_string())),
+                _ => Err(anyhow!("Unknown fact: {}", name)),
+            },
+        }
+    }
+
+    fn value(&self) -> Option<String> {
+        match self {
+            Fact::Os => Some(facts::os().to_string()),
+            Fact::Arch => Some(facts::arch().to_string()),
+            Fact::Hostname => Some(facts::hostname()),
+            Fact::User => Some(facts::user()),
+            Fact::Env(var) => std::env::var(var).ok(),
+        }
+    }
+}
+
+impl fmt::Display for Fact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fact::Os => write!(f, "os"),
+            Fact::Arch => write!(f, "arch"),
+            Fact::Hostname => write!(f, "hostname"),
+            Fact::User => write!(f, "user"),
+            Fact::Env(var) => write!(f, "env.{}", var),
+        }
+    }
+}
+
+/// A `when` condition of a dotfile entry, such as `os = "linux"`,
+/// `hostname ~= "^build-"` or `env.WAYLAND_DISPLAY exists`
+#[derive(Debug, Clone)]
+pub enum Condition {
+    Equals(Fact, String),
+    NotEquals(Fact, String),
+    Matches(Fact, Regex),
+    Exists(Fact),
+    Missing(Fact),
+}
+
+impl Condition {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let expr = expr.trim();
+
+        // Fact names never hold a '=', so the first one belongs to the operator and
+        // anything after it, '=' and '!=' included, to the value
+        if let Some(eq) = expr.find('=') {
+            let (fact, op) = match expr[..eq].chars().last() {
+                Some(op @ ('~' | '!')) => (&expr[..eq - 1], op),
+                _ => (&expr[..eq], '='),
+            };
+            let fact = Fact::parse(fact.trim())?;
+            let value = unquote(expr[eq + 1..].trim());
+
+            return match op {
+                '~' => Ok(Condition::Matches(
+                    fact,
+                    Regex::new(value)
+                        .map_err(|err| anyhow!("Invalid pattern in '{}': {}", expr, err))?,
+                )),
+                '!' => Ok(Condition::NotEquals(fact, value.to_string())),
+                _ => Ok(Condition::Equals(fact, value.to_string())),
+            };
+        }
+
+        if let Some(fact) = expr.strip_suffix(" exists") {
+            return Ok(Condition::Exists(Fact::parse(fact.trim())?));
+        }
+        if let Some(fact) = expr.strip_suffix(" missing") {
+            return Ok(Condition::Missing(Fact::parse(fact.trim())?));
+        }
+
+        Err(anyhow!(
+            "Invalid condition '{}', expected '<fact> = <value>', '<fact> != <value>', '<fact> ~= <regex>', '<fact> exists' or '<fact> missing'",
+            expr
+        ))
+    }
+
+    /// Check the condition against this machine, explaining why it failed
+    pub fn evaluate(&self) -> std::result::Result<(), String> {
+        let matched = match self {
+            Condition::Equals(fact, expected) => fact.value().as_ref() == Some(expected),
+            Condition::NotEquals(fact, expected) => fact.value().as_ref() != Some(expected),
+            Condition::Matches(fact, regex) => fact.value().is_some_and(|v| regex.is_match(&v)),
+            Condition::Exists(fact) => fact.value().is_some(),
+            Condition::Missing(fact) => fact.value().is_none(),
+        };
+
+        if matched {
+            return Ok(());
+        }
+
+        let actual = match self {
+            Condition::Equals(fact, _)
+            | Condition::NotEquals(fact, _)
+            | Condition::Matches(fact, _)
+            | Condition::Exists(fact)
+            | Condition::Missing(fact) => fact.value(),
+        };
+
+        Err(match actual {
+            Some(actual) => format!("{} (is \"{}\")", self, actual),
+            None => format!("{} (not set)", self),
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Equals(fact, value) => write!(f, "{} = \"{}\"", fact, value),
+            Condition::NotEquals(fact, value) => write!(f, "{} != \"{}\"", fact, value),
+            Condition::Matches(fact, regex) => write!(f, "{} ~= \"{}\"", fact, regex),
+            Condition::Exists(fact) => write!(f, "{} exists", fact),
+            Condition::Missing(fact) => write!(f, "{} missing", fact),
+        }
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_conditions() {
+        assert!(matches!(
+            Condition::parse("os = \"linux\"").unwrap(),
+            Condition::Equals(Fact::Os, v) if v == "linux"
+        ));
+        assert!(matches!(
+            Condition::parse("hostname ~= \"^build-\"").unwrap(),
+            Condition::Matches(Fact::Hostname, _)
+        ));
+        assert!(matches!(
+            Condition::parse("env.WAYLAND_DISPLAY exists").unwrap(),
+            Condition::Exists(Fact::Env(v)) if v == "WAYLAND_DISPLAY"
+        ));
+        assert!(matches!(
+            Condition::parse("env.OPTS = \"a!=b\"").unwrap(),
+            Condition::Equals(Fact::Env(_), v) if v == "a!=b"
+        ));
+        assert!(matches!(
+            Condition::parse("env.OPTS != x==y").unwrap(),
+            Condition::NotEquals(Fact::Env(_), v) if v == "x==y"
+        ));
+        assert!(matches!(
+            Condition::parse("hostname ~= ^a=b").unwrap(),
+            Condition::Matches(Fact::Hostname, r) if r.as_str() == "^a=b"
+        ));
+        assert!(matches!(
+            Condition::parse("env.MODE = is missing").unwrap(),
+            Condition::Equals(Fact::Env(_), v) if v == "is missing"
+        ));
+        assert!(Condition::parse("shell = zsh").is_err());
+        assert!(Condition::parse("os").is_err());
+    }
+
+    #[test]
+    fn test_evaluate_conditions() {
+        let os = std::env::consts::OS;
+
+        assert!(
+            Condition::parse(&format!("os = {}", os))
+                .unwrap()
+                .evaluate()
+                .is_ok()
+        );
+        assert!(
+            Condition::parse(&format!("os != {}", os))
+                .unwrap()
+                .evaluate()
+                .is_err()
+        );
+        assert!(
+            Condition::parse("env.PATH exists")
+                .unwrap()
+                .evaluate()
+                .is_ok()
+        );
+        assert!(
+            Condition::parse("env.DOTZILLA_SURELY_UNSET missing")
+                .unwrap()
+                .evaluate()
+                .is_ok()
+        );
+        assert!(
+            Condition::parse("arch ~= \".+\"")
+                .unwrap()
+                .evaluate()
+                .is_ok()
+        );
+    }
+}
@@ -1,13 +1,45 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::path::PathBuf;
 
-use super::DotPath;
+use super::{Condition, DotPath};
+use crate::utils::mode::{self, Modes};
+use crate::utils::walk::SymlinkPolicy;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DotfileEntry {
     pub source: PathBuf,
     pub target: PathBuf,
     pub status: DotfileStatus,
+    /// SHA-256 digest of the repository copy, taken at add/commit time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Conditions that must all hold on this machine for the entry to be active
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub when: Vec<String>,
+    /// Render the repository copy as a template before linking
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub template: bool,
+    /// Store the repository copy encrypted and deploy it as a private copy
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted: bool,
+    /// How the repository copy is put in place on `link`
+    #[serde(default, skip_serializing_if = "DeployMode::is_symlink")]
+    pub deploy: DeployMode,
+    /// Gitignore-style patterns for paths inside a directory dotfile to leave out
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+    /// What happens to symlinks inside a directory dotfile
+    #[serde(default, skip_serializing_if = "SymlinkPolicy::is_preserve")]
+    pub symlinks: SymlinkPolicy,
+    /// The local path is only writable by root, changes to it go through the
+    /// repository's escalation command
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub privileged: bool,
+    /// Permission bits of each file and directory relative to the local path, with the
+    /// owner when it isn't the user, restored on `link` and `commit`
+    #[serde(default, skip_serializing_if = "Modes::is_empty")]
+    pub modes: Modes,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -18,12 +50,97 @@ pub enum DotfileStatus {
     Modified,
 }
 
+/// How `link` puts a dotfile in place
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DeployMode {
+    /// Symlink the local path to the repository copy
+    #[default]
+    Symlink,
+    /// Copy the repository copy, tracking drift by content hash
+    Copy,
+    /// Hard link the local path to the repository copy
+    Hardlink,
+    /// Keep the local directory and symlink each file of the repository copy into it
+    Unfold,
+}
+
+impl DeployMode {
+    pub fn is_symlink(&self) -> bool {
+        *self == DeployMode::Symlink
+    }
+
+    /// Whether the local path reaches the repository copy through symlinks, so local
+    /// edits land there directly
+    pub fn uses_symlinks(&self) -> bool {
+        matches!(self, DeployMode::Symlink | DeployMode::Unfold)
+    }
+}
+
+impl fmt::Display for DeployMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeployMode::Symlink => write!(f, "symlink"),
+            DeployMode::Copy => write!(f, "copy"),
+            DeployMode::Hardlink => write!(f, "hardlink"),
+            DeployMode::Unfold => write!(f, "unfold"),
+        }
+    }
+}
+
+/// Whether an entry applies to this machine, based on its `when` conditions
+#[derive(Debug, Clone, PartialEq)]
+pub enum Activation {
+    Active,
+    Inactive(String),
+}
+
 impl DotfileEntry {
     pub fn new(source: PathBuf, target: PathBuf, status: DotfileStatus) -> Self {
         DotfileEntry {
             source,
             target,
             status,
+            hash: None,
+            when: Vec::new(),
+            template: false,
+            encrypted: false,
+            deploy: DeployMode::Symlink,
+            ignore: Vec::new(),
+            symlinks: SymlinkPolicy::Preserve,
+            privileged: false,
+            modes: Modes::new(),
+        }
+    }
+
+    /// Evaluate the `when` conditions against this machine
+    pub fn activation(&self) -> Activation {
+        for expr in &self.when {
+            let reason = match Condition::parse(expr) {
+                Ok(condition) => condition.evaluate().err(),
+                Err(err) => Some(err.to_string()),
+            };
+
+            if let Some(reason) = reason {
+                return Activation::Inactive(reason);
+            }
+        }
+
+        Activation::Active
+    }
+
+    /// The recorded modes as they should show at the local path; owners only apply to
+    /// a local copy, linked files belong to the repository
+    ///
+    /// A decrypted copy is never made readable by anyone but its owner, whatever mode
+    /// the file had when it was added.
+    pub fn deployed_modes(&self) -> Modes {
+        if self.encrypted {
+            mode::masked(&self.modes, 0o600)
+        } else if self.deploy == DeployMode::Copy {
+            self.modes.clone()
+        } else {
+            mode::without_owners(&self.modes)
         }
     }
 
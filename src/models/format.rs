@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use toml_edit::{DocumentMut, Item, Table};
+
+/// On-disk format of the repository manifest
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum ConfigFormat {
+    /// `.dotzilla.json`
+    #[default]
+    Json,
+    /// `dotzilla.toml`, sorted and comment preserving
+    Toml,
+}
+
+impl ConfigFormat {
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            ConfigFormat::Json => ".dotzilla.json",
+            ConfigFormat::Toml => "dotzilla.toml",
+        }
+    }
+
+    /// Detect which manifest a repository uses, preferring TOML when both exist
+    pub fn detect(repo_path: &Path) -> Option<Self> {
+        [ConfigFormat::Toml, ConfigFormat::Json]
+            .into_iter()
+            .find(|format| repo_path.join(format.file_name()).exists())
+    }
+
+    pub fn other(&self) -> Self {
+        match self {
+            ConfigFormat::Json => ConfigFormat::Toml,
+            ConfigFormat::Toml => ConfigFormat::Json,
+        }
+    }
+
+    /// Parse a manifest into a generic value so it can be migrated before deserializing
+    pub fn parse(&self, content: &str) -> Result<serde_json::Value> {
+        match self {
+            ConfigFormat::Json => Ok(serde_json::from_str(content)?),
+            ConfigFormat::Toml => Ok(toml_edit::de::from_str(content)?),
+        }
+    }
+
+    /// Render a manifest, keeping the comments of the existing TOML document if given
+    pub fn render<T: Serialize>(&self, value: &T, existing: Option<&str>) -> Result<String> {
+        match self {
+            ConfigFormat::Json => Ok(serde_json::to_string_pretty(value)?),
+            ConfigFormat::Toml => {
+                let mut document: DocumentMut = toml_edit::ser::to_string_pretty(value)?
+                    .parse()
+                    .context("Failed to render TOML manifest")?;
+
+                if let Some(old) = existing.and_then(|s| s.parse::<DocumentMut>().ok()) {
+                    carry_comments(old.as_table(), document.as_table_mut());
+                    document.set_trailing(old.trailing().clone());
+                }
+
+                Ok(document.to_string())
+            }
+        }
+    }
+}
+
+/// Copy comments and formatting of matching keys from the old document into the new one
+fn carry_comments(old: &Table, new: &mut Table) {
+    *new.decor_mut() = old.decor().clone();
+
+    for (mut key, item) in new.iter_mut() {
+        let Some((old_key, old_item)) = old.get_key_value(key.get()) else {
+            continue;
+        };
+        *key.leaf_decor_mut() = old_key.leaf_decor().clone();
+
+        match (old_item, item) {
+            (Item::Table(old_table), Item::Table(new_table)) => {
+                carry_comments(old_table, new_table);
+            }
+            (Item::Value(old_value), Item::Value(new_value)) => {
+                *new_value.decor_mut() = old_value.decor().clone();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_toml_keeps_comments() {
+        let existing = "# Shared dotfiles\nversion = 1\n\n# Shell\n[dotfiles.\"~/.bashrc\"]\nsource = \"~/.bashrc\" # keep\n";
+        let value = json!({
+            "version": 1,
+            "dotfiles": {
+                "~/.zshrc": { "source": "~/.zshrc" },
+                "~/.bashrc": { "source": "~/.bashrc" },
+            },
+        });
+
+        let rendered = ConfigFormat::Toml.render(&value, Some(existing)).unwrap();
+
+        assert!(rendered.starts_with("# Shared dotfiles\n"));
+        assert!(rendered.contains("# Shell\n[dotfiles.\"~/.bashrc\"]"));
+        assert!(rendered.contains("source = \"~/.bashrc\" # keep"));
+        assert!(rendered.find("~/.bashrc").unwrap() < rendered.find("~/.zshrc").unwrap());
+    }
+}
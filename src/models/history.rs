@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::plan::copy_dir;
+use super::{Config, DotPath, DotfileEntry};
+use crate::utils::ignore::IgnoreRules;
+
+/// A single recorded `dotzilla commit`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitRecord {
+    pub id: String,
+    pub timestamp: DateTime<Local>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    pub files: Vec<PathBuf>,
+    /// Every tracked dotfile as of the commit, so a restore can bring back entries
+    /// removed since
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub entries: BTreeMap<PathBuf, DotfileEntry>,
+}
+
+/// Snapshot store kept under `.dotzilla/history` in the repository
+pub struct History {
+    history_path: PathBuf,
+}
+
+impl CommitRecord {
+    /// The record of a commit of the given dotpaths made now, not written anywhere yet
+    ///
+    /// `config` is the manifest with the commit applied.
+    pub fn new(message: Option<String>, config: &Config, dotpaths: &[DotPath]) -> Self {
+        let timestamp = Local::now();
+        let files: Vec<PathBuf> = dotpaths.iter().map(|d| d.to_name().clone()).collect();
+        let id = commit_id(&timestamp, message.as_deref(), &files);
+
+        CommitRecord {
+            id,
+            timestamp,
+            message,
+            files,
+            entries: config.dotfiles.clone(),
+        }
+    }
+}
+
+impl History {
+    pub fn new(repo_path: &Path) -> Self {
+        History {
+            history_path: repo_path.join(".dotzilla").join("history"),
+        }
+    }
+
+    /// Snapshot the repository copy of every dotpath and write the record of the commit
+    pub fn record(&self, record: &CommitRecord, dotpaths: &[DotPath]) -> Result<()> {
+        let commit_path = self.commit_path(&record.id);
+        let files_path = commit_path.join("files");
+        fs::create_dir_all(&files_path).with_context(|| {
+            format!(
+                "Failed to create history directory: {}",
+                commit_path.display()
+            )
+        })?;
+
+        if let Err(err) = write_commit(&commit_path, record, dotpaths) {
+            // A commit without its record or with half of its snapshots is no use to anyone
+            let _ = fs::remove_dir_all(&commit_path);
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// List every recorded commit, newest first
+    pub fn list(&self) -> Result<Vec<CommitRecord>> {
+        let mut commits = Vec::new();
+
+        if !self.history_path.exists() {
+            return Ok(commits);
+        }
+
+        for entry in fs::read_dir(&self.history_path)? {
+            let record_path = entry?.path().join("commit.json");
+            if !record_path.exists() {
+                continue;
+            }
+
+            let record_str = fs::read_to_string(&record_path)
+                .with_context(|| format!("Failed to read commit from {}", record_path.display()))?;
+            let record: CommitRecord = serde_json::from_str(&record_str).with_context(|| {
+                format!("Failed to parse commit from {}", record_path.display())
+            })?;
+            commits.push(record);
+        }
+
+        commits.sort_by_key(|commit| std::cmp::Reverse(commit.timestamp));
+        Ok(commits)
+    }
+
+    /// Delete a recorded commit and its snapshots, if anything of it was written
+    pub fn remove(&self, id: &str) -> Result<()> {
+        let commit_path = self.commit_path(id);
+        match fs::remove_dir_all(&commit_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err)
+                .with_context(|| format!("Failed to remove commit {}", commit_path.display())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Find a commit by its id or an unambiguous id prefix
+    pub fn get(&self, id: &str) -> Result<CommitRecord> {
+        let mut matches = self
+            .list()?
+            .into_iter()
+            .filter(|commit| commit.id.starts_with(id));
+
+        match (matches.next(), matches.next()) {
+            (Some(commit), None) => Ok(commit),
+            (Some(_), Some(_)) => Err(anyhow!("Ambiguous commit id: {}", id)),
+            (None, _) => Err(anyhow!("No such commit: {}", id)),
+        }
+    }
+
+    /// Latest snapshot of a dotpath taken at or before the given commit
+    pub fn snapshot_at(&self, commit: &CommitRecord, dotpath: &DotPath) -> Result<Option<PathBuf>> {
+        let snapshot = self
+            .list()?
+            .into_iter()
+            .filter(|c| c.timestamp <= commit.timestamp)
+            .find(|c| c.files.contains(dotpath.to_name()))
+            .map(|c| self.snapshot_path(&c, dotpath))
+            .filter(|path| path.exists());
+
+        Ok(snapshot)
+    }
+
+    /// Directory holding the record and snapshots of a commit
+    pub fn commit_path(&self, id: &str) -> PathBuf {
+        self.history_path.join(id)
+    }
+
+    fn snapshot_path(&self, commit: &CommitRecord, dotpath: &DotPath) -> PathBuf {
+        self.history_path
+            .join(&commit.id)
+            .join("files")
+            .join(snapshot_rel_path(dotpath))
+    }
+}
+
+fn commit_id(timestamp: &DateTime<Local>, message: Option<&str>, files: &[PathBuf]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(timestamp.to_rfc3339().as_bytes());
+    hasher.update(message.unwrap_or_default().as_bytes());
+    for file in files {
+        hasher.update(file.as_os_str().as_encoded_bytes());
+    }
+    hex::encode(hasher.finalize())[..12].to_string()
+}
+
+fn snapshot_rel_path(dotpath: &DotPath) -> PathBuf {
+    dotpath
+        .target
+        .strip_prefix(".")
+        .unwrap_or(&dotpath.target)
+        .to_path_buf()
+}
+
+/// Snapshot the repository copy of every dotpath and write the record of the commit
+fn write_commit(commit_path: &Path, record: &CommitRecord, dotpaths: &[DotPath]) -> Result<()> {
+    let files_path = commit_path.join("files");
+    for dotpath in dotpaths {
+        if !dotpath.abs_target.exists() {
+            continue;
+        }
+        let snapshot_path = files_path.join(snapshot_rel_path(dotpath));
+        copy_snapshot(&dotpath.abs_target, &snapshot_path)?;
+    }
+
+    let record_path = commit_path.join("commit.json");
+    fs::write(&record_path, serde_json::to_string_pretty(record)?)
+        .with_context(|| format!("Failed to write commit to {}", record_path.display()))
+}
+
+/// Copy a file or directory, creating any missing parent directories
+pub fn copy_snapshot(source: &Path, snapshot_path: &Path) -> Result<()> {
+    if let Some(parent) = snapshot_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if source.is_dir() {
+        // Symlinks are kept as they are, so links back into the tree can't loop
+        copy_dir(source, snapshot_path, &IgnoreRules::default()).with_context(|| {
+            format!(
+                "Failed to snapshot directory from {} to {}",
+                source.display(),
+                snapshot_path.display()
+            )
+        })?;
+    } else {
+        fs::copy(source, snapshot_path).with_context(|| {
+            format!(
+                "Failed to snapshot file from {} to {}",
+                source.display(),
+                snapshot_path.display()
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Config;
+    use chrono::{Duration, TimeZone};
+
+    fn setup(name: &str) -> (PathBuf, DotPath) {
+        let root = Path::new("/tmp").join(name);
+        let _ = fs::remove_dir_all(&root);
+        let config = Config::new(root.clone());
+        let dotpath = DotPath::new(&config, "/etc/app.conf");
+        fs::create_dir_all(dotpath.abs_target.parent().unwrap()).unwrap();
+        (root, dotpath)
+    }
+
+    /// Record a commit of `dotpath` holding `content`, `seconds` after a fixed point in time
+    fn commit(
+        root: &Path,
+        id: &str,
+        seconds: i64,
+        dotpath: &DotPath,
+        content: &str,
+    ) -> CommitRecord {
+        fs::write(&dotpath.abs_target, content).unwrap();
+        let record = CommitRecord {
+            id: id.to_string(),
+            timestamp: Local.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap()
+                + Duration::seconds(seconds),
+            message: None,
+            files: vec![dotpath.to_name().clone()],
+            entries: BTreeMap::new(),
+        };
+        History::new(root)
+            .record(&record, std::slice::from_ref(dotpath))
+            .unwrap();
+        record
+    }
+
+    #[test]
+    fn test_record_and_list_newest_first() {
+        let (root, dotpath) = setup("test_dotzilla_history_list");
+        let history = History::new(&root);
+        assert!(history.list().unwrap().is_empty());
+
+        commit(&root, "bbb", 20, &dotpath, "two");
+        commit(&root, "aaa", 10, &dotpath, "one");
+        commit(&root, "ccc", 30, &dotpath, "three");
+
+        let ids: Vec<String> = history.list().unwrap().into_iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec!["ccc", "bbb", "aaa"]);
+        let snapshot = history.commit_path("aaa").join("files/root/etc/app.conf");
+        assert_eq!(fs::read_to_string(snapshot).unwrap(), "one");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_get_by_id_or_prefix() {
+        let (root, dotpath) = setup("test_dotzilla_history_get");
+        let history = History::new(&root);
+        commit(&root, "abc111", 10, &dotpath, "one");
+        commit(&root, "abc222", 20, &dotpath, "two");
+
+        assert_eq!(history.get("abc111").unwrap().id, "abc111");
+        assert_eq!(history.get("abc2").unwrap().id, "abc222");
+        let ambiguous = history.get("abc").unwrap_err();
+        assert_eq!(ambiguous.to_string(), "Ambiguous commit id: abc");
+        let unknown = history.get("fff").unwrap_err();
+        assert_eq!(unknown.to_string(), "No such commit: fff");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_at_takes_latest_up_to_commit() {
+        let (root, dotpath) = setup("test_dotzilla_history_snapshot");
+        let history = History::new(&root);
+        let other = DotPath::new(&Config::new(root.clone()), "/etc/other.conf");
+        let first = commit(&root, "first", 10, &other, "other");
+        let second = commit(&root, "second", 20, &dotpath, "one");
+        let third = commit(&root, "third", 30, &other, "other");
+        let fourth = commit(&root, "fourth", 40, &dotpath, "two");
+
+        let content = |commit: &CommitRecord| {
+            history
+                .snapshot_at(commit, &dotpath)
+                .unwrap()
+                .map(|path| fs::read_to_string(path).unwrap())
+        };
+        assert_eq!(content(&first), None);
+        // A commit's own snapshot counts, its timestamp is the boundary
+        assert_eq!(content(&second).as_deref(), Some("one"));
+        assert_eq!(content(&third).as_deref(), Some("one"));
+        assert_eq!(content(&fourth).as_deref(), Some("two"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,409 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use super::{DotPath, Operation, Plan, ignore_local};
+use crate::utils::atomic::write_atomic;
+use crate::utils::ignore::IgnoreRules;
+use crate::utils::mode::{self, Modes};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StepState {
+    Pending,
+    BackedUp,
+    Applied,
+}
+
+/// Move of a single staged dotfile into the repository
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalStep {
+    pub name: PathBuf,
+    pub staged: PathBuf,
+    pub target: PathBuf,
+    pub backup: PathBuf,
+    pub has_staged: bool,
+    pub has_target: bool,
+    pub state: StepState,
+    /// Modes of the staged copy, put back when it returns to staging on rollback
+    #[serde(default, skip_serializing_if = "Modes::is_empty")]
+    pub modes: Modes,
+}
+
+impl JournalStep {
+    fn backup_operation(&self) -> Operation {
+        Operation::Rename {
+            from: self.target.clone(),
+            to: self.backup.clone(),
+        }
+    }
+
+    fn move_operation(&self) -> Operation {
+        Operation::Rename {
+            from: self.staged.clone(),
+            to: self.target.clone(),
+        }
+    }
+}
+
+/// Write-ahead journal of an in-progress commit, kept at `.dotzilla/journal.json`
+///
+/// The journal is written before anything is moved and updated after every
+/// step, so an interrupted commit can always be rolled forward or back.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Journal {
+    #[serde(skip)]
+    path: PathBuf,
+    pub started: DateTime<Local>,
+    pub steps: Vec<JournalStep>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit_id: Option<String>,
+    #[serde(default)]
+    pub committed: bool,
+}
+
+impl Journal {
+    fn journal_path(repo_path: &Path) -> PathBuf {
+        repo_path.join(".dotzilla").join("journal.json")
+    }
+
+    /// Start a new journal for the given dotpaths, failing if one is already pending
+    pub fn begin(repo_path: &Path, dotpaths: &[DotPath]) -> Result<Self> {
+        let journal = Journal::new(repo_path, dotpaths)?;
+        ignore_local(repo_path, "journal.json")?;
+        journal.save()?;
+        Ok(journal)
+    }
+
+    /// Prepare a journal without writing it, e.g. to plan a dry run
+    pub fn new(repo_path: &Path, dotpaths: &[DotPath]) -> Result<Self> {
+        let path = Journal::journal_path(repo_path);
+        if path.exists() {
+            return Err(anyhow!(
+                "An interrupted commit is pending at {}, run dotzilla again to recover it",
+                path.display()
+            ));
+        }
+
+        let steps = dotpaths
+            .iter()
+            .map(|dotpath| {
+                let mut backup = dotpath.abs_target.clone();
+                backup.set_file_name(format!(
+                    "{}-dz-bpk",
+                    backup.file_name().unwrap_or_default().to_string_lossy()
+                ));
+
+                let has_staged = dotpath.abs_target_staged.exists();
+                let modes = if has_staged {
+                    mode::capture(&dotpath.abs_target_staged, &IgnoreRules::default())
+                        .with_context(|| {
+                            format!(
+                                "Failed to read the modes of {}",
+                                dotpath.abs_target_staged.display()
+                            )
+                        })?
+                } else {
+                    Modes::new()
+                };
+
+                Ok(JournalStep {
+                    name: dotpath.to_name().clone(),
+                    staged: dotpath.abs_target_staged.clone(),
+                    target: dotpath.abs_target.clone(),
+                    backup,
+                    has_staged,
+                    has_target: fs::symlink_metadata(&dotpath.abs_target).is_ok(),
+                    state: StepState::Pending,
+                    modes: mode::without_owners(&modes),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Journal {
+            path,
+            started: Local::now(),
+            steps,
+            commit_id: None,
+            committed: false,
+        })
+    }
+
+    /// Load the pending journal of a repository, if there is one
+    pub fn load(repo_path: &Path) -> Result<Option<Self>> {
+        let path = Journal::journal_path(repo_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let journal_str = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read journal from {}", path.display()))?;
+        let mut journal: Journal = serde_json::from_str(&journal_str)
+            .with_context(|| format!("Failed to parse journal from {}", path.display()))?;
+        journal.path = path;
+        Ok(Some(journal))
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let journal_str = serde_json::to_string_pretty(self)?;
+        write_atomic(&self.path, journal_str)
+            .with_context(|| format!("Failed to write journal to {}", self.path.display()))
+    }
+
+    /// Move every staged file into place, backing up what it replaces
+    pub fn apply(&mut self) -> Result<()> {
+        for i in 0..self.steps.len() {
+            if self.steps[i].state == StepState::Pending {
+                let step = &self.steps[i];
+                if step.has_staged && step.has_target && fs::symlink_metadata(&step.target).is_ok()
+                {
+                    step.backup_operation().apply().with_context(|| {
+                        format!("Failed to back up existing file: {}", step.target.display())
+                    })?;
+                }
+                self.steps[i].state = StepState::BackedUp;
+                self.save()?;
+            }
+
+            if self.steps[i].state == StepState::BackedUp {
+                let step = &self.steps[i];
+                if step.has_staged && step.staged.exists() {
+                    step.move_operation().apply().with_context(|| {
+                        format!(
+                            "Failed to move staged file into place: {} -> {}",
+                            step.staged.display(),
+                            step.target.display()
+                        )
+                    })?;
+                }
+                self.steps[i].state = StepState::Applied;
+                self.save()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Everything `apply` and `finish` would do, for a dry run
+    pub fn plan(&self) -> Plan {
+        let mut plan = Plan::new();
+        for step in self.steps.iter().filter(|step| step.has_staged) {
+            if step.has_target {
+                plan.push(step.backup_operation());
+            }
+            plan.push(step.move_operation());
+        }
+        for step in &self.steps {
+            if step.has_staged && step.has_target {
+                plan.push(Operation::Delete(step.backup.clone()));
+            }
+        }
+        plan
+    }
+
+    /// Record the id of the history snapshot before it is written, so a rollback can
+    /// find whatever of it made it to the disk
+    pub fn mark_recording(&mut self, commit_id: &str) -> Result<()> {
+        self.commit_id = Some(commit_id.to_string());
+        self.save()
+    }
+
+    /// Record that the commit is past the point of no return
+    pub fn mark_committed(&mut self) -> Result<()> {
+        self.committed = true;
+        self.save()
+    }
+
+    /// Undo every step in reverse order and discard the journal
+    ///
+    /// What each step did is read from the disk rather than from its saved state, since a
+    /// crash can land between a move and the save that records it: a missing staged file
+    /// was moved into place, and a backup that is still there has to be put back. Staged
+    /// copies get back the modes they had before the commit changed them.
+    pub fn rollback(mut self) -> Result<()> {
+        for i in (0..self.steps.len()).rev() {
+            let step = &self.steps[i];
+            if !step.has_staged {
+                continue;
+            }
+
+            // A target that was there is only replaced once it is backed up
+            let backed_up = !step.has_target || fs::symlink_metadata(&step.backup).is_ok();
+            if backed_up && !step.staged.exists() && fs::symlink_metadata(&step.target).is_ok() {
+                if let Some(parent) = step.staged.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::rename(&step.target, &step.staged)
+                    .with_context(|| format!("Failed to roll back {}", step.target.display()))?;
+            }
+
+            if step.has_target
+                && fs::symlink_metadata(&step.backup).is_ok()
+                && fs::symlink_metadata(&step.target).is_err()
+            {
+                fs::rename(&step.backup, &step.target).with_context(|| {
+                    format!("Failed to restore backup {}", step.backup.display())
+                })?;
+            }
+
+            if step.staged.exists() {
+                mode::restore(&step.staged, &step.modes).with_context(|| {
+                    format!("Failed to restore the modes of {}", step.staged.display())
+                })?;
+            }
+
+            self.steps[i].state = StepState::Pending;
+            self.save()?;
+        }
+
+        self.discard()
+    }
+
+    /// Remove the backups of a finished commit and discard the journal
+    pub fn finish(self) -> Result<()> {
+        for step in &self.steps {
+            if fs::symlink_metadata(&step.backup).is_ok() {
+                Operation::Delete(step.backup.clone())
+                    .apply()
+                    .with_context(|| {
+                        format!("Failed to remove backup: {}", step.backup.display())
+                    })?;
+            }
+        }
+
+        self.discard()
+    }
+
+    fn discard(self) -> Result<()> {
+        fs::remove_file(&self.path)
+            .with_context(|| format!("Failed to remove journal: {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A journal committing `staged` over `target` in `root`, with the step in `state`
+    fn journal(root: &Path, has_target: bool, state: StepState) -> Journal {
+        Journal {
+            path: root.join(".dotzilla/journal.json"),
+            started: Local::now(),
+            steps: vec![JournalStep {
+                name: PathBuf::from(".bashrc"),
+                staged: root.join(".staging/.bashrc"),
+                target: root.join(".bashrc"),
+                backup: root.join(".bashrc-dz-bpk"),
+                has_staged: true,
+                has_target,
+                state,
+                modes: Modes::new(),
+            }],
+            commit_id: None,
+            committed: false,
+        }
+    }
+
+    fn setup(name: &str) -> PathBuf {
+        let root = Path::new("/tmp").join(name);
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join(".staging")).unwrap();
+        fs::write(root.join(".staging/.bashrc"), "new").unwrap();
+        fs::write(root.join(".bashrc"), "old").unwrap();
+        root
+    }
+
+    fn read(path: PathBuf) -> Option<String> {
+        fs::read_to_string(path).ok()
+    }
+
+    #[test]
+    fn test_begin_keeps_journal_out_of_git() {
+        let root = setup("test_dotzilla_journal_ignored");
+        let journal = Journal::begin(&root, &[]).unwrap();
+
+        assert!(root.join(".dotzilla/journal.json").exists());
+        assert_eq!(
+            read(root.join(".dotzilla/.gitignore")).as_deref(),
+            Some("/journal.json\n")
+        );
+
+        journal.finish().unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_rollback_after_backup_before_save() {
+        let root = setup("test_dotzilla_journal_backup");
+        let journal = journal(&root, true, StepState::Pending);
+        journal.save().unwrap();
+        fs::rename(root.join(".bashrc"), root.join(".bashrc-dz-bpk")).unwrap();
+
+        journal.rollback().unwrap();
+        assert_eq!(read(root.join(".bashrc")).as_deref(), Some("old"));
+        assert_eq!(read(root.join(".staging/.bashrc")).as_deref(), Some("new"));
+        assert!(!root.join(".bashrc-dz-bpk").exists());
+        assert!(!root.join(".dotzilla/journal.json").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_rollback_after_move_before_save() {
+        let root = setup("test_dotzilla_journal_move");
+        let journal = journal(&root, true, StepState::BackedUp);
+        journal.save().unwrap();
+        fs::rename(root.join(".bashrc"), root.join(".bashrc-dz-bpk")).unwrap();
+        fs::rename(root.join(".staging/.bashrc"), root.join(".bashrc")).unwrap();
+
+        journal.rollback().unwrap();
+        assert_eq!(read(root.join(".bashrc")).as_deref(), Some("old"));
+        assert_eq!(read(root.join(".staging/.bashrc")).as_deref(), Some("new"));
+        assert!(!root.join(".bashrc-dz-bpk").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_rollback_new_file_after_move_before_save() {
+        let root = setup("test_dotzilla_journal_new");
+        fs::remove_file(root.join(".bashrc")).unwrap();
+        let journal = journal(&root, false, StepState::BackedUp);
+        journal.save().unwrap();
+        fs::rename(root.join(".staging/.bashrc"), root.join(".bashrc")).unwrap();
+
+        journal.rollback().unwrap();
+        assert!(!root.join(".bashrc").exists());
+        assert_eq!(read(root.join(".staging/.bashrc")).as_deref(), Some("new"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_apply_after_backup_before_save() {
+        let root = setup("test_dotzilla_journal_forward");
+        let mut journal = journal(&root, true, StepState::Pending);
+        journal.save().unwrap();
+        fs::rename(root.join(".bashrc"), root.join(".bashrc-dz-bpk")).unwrap();
+
+        journal.apply().unwrap();
+        assert!(
+            journal
+                .steps
+                .iter()
+                .all(|step| step.state == StepState::Applied)
+        );
+        assert_eq!(read(root.join(".bashrc")).as_deref(), Some("new"));
+        assert_eq!(read(root.join(".bashrc-dz-bpk")).as_deref(), Some("old"));
+
+        journal.finish().unwrap();
+        assert!(!root.join(".bashrc-dz-bpk").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
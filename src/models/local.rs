@@ -0,0 +1,32 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::utils::atomic::write_atomic;
+
+/// Keep `name` under `.dotzilla` out of git, by listing it in `.dotzilla/.gitignore`
+///
+/// For files that only make sense on this machine, like the lock or the journal of a
+/// commit in progress. The history and template variables stay shared.
+pub fn ignore_local(repo_path: &Path, name: &str) -> Result<()> {
+    let dir = repo_path.join(".dotzilla");
+    let gitignore = dir.join(".gitignore");
+    let entry = format!("/{}", name);
+
+    let existing = fs::read_to_string(&gitignore).unwrap_or_default();
+    if existing.lines().any(|line| line == entry) {
+        return Ok(());
+    }
+
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    let mut content = existing;
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&entry);
+    content.push('\n');
+    write_atomic(&gitignore, content)
+        .with_context(|| format!("Failed to write {}", gitignore.display()))
+}
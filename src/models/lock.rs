@@ -0,0 +1,92 @@
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+
+use super::ignore_local;
+
+/// Advisory lock on a repository, held for a whole load-modify-save cycle
+///
+/// The lock is an exclusive `flock` on the `.dotzilla/lock` file, which also holds the
+/// owner's PID for error messages. The kernel releases it when the guard is dropped or
+/// its process dies, so a lock can never be left stale. The file is kept out of git.
+#[derive(Debug)]
+pub struct RepoLock {
+    _file: File,
+}
+
+impl RepoLock {
+    pub fn acquire(repo_path: &Path) -> Result<Self> {
+        if !repo_path.is_dir() {
+            return Err(anyhow!(
+                "No dotzilla repository found at {}, please run 'dotzilla init'",
+                repo_path.display()
+            ));
+        }
+        ignore_local(repo_path, "lock")?;
+
+        let path = repo_path.join(".dotzilla").join("lock");
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock {}", path.display()))?;
+
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() != ErrorKind::WouldBlock {
+                return Err(err).with_context(|| format!("Failed to lock {}", path.display()));
+            }
+
+            let mut owner = String::new();
+            let _ = file.read_to_string(&mut owner);
+            return Err(match owner.trim() {
+                "" => anyhow!("Repository is locked ({})", path.display()),
+                pid => anyhow!("Repository is locked by PID {} ({})", pid, path.display()),
+            });
+        }
+
+        // Only written while holding the lock, so readers never act on it
+        file.set_len(0)
+            .and_then(|_| file.rewind())
+            .and_then(|_| write!(file, "{}", std::process::id()))
+            .with_context(|| format!("Failed to write lock {}", path.display()))?;
+
+        Ok(RepoLock { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_second_acquirer_is_refused() {
+        let root = Path::new("/tmp/test_dotzilla_lock");
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+
+        let first = RepoLock::acquire(root).unwrap();
+        let err = RepoLock::acquire(root).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(&format!("locked by PID {}", std::process::id()))
+        );
+
+        drop(first);
+        let second = RepoLock::acquire(root).unwrap();
+        drop(second);
+        assert_eq!(
+            fs::read_to_string(root.join(".dotzilla/.gitignore")).unwrap(),
+            "/lock\n"
+        );
+
+        assert!(RepoLock::acquire(&root.join("missing")).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
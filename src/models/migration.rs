@@ -0,0 +1,152 @@
+use anyhow::{Result, anyhow};
+use serde_json::{Value, json};
+
+/// Schema version written by this build of dotzilla
+pub const CONFIG_VERSION: u64 = 2;
+
+/// A single upgrade step of the `.dotzilla.json` schema
+pub struct Migration {
+    pub from: u64,
+    pub description: &'static str,
+    apply: fn(&mut Value) -> Result<()>,
+}
+
+/// Every migration, ordered by the version it upgrades from
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "Add the schema version field",
+        apply: migrate_v0_to_v1,
+    },
+    Migration {
+        from: 1,
+        description: "Drop the unkeyed plaintext digests of encrypted dotfiles",
+        apply: migrate_v1_to_v2,
+    },
+];
+
+/// Schema version of a raw config, configs written before versioning are version 0
+pub fn config_version(value: &Value) -> u64 {
+    value.get("version").and_then(Value::as_u64).unwrap_or(0)
+}
+
+/// Migrations that still have to run to bring a raw config up to date
+pub fn pending_migrations(value: &Value) -> Result<Vec<&'static Migration>> {
+    let version = config_version(value);
+    if version > CONFIG_VERSION {
+        return Err(anyhow!(
+            "Config schema version {} is newer than this dotzilla supports ({}), please upgrade dotzilla",
+            version,
+            CONFIG_VERSION
+        ));
+    }
+
+    Ok(MIGRATIONS.iter().filter(|m| m.from >= version).collect())
+}
+
+/// Run every pending migration on a raw config, returning how many were applied
+pub fn migrate(value: &mut Value) -> Result<usize> {
+    let pending = pending_migrations(value)?;
+
+    for migration in &pending {
+        (migration.apply)(value)
+            .map_err(|err| anyhow!("Migration from version {} failed: {}", migration.from, err))?;
+        value["version"] = json!(migration.from + 1);
+    }
+
+    Ok(pending.len())
+}
+
+fn migrate_v0_to_v1(value: &mut Value) -> Result<()> {
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Config is not a JSON object"))?;
+
+    object.entry("dotfiles").or_insert_with(|| json!({}));
+    object.entry("staged").or_insert_with(|| json!({}));
+    Ok(())
+}
+
+/// Encrypted dotfiles were tracked by a plain SHA-256 of their plaintext, which lets anyone
+/// with the manifest guess weak secrets; they get a keyed digest on their next `add` or `stage`
+fn migrate_v1_to_v2(value: &mut Value) -> Result<()> {
+    for section in ["dotfiles", "staged"] {
+        let Some(entries) = value.get_mut(section).and_then(Value::as_object_mut) else {
+            continue;
+        };
+        for entry in entries.values_mut().filter_map(Value::as_object_mut) {
+            if entry.get("encrypted").and_then(Value::as_bool) == Some(true) {
+                entry.remove("hash");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_unversioned_config() {
+        let mut value = json!({
+            "dotfiles": {
+                "~/.bashrc": { "source": "~/.bashrc", "target": "./.bashrc", "status": "Tracked" }
+            }
+        });
+
+        assert_eq!(pending_migrations(&value).unwrap().len(), 2);
+        assert_eq!(migrate(&mut value).unwrap(), 2);
+        assert_eq!(config_version(&value), CONFIG_VERSION);
+        assert!(value["staged"].is_object());
+        assert!(pending_migrations(&value).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_drops_secret_digests() {
+        let mut value = json!({
+            "version": 1,
+            "dotfiles": {
+                "~/.netrc": { "encrypted": true, "hash": "f52fbd32", "status": "Tracked" },
+                "~/.bashrc": { "hash": "0a1b2c3d", "status": "Tracked" }
+            },
+            "staged": {}
+        });
+
+        assert_eq!(migrate(&mut value).unwrap(), 1);
+        assert!(value["dotfiles"]["~/.netrc"].get("hash").is_none());
+        assert_eq!(value["dotfiles"]["~/.bashrc"]["hash"], "0a1b2c3d");
+    }
+
+    #[test]
+    fn test_newer_config_is_rejected() {
+        let value = json!({ "version": CONFIG_VERSION + 1, "dotfiles": {}, "staged": {} });
+        assert!(pending_migrations(&value).is_err());
+    }
+
+    #[test]
+    fn test_migration_is_written_by_the_next_save() {
+        let root = std::path::Path::new("/tmp/test_dotzilla_migration_save");
+        let _ = std::fs::remove_dir_all(root);
+        std::fs::create_dir_all(root).unwrap();
+        let config_path = root.join(".dotzilla.json");
+        let original = r#"{ "dotfiles": {} }"#;
+        std::fs::write(&config_path, original).unwrap();
+
+        // Loading migrates in memory only, like a read-only command or a dry run
+        let config = crate::models::Config::load(root).unwrap();
+        assert_eq!(std::fs::read_to_string(&config_path).unwrap(), original);
+        assert!(!root.join(".dotzilla.json.v0.bak").exists());
+
+        config.save().unwrap();
+        let saved: Value =
+            serde_json::from_str(&std::fs::read_to_string(&config_path).unwrap()).unwrap();
+        assert_eq!(config_version(&saved), CONFIG_VERSION);
+        assert_eq!(
+            std::fs::read_to_string(root.join(".dotzilla.json.v0.bak")).unwrap(),
+            original
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,24 +1,115 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result, anyhow};
 use serde::{Deserialize, Serialize};
 
+use crate::utils::atomic::write_atomic;
+use crate::utils::escalate;
+use crate::utils::facts;
+use crate::utils::ignore::{IGNORE_FILE, IgnoreRules};
+use crate::utils::mode::{self, Modes};
+use crate::utils::path_compare;
+
+mod backup;
+pub use backup::*;
+
+mod condition;
+pub use condition::*;
+
 mod dotfile;
 pub use dotfile::*;
 
+mod format;
+pub use format::*;
+
+mod history;
+pub use history::*;
+
+mod journal;
+pub use journal::*;
+
+mod local;
+pub use local::*;
+
+mod lock;
+pub use lock::*;
+
+mod migration;
+pub use migration::*;
+
 mod path;
 pub use path::*;
 
-#[derive(Debug, Serialize, Deserialize)]
+mod plan;
+pub use plan::*;
+
+mod profile;
+pub use profile::*;
+
+mod secret;
+pub use secret::*;
+
+mod template;
+pub use template::*;
+
+/// How `commit` records its changes in the git repository holding the dotfiles
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum GitMode {
+    /// Leave git alone
+    #[default]
+    Off,
+    /// Create a git commit for every dotzilla commit
+    Commit,
+    /// Create a git commit and push it to the upstream branch
+    Push,
+}
+
+impl GitMode {
+    pub fn is_off(&self) -> bool {
+        *self == GitMode::Off
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default)]
+    version: u64,
+    /// Create relative symlinks by default, so links survive moving the home directory
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    relative_links: bool,
+    /// Mirror dotzilla commits into the git repository holding the dotfiles
+    #[serde(default, skip_serializing_if = "GitMode::is_off")]
+    git: GitMode,
+    /// Command privileged dotfiles are changed through, `sudo` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    escalation: Option<String>,
     #[serde(skip)]
     repo_path: PathBuf,
     #[serde(skip)]
     staging_path: PathBuf,
-    dotfiles: HashMap<PathBuf, DotfileEntry>,
-    staged: HashMap<PathBuf, DotfileEntry>,
+    #[serde(skip)]
+    format: ConfigFormat,
+    #[serde(skip)]
+    active_profile: Option<String>,
+    dotfiles: BTreeMap<PathBuf, DotfileEntry>,
+    staged: BTreeMap<PathBuf, DotfileEntry>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    profiles: BTreeMap<String, Profile>,
+    /// The config file as read before it was migrated on load
+    #[serde(skip)]
+    original: Option<OriginalConfig>,
+}
+
+/// A config file from before a migration, backed up by the first save writing the
+/// migrated schema
+#[derive(Debug, Clone)]
+struct OriginalConfig {
+    path: PathBuf,
+    version: u64,
+    content: String,
 }
 
 impl Config {
@@ -26,39 +117,247 @@ impl Config {
         let staging_path = repo_path.join(".staging");
 
         Config {
+            version: CONFIG_VERSION,
+            relative_links: false,
+            git: GitMode::Off,
+            escalation: None,
             repo_path,
             staging_path,
-            dotfiles: HashMap::new(),
-            staged: HashMap::new(),
+            format: ConfigFormat::default(),
+            active_profile: None,
+            dotfiles: BTreeMap::new(),
+            staged: BTreeMap::new(),
+            profiles: BTreeMap::new(),
+            original: None,
         }
     }
 
+    pub fn repo_path(&self) -> &Path {
+        &self.repo_path
+    }
+
+    pub fn format(&self) -> ConfigFormat {
+        self.format
+    }
+
+    /// Whether `link` creates relative symlinks when not told otherwise
+    pub fn relative_links(&self) -> bool {
+        self.relative_links
+    }
+
+    pub fn set_relative_links(&mut self, relative_links: bool) {
+        self.relative_links = relative_links;
+    }
+
+    pub fn git_mode(&self) -> GitMode {
+        self.git
+    }
+
+    pub fn set_git_mode(&mut self, git: GitMode) {
+        self.git = git;
+    }
+
+    /// The command privileged dotfiles are changed through
+    pub fn escalation_command(&self) -> &str {
+        self.escalation
+            .as_deref()
+            .unwrap_or(escalate::DEFAULT_COMMAND)
+    }
+
+    pub fn set_escalation_command(&mut self, command: String) {
+        self.escalation = Some(command);
+    }
+
+    /// The escalation command to change an entry's local path through, `None` when the
+    /// entry isn't privileged or this process already runs as root
+    pub fn escalation_for(&self, entry: &DotfileEntry) -> Option<&str> {
+        (entry.privileged && !escalate::is_root()).then(|| self.escalation_command())
+    }
+
+    /// Switch the manifest format, the next save writes the new file
+    pub fn set_format(&mut self, format: ConfigFormat) {
+        self.format = format;
+    }
+
+    /// Write the config, keeping a copy of the file a migrated config was loaded from
     pub fn save(&self) -> Result<()> {
-        let config_path = self.repo_path.join(".dotzilla.json");
-        let config_str = serde_json::to_string_pretty(self)?;
-        fs::write(&config_path, config_str)
+        if let Some(original) = &self.original {
+            Config::backup(&original.path, original.version, &original.content)?;
+        }
+
+        let config_path = self.repo_path.join(self.format.file_name());
+        let existing = fs::read_to_string(&config_path).ok();
+        let config_str = self
+            .format
+            .render(self, existing.as_deref())
+            .with_context(|| format!("Failed to render config for {}", config_path.display()))?;
+        write_atomic(&config_path, config_str)
             .with_context(|| format!("Failed to write config to {}", config_path.display()))
     }
 
     pub fn load(repo_path: &Path) -> Result<Self> {
-        let config_path = repo_path.join(".dotzilla.json");
-        if !config_path.exists() {
-            return Err(anyhow!(
+        let format = ConfigFormat::detect(repo_path).ok_or_else(|| {
+            anyhow!(
                 "No dotzilla repository found at {}, please run 'dotzilla init'",
                 repo_path.display()
-            ));
-        }
+            )
+        })?;
+        let config_path = repo_path.join(format.file_name());
 
         let config_str = fs::read_to_string(&config_path)
             .with_context(|| format!("Failed to read config from {}", config_path.display()))?;
-        let mut config: Config = serde_json::from_str(&config_str)
+        let mut value = format
+            .parse(&config_str)
+            .with_context(|| format!("Failed to parse config from {}", config_path.display()))?;
+
+        let from_version = config_version(&value);
+        let migrated = migrate(&mut value)
+            .with_context(|| format!("Failed to migrate config at {}", config_path.display()))?;
+
+        let mut config: Config = serde_json::from_value(value)
             .with_context(|| format!("Failed to parse config from {}", config_path.display()))?;
 
         config.repo_path = repo_path.to_path_buf();
         config.staging_path = repo_path.join(".staging");
+        config.format = format;
+
+        // Read-only commands and dry runs leave the file alone, the next save writes the
+        // new schema
+        if migrated > 0 {
+            log::info!(
+                "Migrated {} from schema version {} to {} in memory",
+                config_path.display(),
+                from_version,
+                CONFIG_VERSION
+            );
+            config.original = Some(OriginalConfig {
+                path: config_path,
+                version: from_version,
+                content: config_str,
+            });
+        }
+
         Ok(config)
     }
 
+    /// Keep a copy of a config as it was before migrating it
+    fn backup(config_path: &Path, version: u64, config_str: &str) -> Result<()> {
+        let mut backup_path = config_path.to_path_buf();
+        backup_path.set_file_name(format!(
+            "{}.v{}.bak",
+            config_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy(),
+            version
+        ));
+        if backup_path.exists() {
+            return Ok(());
+        }
+
+        fs::write(&backup_path, config_str)
+            .with_context(|| format!("Failed to back up config to {}", backup_path.display()))
+    }
+
+    /// Rules for what to leave out of a directory dotfile: the repository's
+    /// `.dotzillaignore` followed by the entry's own patterns
+    pub fn ignore_rules(&self, patterns: &[String]) -> Result<IgnoreRules> {
+        let mut rules = IgnoreRules::from_file(&self.repo_path.join(IGNORE_FILE))?;
+        rules.extend(IgnoreRules::new(patterns)?);
+        Ok(rules)
+    }
+
+    /// Rules for walking a directory dotfile: its ignore patterns and symlink policy
+    pub fn entry_rules(&self, entry: &DotfileEntry) -> Result<IgnoreRules> {
+        Ok(self
+            .ignore_rules(&entry.ignore)?
+            .with_symlinks(entry.symlinks))
+    }
+
+    /// Files of an unfolded directory dotfile, relative to its root: everything in the
+    /// repository copy that isn't ignored
+    pub fn unfolded_files(&self, dotpath: &DotPath, entry: &DotfileEntry) -> Result<Vec<PathBuf>> {
+        let ignore = self.entry_rules(entry)?;
+        path_compare::relative_files(&dotpath.abs_target, &ignore).map_err(|err| {
+            anyhow!(
+                "Failed to list the files of {}: {}",
+                dotpath.abs_target.display(),
+                err
+            )
+        })
+    }
+
+    /// Record the modes of a dotfile's local path; for an unfolded directory only the
+    /// directory itself and its tracked files
+    pub fn capture_modes(&self, dotpath: &DotPath, entry: &DotfileEntry) -> Result<Modes> {
+        let modes = if entry.deploy == DeployMode::Unfold {
+            let files = self.unfolded_files(dotpath, entry)?;
+            mode::capture_files(&dotpath.abs_path, &dotpath.abs_target, &files)
+        } else {
+            mode::capture(&dotpath.abs_path, &self.entry_rules(entry)?)
+        };
+
+        modes.map_err(|err| {
+            anyhow!(
+                "Failed to read the modes of {}: {}",
+                dotpath.abs_path.display(),
+                err
+            )
+        })
+    }
+
+    /// Pick the active profile: the requested one, else the one listing this host
+    pub fn select_profile(&mut self, name: Option<&str>) -> Result<()> {
+        self.active_profile = match name {
+            Some(name) if self.profiles.contains_key(name) => Some(name.to_string()),
+            Some(name) => return Err(anyhow!("No such profile: {}", name)),
+            None => {
+                let hostname = facts::hostname();
+                self.profiles
+                    .iter()
+                    .find(|(_, profile)| profile.hosts.contains(&hostname))
+                    .map(|(name, _)| name.clone())
+            }
+        };
+        Ok(())
+    }
+
+    pub fn active_profile(&self) -> Option<&str> {
+        self.active_profile.as_deref()
+    }
+
+    pub fn profiles(&self) -> &BTreeMap<String, Profile> {
+        &self.profiles
+    }
+
+    pub fn set_profile(&mut self, name: &str, profile: Profile) -> Result<()> {
+        self.profiles.insert(name.to_string(), profile);
+        self.save()
+            .with_context(|| format!("Failed to save config after setting profile {}", name))
+    }
+
+    pub fn remove_profile(&mut self, name: &str) -> Result<()> {
+        if self.profiles.remove(name).is_none() {
+            return Err(anyhow!("No such profile: {}", name));
+        }
+        self.save()
+            .with_context(|| format!("Failed to save config after removing profile {}", name))
+    }
+
+    /// Tracked dotfiles that belong to the active profile
+    pub fn get_active(&self) -> HashMap<DotPath, DotfileEntry> {
+        let profile = self
+            .active_profile
+            .as_ref()
+            .and_then(|name| self.profiles.get(name));
+
+        self.dotfiles
+            .iter()
+            .filter(|(name, _)| profile.is_none_or(|profile| profile.includes(name)))
+            .map(|(k, v)| (DotPath::from_path(self, k.clone()), v.clone()))
+            .collect::<HashMap<DotPath, DotfileEntry>>()
+    }
+
     pub fn get_dotfile(&self, dotpath: &DotPath) -> Result<&DotfileEntry> {
         let dot = self
             .dotfiles
@@ -114,6 +413,12 @@ impl Config {
             .with_context(|| format!("Failed to save config after removing {}", dotpath))
     }
 
+    /// Move a staged entry into the tracked dotfiles without saving
+    pub fn promote_staged(&mut self, dotpath: &DotPath, entry: DotfileEntry) {
+        self.staged.remove(dotpath.to_name());
+        self.dotfiles.insert(dotpath.to_name().to_path_buf(), entry);
+    }
+
     pub fn get_staged(&self) -> HashMap<DotPath, DotfileEntry> {
         self.staged
             .iter()
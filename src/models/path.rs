@@ -1,9 +1,16 @@
-use std::{fmt, path::PathBuf};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
 
 use crate::utils;
 
 use super::Config;
 
+/// Directory of the repository holding dotfiles from outside the home directory,
+/// e.g. `root/etc/hosts` for `/etc/hosts`
+pub const SYSTEM_DIR: &str = "root";
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct DotPath {
     pub rel_path: PathBuf,
@@ -19,8 +26,13 @@ impl DotPath {
         let binding = String::from(name).trim_end_matches('/').to_string();
         let trimmed_name = binding.as_str();
 
+        let rel_path = utils::reduce_path_to_home(trimmed_name);
+        if rel_path.is_absolute() {
+            return DotPath::system(config, rel_path);
+        }
+
         DotPath {
-            rel_path: utils::reduce_path_to_home(trimmed_name),
+            rel_path,
             abs_path: utils::get_full_path(trimmed_name),
             target: utils::replace_home(trimmed_name, "."),
             abs_target: utils::replace_home(trimmed_name, config.repo_path.to_str().unwrap()),
@@ -33,6 +45,32 @@ impl DotPath {
         }
     }
 
+    /// A path outside the home directory, kept under [`SYSTEM_DIR`] in the repository
+    fn system(config: &Config, abs_path: PathBuf) -> Self {
+        let inner = abs_path.strip_prefix("/").unwrap_or(&abs_path);
+
+        DotPath {
+            rel_path: abs_path.clone(),
+            abs_path: abs_path.clone(),
+            target: Path::new(".").join(SYSTEM_DIR).join(inner),
+            abs_target: config.repo_path.join(SYSTEM_DIR).join(inner),
+            target_staged: config.staging_path.join(SYSTEM_DIR).join(inner),
+            abs_target_staged: utils::get_full_path(
+                config
+                    .staging_path
+                    .join(SYSTEM_DIR)
+                    .join(inner)
+                    .to_string_lossy()
+                    .as_ref(),
+            ),
+        }
+    }
+
+    /// Whether the dotfile lives outside the home directory
+    pub fn is_system(&self) -> bool {
+        self.rel_path.is_absolute()
+    }
+
     pub fn from_path(config: &Config, path: PathBuf) -> Self {
         DotPath::new(config, path.to_str().unwrap())
     }
@@ -47,3 +85,34 @@ impl fmt::Display for DotPath {
         write!(f, "{}", self.rel_path.display())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_path_mapping() {
+        let config = Config::new(PathBuf::from("/tmp/test_dotzilla_repo"));
+
+        let dotpath = DotPath::new(&config, "/etc/keyd/default.conf/");
+        assert!(dotpath.is_system());
+        assert_eq!(dotpath.to_name(), Path::new("/etc/keyd/default.conf"));
+        assert_eq!(dotpath.abs_path, Path::new("/etc/keyd/default.conf"));
+        assert_eq!(dotpath.target, Path::new("./root/etc/keyd/default.conf"));
+        assert_eq!(
+            dotpath.abs_target,
+            Path::new("/tmp/test_dotzilla_repo/root/etc/keyd/default.conf")
+        );
+        assert_eq!(
+            dotpath.abs_target_staged,
+            Path::new("/tmp/test_dotzilla_repo/.staging/root/etc/keyd/default.conf")
+        );
+
+        let home = DotPath::new(&config, "~/.bashrc");
+        assert!(!home.is_system());
+        assert_eq!(
+            home.abs_target,
+            Path::new("/tmp/test_dotzilla_repo/.bashrc")
+        );
+    }
+}
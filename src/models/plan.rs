@@ -0,0 +1,733 @@
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{self as unix_fs, DirBuilderExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, anyhow};
+use colored::*;
+
+use super::{
+    BackupStore, DotPath, deploy_secret, encrypt_dotfile, rendered_path, save_rendered, secret_key,
+};
+use crate::utils;
+use crate::utils::atomic::{write_atomic, write_atomic_private};
+use crate::utils::escalate;
+use crate::utils::ignore::IgnoreRules;
+use crate::utils::mode::{self, Modes};
+use crate::utils::path_compare;
+use crate::utils::walk::{EntryKind, SymlinkPolicy, walk};
+
+/// A single change to the file system
+pub enum Operation {
+    /// Create a directory and any missing parents
+    CreateDir(PathBuf),
+    /// Copy a file or directory, merging into a directory that already exists and
+    /// leaving out the paths inside it matched by `ignore`
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+        ignore: IgnoreRules,
+    },
+    /// Move a file or directory, creating the parent directories of `to`
+    Rename { from: PathBuf, to: PathBuf },
+    /// Create a symlink at `link`, expressed relative to its directory when `relative`
+    Symlink {
+        link: PathBuf,
+        target: PathBuf,
+        relative: bool,
+    },
+    /// Create a hard link at `link` to the file `target`
+    HardLink { link: PathBuf, target: PathBuf },
+    /// Delete a file, symlink or directory tree
+    Delete(PathBuf),
+    /// Write generated content, readable only by the owner when `private`
+    Write {
+        path: PathBuf,
+        contents: Vec<u8>,
+        private: bool,
+    },
+    /// Store the rendered output of a template dotfile in the repository
+    Render {
+        repo: PathBuf,
+        dotpath: DotPath,
+        contents: Vec<u8>,
+    },
+    /// Encrypt a local file into the repository
+    Encrypt { from: PathBuf, to: PathBuf },
+    /// Move the local path of a dotfile into the repository's backup store
+    Backup {
+        repo: PathBuf,
+        dotpath: DotPath,
+        encrypted: bool,
+    },
+    /// Put recorded modes back on the files under `path`, following symlinks
+    SetModes { path: PathBuf, modes: Modes },
+    /// Perform an operation on paths only root may change, through an escalation
+    /// command such as `sudo`
+    Escalated {
+        command: String,
+        operation: Box<Operation>,
+    },
+}
+
+impl Operation {
+    /// Perform the operation and describe what was done
+    pub fn apply(&self) -> Result<String> {
+        match self {
+            Operation::CreateDir(path) => {
+                fs::create_dir_all(path)
+                    .with_context(|| format!("Failed to create directory {}", path.display()))?;
+                Ok(format!("Created directory {}", path.display()))
+            }
+            Operation::Copy { from, to, ignore } => {
+                copy_path(from, to, ignore)?;
+                Ok(format!("Copied {} -> {}", from.display(), to.display()))
+            }
+            Operation::Rename { from, to } => {
+                if let Some(parent) = to.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::rename(from, to).with_context(|| {
+                    format!("Failed to move {} -> {}", from.display(), to.display())
+                })?;
+                Ok(format!("Moved {} -> {}", from.display(), to.display()))
+            }
+            Operation::Symlink {
+                link,
+                target,
+                relative,
+            } => {
+                let value = symlink_value(link, target, *relative)?;
+                unix_fs::symlink(&value, link)
+                    .with_context(|| format!("Failed to create symlink at {}", link.display()))?;
+                Ok(format!("Linked {} -> {}", link.display(), value.display()))
+            }
+            Operation::HardLink { link, target } => {
+                fs::hard_link(target, link)
+                    .with_context(|| format!("Failed to create hard link at {}", link.display()))?;
+                Ok(format!(
+                    "Hard linked {} -> {}",
+                    link.display(),
+                    target.display()
+                ))
+            }
+            Operation::Delete(path) => {
+                let metadata = fs::symlink_metadata(path)
+                    .with_context(|| format!("Nothing to delete at {}", path.display()))?;
+                if metadata.is_dir() {
+                    fs::remove_dir_all(path)
+                } else {
+                    fs::remove_file(path)
+                }
+                .with_context(|| format!("Failed to delete {}", path.display()))?;
+                Ok(format!("Deleted {}", path.display()))
+            }
+            Operation::Write {
+                path,
+                contents,
+                private,
+            } => {
+                if *private {
+                    deploy_secret(path, contents)?;
+                } else {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    write_atomic(path, contents)
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                }
+                Ok(format!("Wrote {}", path.display()))
+            }
+            Operation::Render {
+                repo,
+                dotpath,
+                contents,
+            } => {
+                let path = save_rendered(repo, dotpath, contents)?;
+                Ok(format!("Rendered {} -> {}", dotpath, path.display()))
+            }
+            Operation::Encrypt { from, to } => {
+                encrypt_dotfile(from, to)?;
+                Ok(format!("Encrypted {} -> {}", from.display(), to.display()))
+            }
+            Operation::Backup {
+                repo,
+                dotpath,
+                encrypted,
+            } => {
+                let record = BackupStore::new(repo).backup(dotpath, *encrypted, None)?;
+                Ok(format!(
+                    "Backed up {} [{}]",
+                    dotpath.abs_path.display(),
+                    record.id
+                ))
+            }
+            Operation::SetModes { path, modes } => {
+                let changed = mode::restore(path, modes)
+                    .with_context(|| format!("Failed to restore modes under {}", path.display()))?;
+                Ok(format!(
+                    "Restored modes of {} ({} changed)",
+                    path.display(),
+                    changed
+                ))
+            }
+            Operation::Escalated { command, operation } => {
+                apply_escalated(command, operation)?;
+                Ok(format!(
+                    "{} (via {})",
+                    capitalize(&operation.to_string()),
+                    command
+                ))
+            }
+        }
+    }
+
+    /// Every path outside the repository the operation reads or changes
+    fn local_paths(&self, repo: &Path) -> Vec<&Path> {
+        let paths: Vec<&Path> = match self {
+            Operation::CreateDir(path)
+            | Operation::Delete(path)
+            | Operation::SetModes { path, .. } => vec![path],
+            Operation::Copy { from, to, .. }
+            | Operation::Rename { from, to }
+            | Operation::Encrypt { from, to } => vec![from, to],
+            Operation::Symlink { link, .. } | Operation::HardLink { link, .. } => vec![link],
+            Operation::Write { path, .. } => vec![path],
+            Operation::Backup { dotpath, .. } => vec![&dotpath.abs_path],
+            Operation::Render { .. } | Operation::Escalated { .. } => Vec::new(),
+        };
+
+        paths
+            .into_iter()
+            .filter(|path| !path.starts_with(repo))
+            .collect()
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::CreateDir(path) => write!(f, "create directory {}", path.display()),
+            Operation::Copy { from, to, ignore } => {
+                write!(f, "copy {} -> {}", from.display(), to.display())?;
+                if !ignore.is_empty() && from.is_dir() {
+                    write!(f, " (skipping ignored paths)")?;
+                }
+                Ok(())
+            }
+            Operation::Rename { from, to } => {
+                write!(f, "move {} -> {}", from.display(), to.display())
+            }
+            Operation::Symlink {
+                link,
+                target,
+                relative,
+            } => {
+                write!(f, "symlink {} -> {}", link.display(), target.display())?;
+                if *relative {
+                    write!(f, " (relative)")?;
+                }
+                Ok(())
+            }
+            Operation::HardLink { link, target } => {
+                write!(f, "hard link {} -> {}", link.display(), target.display())
+            }
+            Operation::Delete(path) => write!(f, "delete {}", path.display()),
+            Operation::Write { path, private, .. } => {
+                write!(f, "write {}", path.display())?;
+                if *private {
+                    write!(f, " (private)")?;
+                }
+                Ok(())
+            }
+            Operation::Render { repo, dotpath, .. } => write!(
+                f,
+                "render {} -> {}",
+                dotpath,
+                rendered_path(repo, dotpath).display()
+            ),
+            Operation::Encrypt { from, to } => {
+                write!(f, "encrypt {} -> {}", from.display(), to.display())
+            }
+            Operation::Backup { dotpath, .. } => {
+                write!(f, "back up {}", dotpath.abs_path.display())
+            }
+            Operation::SetModes { path, .. } => write!(f, "restore modes of {}", path.display()),
+            Operation::Escalated { command, operation } => {
+                write!(f, "{} (via {})", operation, command)
+            }
+        }
+    }
+}
+
+/// File system operations a command intends to perform, in order
+///
+/// Commands build a plan first and then either print it for `--dry-run` or
+/// execute it, so a dry run shows exactly what the real run does.
+#[derive(Default)]
+pub struct Plan {
+    operations: Vec<Operation>,
+}
+
+impl Plan {
+    pub fn new() -> Self {
+        Plan::default()
+    }
+
+    pub fn push(&mut self, operation: Operation) {
+        self.operations.push(operation);
+    }
+
+    pub fn extend(&mut self, other: Plan) {
+        self.operations.extend(other.operations);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Run every operation touching paths outside the repository through `command`
+    pub fn escalate(&mut self, repo: &Path, command: &str) {
+        for operation in &mut self.operations {
+            if operation.local_paths(repo).is_empty() {
+                continue;
+            }
+
+            let inner = std::mem::replace(operation, Operation::CreateDir(PathBuf::new()));
+            *operation = Operation::Escalated {
+                command: command.to_string(),
+                operation: Box::new(inner),
+            };
+        }
+    }
+
+    /// Print the plan without touching the file system
+    pub fn print(&self) {
+        if self.operations.is_empty() {
+            println!("{} Dry run, nothing to do", "-".dimmed());
+            return;
+        }
+
+        println!(
+            "{} Dry run, nothing was changed. Planned operations:",
+            "-".dimmed()
+        );
+        self.print_operations();
+    }
+
+    /// Print each operation on its own indented line
+    pub fn print_operations(&self) {
+        for operation in &self.operations {
+            println!("  {}", operation);
+        }
+    }
+
+    /// Perform every operation in order, stopping at the first failure
+    pub fn execute(&self) -> Result<()> {
+        for operation in &self.operations {
+            let done = operation.apply()?;
+            println!("{} {}", "✓".green(), done);
+        }
+        Ok(())
+    }
+}
+
+/// What a symlink at `link` should contain to point at `source`
+///
+/// Relative values are computed between canonical paths, so they resolve correctly
+/// even when the link's directory is reached through another symlink.
+pub fn symlink_value(link: &Path, source: &Path, relative: bool) -> Result<PathBuf> {
+    if !relative {
+        return Ok(source.to_path_buf());
+    }
+
+    let parent = link
+        .parent()
+        .ok_or_else(|| anyhow!("{} has no parent directory", link.display()))?;
+    let parent = fs::canonicalize(parent)
+        .with_context(|| format!("Failed to resolve directory {}", parent.display()))?;
+    let source = fs::canonicalize(source)
+        .with_context(|| format!("Failed to resolve {}", source.display()))?;
+
+    Ok(utils::relative_path(&parent, &source))
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Perform an operation with the standard tools run through the escalation command
+fn apply_escalated(command: &str, operation: &Operation) -> Result<()> {
+    match operation {
+        Operation::CreateDir(path) => {
+            escalate::run(command, &[&"mkdir", &"-p", &"--", path], None)?;
+        }
+        Operation::Copy { from, to, ignore } => copy_escalated(command, from, to, ignore)?,
+        Operation::Rename { from, to } => {
+            if let Some(parent) = to.parent() {
+                escalate::run(command, &[&"mkdir", &"-p", &"--", &parent], None)?;
+            }
+            escalate::run(command, &[&"mv", &"--", from, to], None)?;
+        }
+        Operation::Symlink {
+            link,
+            target,
+            relative,
+        } => {
+            let value = symlink_value(link, target, *relative)?;
+            escalate::run(command, &[&"ln", &"-s", &"--", &value, link], None)?;
+        }
+        Operation::HardLink { link, target } => {
+            escalate::run(command, &[&"ln", &"--", target, link], None)?;
+        }
+        Operation::Delete(path) => {
+            escalate::run(command, &[&"rm", &"-rf", &"--", path], None)?;
+        }
+        Operation::Write {
+            path,
+            contents,
+            private,
+        } => {
+            if let Some(parent) = path.parent()
+                && !parent.exists()
+            {
+                let mode = if *private { "700" } else { "755" };
+                escalate::run(
+                    command,
+                    &[&"mkdir", &"-p", &"-m", &mode, &"--", &parent],
+                    None,
+                )?;
+            }
+            if *private {
+                // Create the file readable by root only before any content lands in it
+                escalate::run(
+                    command,
+                    &[&"install", &"-m", &"600", &"/dev/null", path],
+                    None,
+                )?;
+            }
+            escalate::run(command, &[&"tee", &"--", path], Some(contents))?;
+        }
+        // Rendered output stays in the repository, where no escalation is needed
+        Operation::Render { .. } => {
+            operation.apply()?;
+        }
+        Operation::Encrypt { from, to } => {
+            let plaintext = escalate::run(command, &[&"cat", &"--", from], None)?;
+            write_atomic(to, secret_key()?.encrypt(&plaintext)?)
+                .with_context(|| format!("Failed to write encrypted file {}", to.display()))?;
+        }
+        Operation::Backup {
+            repo,
+            dotpath,
+            encrypted,
+        } => {
+            BackupStore::new(repo).backup(dotpath, *encrypted, Some(command))?;
+        }
+        Operation::SetModes { path, modes } => {
+            for change in mode::changes(path, modes) {
+                let file = mode::resolve(path, &change.path);
+                if change.mode_differs() {
+                    let bits = format!("{:04o}", change.expected.mode);
+                    escalate::run(command, &[&"chmod", &bits, &"--", &file], None)?;
+                }
+                if let Some(owner) = change.owner_differs() {
+                    escalate::run(command, &[&"chown", &owner, &"--", &file], None)?;
+                }
+            }
+        }
+        Operation::Escalated { operation, .. } => apply_escalated(command, operation)?,
+    }
+
+    Ok(())
+}
+
+/// Copy into a path only root may write file by file, or out of a path only root may
+/// read as one archive
+///
+/// What is read out is only readable by the user: a file is written with 0600 and a
+/// directory is unpacked into one created with 0700, which takes the mode of the source
+/// directory from the archive.
+fn copy_escalated(command: &str, from: &Path, to: &Path, ignore: &IgnoreRules) -> Result<()> {
+    if escalate::needs_root(to) {
+        if !from.is_dir() {
+            escalate::run(command, &[&"cp", &"--", &from, &to], None)?;
+            return Ok(());
+        }
+
+        let links = if ignore.symlinks() == SymlinkPolicy::Dereference {
+            "-L"
+        } else {
+            "-P"
+        };
+        escalate::run(command, &[&"mkdir", &"-p", &"--", &to], None)?;
+        let files = path_compare::relative_files(from, ignore)
+            .map_err(|err| anyhow!("Failed to list {}: {}", from.display(), err))?;
+        for file in files {
+            let dest = to.join(&file);
+            if let Some(parent) = dest.parent() {
+                escalate::run(command, &[&"mkdir", &"-p", &"--", &parent], None)?;
+            }
+            escalate::run(
+                command,
+                &[&"cp", &links, &"--", &from.join(&file), &dest],
+                None,
+            )?;
+        }
+        return Ok(());
+    }
+
+    if !from.is_dir() {
+        let content = escalate::run(command, &[&"cat", &"--", &from], None)?;
+        write_atomic_private(to, content)
+            .with_context(|| format!("Failed to write {}", to.display()))?;
+        return Ok(());
+    }
+
+    let dereference = if ignore.symlinks() == SymlinkPolicy::Dereference {
+        "-chf"
+    } else {
+        "-cf"
+    };
+    let archive = escalate::run(
+        command,
+        &[&"tar", &"-C", &from, &dereference, &"-", &"."],
+        None,
+    )?;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(to)
+        .with_context(|| format!("Failed to create directory {}", to.display()))?;
+    let mut tar = Command::new("tar")
+        .arg("-C")
+        .arg(to)
+        .args(["-xf", "-"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run tar")?;
+    if let Some(mut stdin) = tar.stdin.take() {
+        stdin
+            .write_all(&archive)
+            .context("Failed to write to tar")?;
+    }
+    if !tar.wait().context("Failed to run tar")?.success() {
+        return Err(anyhow!(
+            "Failed to unpack {} into {}",
+            from.display(),
+            to.display()
+        ));
+    }
+
+    remove_untracked(to, to, ignore)
+}
+
+/// Delete what the walk of `root` leaves out under `dir`: paths `rules` ignore,
+/// symlinks it skips, sockets and FIFOs
+fn remove_untracked(root: &Path, dir: &Path, rules: &IgnoreRules) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        let untracked = rules.is_ignored(path.strip_prefix(root)?, file_type.is_dir())
+            || (file_type.is_symlink() && rules.symlinks() == SymlinkPolicy::Skip)
+            || !(file_type.is_dir() || file_type.is_file() || file_type.is_symlink());
+
+        if untracked && file_type.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else if untracked {
+            fs::remove_file(&path)?;
+        } else if file_type.is_dir() {
+            remove_untracked(root, &path, rules)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn copy_path(from: &Path, to: &Path, ignore: &IgnoreRules) -> Result<()> {
+    if from.is_dir() {
+        copy_dir(from, to, ignore).with_context(|| {
+            format!(
+                "Failed to copy directory from {} to {}",
+                from.display(),
+                to.display()
+            )
+        })?;
+    } else {
+        fs::copy(from, to).with_context(|| {
+            format!(
+                "Failed to copy file from {} to {}",
+                from.display(),
+                to.display()
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Copy the tree under `from` into `to`, leaving out and keeping symlinks as `rules` say
+pub(super) fn copy_dir(from: &Path, to: &Path, rules: &IgnoreRules) -> Result<()> {
+    fs::create_dir_all(to)?;
+
+    for entry in walk(from, rules)? {
+        let path = from.join(&entry.relative);
+        let dest = to.join(&entry.relative);
+        match entry.kind {
+            EntryKind::Dir => fs::create_dir_all(&dest)?,
+            EntryKind::File => {
+                fs::copy(&path, &dest)
+                    .with_context(|| format!("Failed to copy {}", path.display()))?;
+            }
+            EntryKind::Symlink(value) => {
+                // Merging into an existing copy replaces what is in the way
+                if fs::symlink_metadata(&dest).is_ok_and(|metadata| !metadata.is_dir()) {
+                    fs::remove_file(&dest)?;
+                }
+                unix_fs::symlink(&value, &dest)
+                    .with_context(|| format!("Failed to copy symlink {}", path.display()))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_execute() {
+        let root = Path::new("/tmp/test_dotzilla_plan");
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+        fs::write(root.join("source"), "content").unwrap();
+
+        let mut plan = Plan::new();
+        plan.push(Operation::CreateDir(root.join("dir")));
+        plan.push(Operation::Copy {
+            from: root.join("source"),
+            to: root.join("dir").join("copy"),
+            ignore: IgnoreRules::default(),
+        });
+        plan.push(Operation::Rename {
+            from: root.join("dir").join("copy"),
+            to: root.join("moved").join("copy"),
+        });
+        plan.push(Operation::Symlink {
+            link: root.join("link"),
+            target: root.join("moved").join("copy"),
+            relative: true,
+        });
+        plan.push(Operation::Delete(root.join("dir")));
+
+        assert_eq!(
+            plan.operations[3].to_string(),
+            format!(
+                "symlink {}/link -> {}/moved/copy (relative)",
+                root.display(),
+                root.display()
+            )
+        );
+
+        plan.execute().unwrap();
+
+        assert!(!root.join("dir").exists());
+        assert_eq!(
+            fs::read_link(root.join("link")).unwrap(),
+            PathBuf::from("moved/copy")
+        );
+        assert_eq!(fs::read_to_string(root.join("link")).unwrap(), "content");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_escalated_reads_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = Path::new("/tmp/test_dotzilla_plan_escalated");
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root.join("etc/sudoers.d")).unwrap();
+        fs::write(root.join("etc/sudoers"), "root ALL=(ALL) ALL").unwrap();
+        fs::write(root.join("etc/sudoers.d/extra"), "").unwrap();
+        fs::set_permissions(root.join("etc/sudoers"), fs::Permissions::from_mode(0o644)).unwrap();
+        fs::set_permissions(
+            root.join("etc/sudoers.d"),
+            fs::Permissions::from_mode(0o750),
+        )
+        .unwrap();
+
+        // `env` runs the programs as they are, standing in for sudo
+        let rules = IgnoreRules::default();
+        copy_escalated("env", &root.join("etc/sudoers"), &root.join("file"), &rules).unwrap();
+        copy_escalated(
+            "env",
+            &root.join("etc/sudoers.d"),
+            &root.join("dir"),
+            &rules,
+        )
+        .unwrap();
+
+        let mode = |path: &str| fs::metadata(root.join(path)).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode("file"), 0o600);
+        assert_eq!(mode("dir"), 0o750);
+        assert!(root.join("dir/extra").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_copy_dir_symlink_policies() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let root = Path::new("/tmp/test_dotzilla_plan_copy_dir");
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root.join("from/themes")).unwrap();
+        fs::write(root.join("from/themes/dark.toml"), "dark").unwrap();
+        unix_fs::symlink("themes/dark.toml", root.join("from/current.toml")).unwrap();
+        let fifo = CString::new(root.join("from/pipe").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+
+        let copy = |policy, to: &str| {
+            let rules = IgnoreRules::default().with_symlinks(policy);
+            copy_dir(&root.join("from"), &root.join(to), &rules).unwrap();
+            root.join(to)
+        };
+
+        let preserved = copy(SymlinkPolicy::Preserve, "preserve");
+        assert_eq!(
+            fs::read_link(preserved.join("current.toml")).unwrap(),
+            PathBuf::from("themes/dark.toml")
+        );
+
+        let dereferenced = copy(SymlinkPolicy::Dereference, "dereference");
+        assert!(!dereferenced.join("current.toml").is_symlink());
+        assert_eq!(
+            fs::read_to_string(dereferenced.join("current.toml")).unwrap(),
+            "dark"
+        );
+
+        let skipped = copy(SymlinkPolicy::Skip, "skip");
+        assert!(fs::symlink_metadata(skipped.join("current.toml")).is_err());
+        assert_eq!(
+            fs::read_to_string(skipped.join("themes/dark.toml")).unwrap(),
+            "dark"
+        );
+
+        for copied in [preserved, dereferenced, skipped] {
+            assert!(fs::symlink_metadata(copied.join("pipe")).is_err());
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,46 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Named subset of the tracked dotfiles, e.g. `work` or `server`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    /// Hostnames that select this profile when no `--profile` is given
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    /// Dotfiles (or parent directories) in this profile, everything when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<PathBuf>,
+    /// Dotfiles (or parent directories) left out of this profile
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<PathBuf>,
+}
+
+impl Profile {
+    /// Whether a dotfile, by name, belongs to this profile
+    pub fn includes(&self, name: &Path) -> bool {
+        let included =
+            self.include.is_empty() || self.include.iter().any(|path| name.starts_with(path));
+        included && !self.exclude.iter().any(|path| name.starts_with(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_includes() {
+        let profile = Profile {
+            hosts: vec![],
+            include: vec![PathBuf::from("~/.config"), PathBuf::from("~/.bashrc")],
+            exclude: vec![PathBuf::from("~/.config/Code")],
+        };
+
+        assert!(profile.includes(Path::new("~/.bashrc")));
+        assert!(profile.includes(Path::new("~/.config/nvim")));
+        assert!(!profile.includes(Path::new("~/.config/Code")));
+        assert!(!profile.includes(Path::new("~/.zshrc")));
+        assert!(Profile::default().includes(Path::new("~/.zshrc")));
+    }
+}
@@ -0,0 +1,105 @@
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+
+use crate::utils::atomic::{write_atomic, write_atomic_private};
+use crate::utils::crypto::{self, SecretKey};
+
+static SECRET_KEY: OnceLock<SecretKey> = OnceLock::new();
+static DIGEST_KEY: OnceLock<[u8; 32]> = OnceLock::new();
+
+/// Key used for encrypted dotfiles, resolved once per run
+///
+/// The age identity file named by `DOTZILLA_KEY_FILE` is used first, then
+/// `~/.config/dotzilla/key.txt` if it exists. Otherwise the passphrase comes from
+/// `DOTZILLA_PASSPHRASE` or is asked for on the terminal.
+pub fn secret_key() -> Result<&'static SecretKey> {
+    if let Some(key) = SECRET_KEY.get() {
+        return Ok(key);
+    }
+
+    let key_file = std::env::var_os("DOTZILLA_KEY_FILE")
+        .map(PathBuf::from)
+        .or_else(|| {
+            home::home_dir()
+                .map(|home| home.join(".config").join("dotzilla").join("key.txt"))
+                .filter(|path| path.exists())
+        });
+
+    let key = match key_file {
+        Some(path) => SecretKey::from_key_file(&path)?,
+        None => {
+            let passphrase = match std::env::var("DOTZILLA_PASSPHRASE") {
+                Ok(passphrase) => passphrase,
+                Err(_) => rpassword::prompt_password("Passphrase for encrypted dotfiles: ")
+                    .context("Failed to read passphrase")?,
+            };
+            SecretKey::Passphrase(passphrase.into())
+        }
+    };
+
+    Ok(SECRET_KEY.get_or_init(|| key))
+}
+
+/// Use a fresh identity as the secret key of this run, for tests that never prompt
+#[cfg(test)]
+pub fn use_test_key() {
+    SECRET_KEY.get_or_init(|| SecretKey::Identity(age::x25519::Identity::generate()));
+}
+
+/// Digest an encrypted dotfile is tracked by, computed from its plaintext
+///
+/// It is keyed by the secret key and bound to the dotfile's name, so the shared manifest
+/// neither helps guess a secret offline nor shows that two secrets are equal.
+pub fn secret_digest(name: &Path, plaintext: &[u8]) -> Result<String> {
+    let key = match DIGEST_KEY.get() {
+        Some(key) => key,
+        None => {
+            let key = secret_key()?.digest_key()?;
+            DIGEST_KEY.get_or_init(|| key)
+        }
+    };
+    Ok(crypto::keyed_digest(key, name, plaintext))
+}
+
+/// Encrypt a local file into the repository
+pub fn encrypt_dotfile(source: &Path, dest: &Path) -> Result<()> {
+    let plaintext =
+        fs::read(source).with_context(|| format!("Failed to read {}", source.display()))?;
+    let ciphertext = secret_key()?.encrypt(&plaintext)?;
+
+    write_atomic(dest, ciphertext)
+        .with_context(|| format!("Failed to write encrypted file {}", dest.display()))
+}
+
+/// Decrypt an encrypted repository file in memory
+pub fn decrypt_dotfile(path: &Path) -> Result<Vec<u8>> {
+    let ciphertext =
+        fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    secret_key()?
+        .decrypt(&ciphertext)
+        .with_context(|| format!("Failed to decrypt {}", path.display()))
+}
+
+/// Write the plaintext of an encrypted dotfile, readable only by the owner
+///
+/// Missing parent directories are created with 0700 so the plaintext never sits in a
+/// directory other users can list.
+pub fn deploy_secret(path: &Path, plaintext: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent()
+        && !parent.exists()
+    {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+
+    write_atomic_private(path, plaintext)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use super::{DotPath, ignore_local};
+use crate::utils::{atomic::write_atomic, facts, template};
+
+/// Template variables of a repository
+///
+/// Names are resolved from `.dotzilla/vars/<hostname>.toml`, then
+/// `.dotzilla/vars/default.toml`, then the built-in facts `hostname`, `user`,
+/// `os` and `arch`. `env.<NAME>` reads an environment variable.
+pub struct Variables {
+    values: BTreeMap<String, String>,
+}
+
+impl Variables {
+    pub fn load(repo_path: &Path) -> Result<Self> {
+        let vars_path = repo_path.join(".dotzilla").join("vars");
+        let mut values = BTreeMap::new();
+
+        for file_name in [
+            "default.toml".to_string(),
+            format!("{}.toml", facts::hostname()),
+        ] {
+            let path = vars_path.join(file_name);
+            if !path.exists() {
+                continue;
+            }
+
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read variables from {}", path.display()))?;
+            let vars: BTreeMap<String, serde_json::Value> = toml_edit::de::from_str(&content)
+                .with_context(|| format!("Failed to parse variables from {}", path.display()))?;
+
+            for (name, value) in vars {
+                let value = match value {
+                    serde_json::Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                values.insert(name, value);
+            }
+        }
+
+        Ok(Variables { values })
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<String> {
+        if let Some(var) = name.strip_prefix("env.") {
+            return std::env::var(var).ok();
+        }
+
+        self.values.get(name).cloned().or_else(|| match name {
+            "hostname" => Some(facts::hostname()),
+            "user" => Some(facts::user()),
+            "os" => Some(facts::os().to_string()),
+            "arch" => Some(facts::arch().to_string()),
+            _ => None,
+        })
+    }
+}
+
+fn rendered_dir(repo_path: &Path) -> PathBuf {
+    repo_path.join(".dotzilla").join("rendered")
+}
+
+/// Where the rendered output of a template dotfile is generated
+pub fn rendered_path(repo_path: &Path, dotpath: &DotPath) -> PathBuf {
+    rendered_dir(repo_path).join(dotpath.target.strip_prefix(".").unwrap_or(&dotpath.target))
+}
+
+/// Render the repository copy of a template dotfile in memory
+pub fn render_dotfile(repo_path: &Path, dotpath: &DotPath) -> Result<String> {
+    let content = fs::read_to_string(&dotpath.abs_target)
+        .with_context(|| format!("Failed to read template {}", dotpath.abs_target.display()))?;
+    let variables = Variables::load(repo_path)?;
+
+    template::render(&content, |name| variables.lookup(name))
+        .with_context(|| format!("Failed to render template {}", dotpath))
+}
+
+/// Render a template dotfile into the generated directory and return the output path
+pub fn write_rendered(repo_path: &Path, dotpath: &DotPath) -> Result<PathBuf> {
+    let rendered = render_dotfile(repo_path, dotpath)?;
+    save_rendered(repo_path, dotpath, rendered.as_bytes())
+}
+
+/// Store the rendered output of a template dotfile and return its path
+///
+/// Values filled in from the variables can be secrets, so the generated directory
+/// is only readable by its owner and kept out of git.
+pub fn save_rendered(repo_path: &Path, dotpath: &DotPath, contents: &[u8]) -> Result<PathBuf> {
+    let dir = rendered_dir(repo_path);
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .and_then(|_| fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)))
+        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    ignore_local(repo_path, "rendered")?;
+
+    let path = rendered_path(repo_path, dotpath);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_atomic(&path, contents)
+        .with_context(|| format!("Failed to write rendered template {}", path.display()))?;
+
+    Ok(path)
+}
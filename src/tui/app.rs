@@ -1,4 +1,4 @@
-use crate::models::{Config, DotPath, DotfileEntry};
+use crate::models::{Config, DotPath, DotfileEntry, RepoLock};
 use anyhow::Result;
 
 pub type AppResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
@@ -188,14 +188,23 @@ impl App {
         self.error_message = None;
     }
 
+    /// Lock the repository and reload the config so changes apply to the latest state
+    fn lock(&mut self) -> Result<RepoLock> {
+        let repo_path = std::path::Path::new(&self.repo_path);
+        let lock = RepoLock::acquire(repo_path)?;
+        self.config = Config::load(repo_path)?;
+        Ok(lock)
+    }
+
     pub fn add_dotfile(&mut self, path: String) -> Result<()> {
+        let _lock = self.lock()?;
         let dot_path = DotPath::new(&self.config, &path);
-        let config = Config::load(&std::path::Path::new(&self.repo_path))?;
-        crate::commands::add_dotfile(config, dot_path)?;
+        crate::commands::add_dotfile(self.config.clone(), dot_path, Default::default(), false)?;
         Ok(())
     }
 
     pub fn remove_selected(&mut self) -> Result<()> {
+        let _lock = self.lock()?;
         match self.current_tab {
             Tab::Tracked => {
                 if let Some((dot_path, _)) = self.tracked_dotfiles.get(self.selected_tracked) {
@@ -217,6 +226,7 @@ impl App {
     }
 
     pub fn toggle_stage_selected(&mut self) -> Result<()> {
+        let _lock = self.lock()?;
         match self.current_tab {
             Tab::Tracked => {
                 if let Some((dot_path, entry)) = self.tracked_dotfiles.get(self.selected_tracked) {
@@ -238,21 +248,30 @@ impl App {
     }
 
     pub fn link_staged(&mut self) -> Result<()> {
-        crate::commands::link_dotfiles(&self.config, None)?;
+        let _lock = self.lock()?;
+        crate::commands::link_dotfiles(
+            &mut self.config,
+            None,
+            None,
+            crate::commands::ConflictPolicy::Backup,
+            false,
+        )?;
         self.message = Some("Linked all staged dotfiles".to_string());
         self.error_message = None;
         Ok(())
     }
 
     pub fn unlink_all(&mut self) -> Result<()> {
-        crate::commands::unlink_dotfiles(&self.config, None)?;
+        let _lock = self.lock()?;
+        crate::commands::unlink_dotfiles(&self.config, None, false)?;
         self.message = Some("Unlinked all dotfiles".to_string());
         self.error_message = None;
         Ok(())
     }
 
     pub fn commit_staged(&mut self) -> Result<()> {
-        crate::commands::commit_dotfiles(&mut self.config)?;
+        let _lock = self.lock()?;
+        crate::commands::commit_dotfiles(&mut self.config, None, false)?;
         self.message = Some("Committed all staged dotfiles".to_string());
         self.error_message = None;
         self.refresh()?;
@@ -6,13 +6,23 @@ use crossterm::{
 };
 use ratatui::{Terminal, backend::CrosstermBackend};
 use std::io;
+use std::path::Path;
 
 mod app;
 mod ui;
 
 use app::{App, AppResult, DialogState};
 
+use crate::models::RepoLock;
+
 pub fn run(repo_path: String) -> Result<()> {
+    // Recover an interrupted commit like every mutating command does, while prompts still work
+    {
+        let repo = Path::new(&repo_path);
+        let _lock = RepoLock::acquire(repo)?;
+        crate::commands::recover_commit(repo)?;
+    }
+
     // Setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -6,6 +6,7 @@ use ratatui::{
     widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Tabs, Wrap},
 };
 
+use crate::models::Activation;
 use crate::tui::app::{App, DialogState, Tab};
 
 pub fn ui(f: &mut Frame, app: &App) {
@@ -82,14 +83,25 @@ fn render_tracked_dotfiles(f: &mut Frame, area: Rect, app: &App) {
                 crate::models::DotfileStatus::Modified => Color::Red,
             };
 
-            let line = Line::from(vec![
+            let mut spans = vec![
                 Span::styled(
                     format!("{:?}", entry.status),
                     Style::default().fg(status_color),
                 ),
                 Span::raw(" "),
                 Span::styled(dot_path.to_string(), style),
-            ]);
+            ];
+
+            // Entries whose `when` conditions fail on this machine are shown dimmed
+            if let Activation::Inactive(reason) = entry.activation() {
+                spans[0] = Span::styled("Inactive", Style::default().fg(Color::DarkGray));
+                spans.push(Span::styled(
+                    format!(" ({})", reason),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+
+            let line = Line::from(spans);
 
             ListItem::new(line)
         })
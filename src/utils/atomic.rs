@@ -0,0 +1,84 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Write a file atomically: write a sibling temp file, sync it, then rename it into place
+///
+/// Readers only ever see the old or the new content, never a partial write.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    write_atomic_mode(path, contents.as_ref(), 0o666)
+}
+
+/// Like [`write_atomic`], but the file is only ever readable by its owner (0600)
+pub fn write_atomic_private(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    write_atomic_mode(path, contents.as_ref(), 0o600)
+}
+
+fn write_atomic_mode(path: &Path, contents: &[u8], mode: u32) -> io::Result<()> {
+    let mut tmp_path = path.to_path_buf();
+    tmp_path.set_file_name(format!(
+        ".{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+
+    // A temp file left by an interrupted run keeps its own mode when reused, so it is
+    // replaced by a fresh one created with `mode`
+    match fs::remove_file(&tmp_path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+
+    let result = (|| {
+        let mut file = File::options()
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic_replaces_content() {
+        let tmp_file = Path::new("/tmp/test_write_atomic.json");
+        std::fs::write(tmp_file, b"old").unwrap();
+
+        write_atomic(tmp_file, b"new").unwrap();
+
+        assert_eq!(std::fs::read(tmp_file).unwrap(), b"new");
+        assert!(!Path::new("/tmp/.test_write_atomic.json.tmp").exists());
+    }
+
+    #[test]
+    fn test_write_atomic_private_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp_file = Path::new("/tmp/test_write_atomic_private.txt");
+        let _ = std::fs::remove_file(tmp_file);
+
+        // Left behind world-readable by an interrupted write
+        let stale = Path::new("/tmp/.test_write_atomic_private.txt.tmp");
+        std::fs::write(stale, b"stale").unwrap();
+        std::fs::set_permissions(stale, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_atomic_private(tmp_file, b"secret").unwrap();
+
+        let mode = std::fs::metadata(tmp_file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(std::fs::read(tmp_file).unwrap(), b"secret");
+        assert!(!stale.exists());
+    }
+}
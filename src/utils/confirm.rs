@@ -1,6 +1,14 @@
-use std::io::{self, Write};
+use std::io::{self, BufRead, Write};
 
+/// Ask a yes/no question, `default` answers an empty line
+///
+/// Without input left to read, like when stdin is closed, the answer is `default` or
+/// no.
 pub fn confirm(question: &str, default: Option<bool>) -> bool {
+    confirm_from(&mut io::stdin().lock(), question, default)
+}
+
+fn confirm_from(input: &mut impl BufRead, question: &str, default: Option<bool>) -> bool {
     loop {
         let prompt = match default {
             Some(true) => format!("{} [Y/n]: ", question),
@@ -10,13 +18,10 @@ pub fn confirm(question: &str, default: Option<bool>) -> bool {
         print!("{}", prompt);
         io::stdout().flush().expect("Failed to flush stdout");
 
-        let mut input = String::new();
-        if io::stdin().read_line(&mut input).is_err() {
-            println!("Failed to read input. Please try again.");
-            continue;
-        }
-        let trimmed = input.trim().to_lowercase();
-        if trimmed.is_empty() {
+        let Some(answer) = read_answer(input) else {
+            return default.unwrap_or(false);
+        };
+        if answer.is_empty() {
             if let Some(def) = default {
                 return def;
             } else {
@@ -24,10 +29,87 @@ pub fn confirm(question: &str, default: Option<bool>) -> bool {
                 continue;
             }
         }
-        match trimmed.as_str() {
+        match answer.as_str() {
             "y" | "yes" => return true,
             "n" | "no" => return false,
             _ => println!("Please enter 'y' or 'n'."),
         }
     }
 }
+
+/// Ask to pick one of `choices`, answered by the full word or its first letter
+///
+/// The first letters of the choices must be unique. Without input left to read, like
+/// when stdin is closed, `default` is picked.
+pub fn choose<'a>(question: &str, choices: &[&'a str], default: &'a str) -> &'a str {
+    choose_from(&mut io::stdin().lock(), question, choices, default)
+}
+
+fn choose_from<'a>(
+    input: &mut impl BufRead,
+    question: &str,
+    choices: &[&'a str],
+    default: &'a str,
+) -> &'a str {
+    let options = choices
+        .iter()
+        .map(|choice| format!("[{}]{}", &choice[..1], &choice[1..]))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    loop {
+        print!("{} {}: ", question, options);
+        io::stdout().flush().expect("Failed to flush stdout");
+
+        let Some(answer) = read_answer(input) else {
+            return default;
+        };
+        let found = choices.iter().find(|choice| {
+            answer == **choice || (answer.len() == 1 && choice.starts_with(&answer))
+        });
+
+        match found {
+            Some(choice) => return choice,
+            None => println!("Please enter one of: {}", options),
+        }
+    }
+}
+
+/// The next answer trimmed and lowercased, `None` at the end of the input or when it
+/// can't be read
+fn read_answer(input: &mut impl BufRead) -> Option<String> {
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) | Err(_) => {
+            // Finish the prompt line
+            println!();
+            None
+        }
+        Ok(_) => Some(line.trim().to_lowercase()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_closed_input_picks_default() {
+        let choices = ["adopt", "backup", "skip"];
+        let choose =
+            |input: &str| choose_from(&mut Cursor::new(input), "Conflict?", &choices, "backup");
+        assert_eq!(choose(""), "backup");
+        assert_eq!(choose("what\n"), "backup");
+        assert_eq!(choose("what\ns\n"), "skip");
+        assert_eq!(choose("adopt"), "adopt");
+
+        let confirm =
+            |input: &str, default| confirm_from(&mut Cursor::new(input), "Continue?", default);
+        assert!(!confirm("", None));
+        assert!(confirm("", Some(true)));
+        assert!(!confirm("maybe\n", Some(false)));
+        assert!(confirm("\n", Some(true)));
+        assert!(confirm("maybe\nyes\n", Some(false)));
+    }
+}
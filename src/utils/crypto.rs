@@ -0,0 +1,151 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use age::secrecy::{ExposeSecret, SecretString};
+use age::x25519;
+use anyhow::{Context, Result, anyhow};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+/// Context mixed into digest keys, so they never equal anything else derived from a key
+const DIGEST_CONTEXT: &[u8] = b"dotzilla plaintext digest v1";
+
+/// Key used to encrypt and decrypt secret dotfiles
+pub enum SecretKey {
+    /// A passphrase, stretched with scrypt like `age -p`
+    Passphrase(SecretString),
+    /// An age X25519 identity, as generated by `age-keygen`
+    Identity(x25519::Identity),
+}
+
+impl SecretKey {
+    /// Read the first `AGE-SECRET-KEY-` line of an age identity file
+    pub fn from_key_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read key file {}", path.display()))?;
+
+        let line = content
+            .lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("AGE-SECRET-KEY-"))
+            .ok_or_else(|| anyhow!("No age secret key found in {}", path.display()))?;
+
+        let identity = x25519::Identity::from_str(line)
+            .map_err(|err| anyhow!("Invalid age secret key in {}: {}", path.display(), err))?;
+
+        Ok(SecretKey::Identity(identity))
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let armored = match self {
+            SecretKey::Passphrase(passphrase) => {
+                age::encrypt_and_armor(&age::scrypt::Recipient::new(passphrase.clone()), plaintext)
+            }
+            SecretKey::Identity(identity) => {
+                age::encrypt_and_armor(&identity.to_public(), plaintext)
+            }
+        }
+        .context("Failed to encrypt")?;
+
+        Ok(armored.into_bytes())
+    }
+
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            SecretKey::Passphrase(passphrase) => {
+                age::decrypt(&age::scrypt::Identity::new(passphrase.clone()), ciphertext)
+            }
+            SecretKey::Identity(identity) => age::decrypt(identity, ciphertext),
+        }
+        .context("Failed to decrypt, is the key or passphrase correct?")
+    }
+
+    /// Key for digests of plaintexts, which can't be recomputed without the secret
+    ///
+    /// Passphrases are stretched with scrypt first, so guessing one from a digest costs
+    /// about as much as guessing it from the ciphertext.
+    pub fn digest_key(&self) -> Result<[u8; 32]> {
+        match self {
+            SecretKey::Passphrase(passphrase) => {
+                let params = scrypt::Params::new(15, 8, 1, 32)
+                    .map_err(|err| anyhow!("Invalid scrypt parameters: {}", err))?;
+                let mut key = [0; 32];
+                scrypt::scrypt(
+                    passphrase.expose_secret().as_bytes(),
+                    DIGEST_CONTEXT,
+                    &params,
+                    &mut key,
+                )
+                .map_err(|err| anyhow!("Failed to derive the digest key: {}", err))?;
+                Ok(key)
+            }
+            SecretKey::Identity(identity) => {
+                let mut hasher = Sha256::new();
+                hasher.update(DIGEST_CONTEXT);
+                hasher.update(identity.to_string().expose_secret().as_bytes());
+                Ok(hasher.finalize().into())
+            }
+        }
+    }
+}
+
+/// HMAC-SHA256 of a plaintext bound to the name of its dotfile, as a hex string
+///
+/// The same plaintext under two names gets two unrelated digests.
+pub fn keyed_digest(key: &[u8; 32], name: &Path, plaintext: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(name.as_os_str().as_encoded_bytes());
+    mac.update(&[0]);
+    mac.update(plaintext);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_file_round_trip() {
+        let key_file = Path::new("/tmp/test_dotzilla_key.txt");
+        let identity = x25519::Identity::generate();
+        std::fs::write(
+            key_file,
+            format!(
+                "# created: test\n{}\n",
+                identity.to_string().expose_secret()
+            ),
+        )
+        .unwrap();
+
+        let key = SecretKey::from_key_file(key_file).unwrap();
+        let ciphertext = key
+            .encrypt(b"machine example.com password hunter2")
+            .unwrap();
+
+        assert!(ciphertext.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----"));
+        assert_eq!(
+            key.decrypt(&ciphertext).unwrap(),
+            b"machine example.com password hunter2"
+        );
+    }
+
+    #[test]
+    fn test_keyed_digest() {
+        let key = SecretKey::Identity(x25519::Identity::generate())
+            .digest_key()
+            .unwrap();
+        let other = SecretKey::Identity(x25519::Identity::generate())
+            .digest_key()
+            .unwrap();
+
+        let digest = keyed_digest(&key, Path::new(".netrc"), b"hunter2");
+        assert_eq!(digest, keyed_digest(&key, Path::new(".netrc"), b"hunter2"));
+        assert_ne!(digest, keyed_digest(&key, Path::new(".pgpass"), b"hunter2"));
+        assert_ne!(
+            digest,
+            keyed_digest(&other, Path::new(".netrc"), b"hunter2")
+        );
+        assert_ne!(digest, hex::encode(Sha256::digest(b"hunter2")));
+    }
+}
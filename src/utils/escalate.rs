@@ -0,0 +1,107 @@
+use std::ffi::{CString, OsStr};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, anyhow};
+
+/// Escalation command used when the repository doesn't configure one
+pub const DEFAULT_COMMAND: &str = "sudo";
+
+/// Whether this process already runs as root
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Whether the current user may write to `path`
+fn writable(path: &Path) -> bool {
+    CString::new(path.as_os_str().as_bytes())
+        .is_ok_and(|path| unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0)
+}
+
+/// Whether replacing `path` needs more rights than the current user has: it isn't
+/// writable, or its closest existing directory isn't
+pub fn needs_root(path: &Path) -> bool {
+    let dir = path
+        .parent()
+        .and_then(|parent| parent.ancestors().find(|dir| dir.exists()));
+
+    !dir.is_some_and(writable) || (path.exists() && !writable(path))
+}
+
+/// Run a program through the escalation command, e.g. `sudo rm -- /etc/motd`
+///
+/// The escalation command may carry its own arguments, like `sudo -n`. `input` is fed
+/// on standard input; without it the standard output is captured and returned.
+pub fn run(command: &str, args: &[&dyn AsRef<OsStr>], input: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut words = command.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| anyhow!("The escalation command is empty"))?;
+
+    let mut child = Command::new(program)
+        .args(words)
+        .args(args.iter().map(|arg| arg.as_ref()))
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        })
+        .stdout(if input.is_some() {
+            Stdio::null()
+        } else {
+            Stdio::piped()
+        })
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}, is it installed?", program))?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin
+            .write_all(input)
+            .with_context(|| format!("Failed to write to {}", program))?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("Failed to run {}", program))?;
+    if !output.status.success() {
+        let name = args
+            .first()
+            .map(|arg| arg.as_ref().to_string_lossy().to_string())
+            .unwrap_or_default();
+        return Err(anyhow!(
+            "{} {} failed: {}",
+            command,
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_through_command() {
+        let dir = Path::new("/tmp/test_dotzilla_escalate");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let file = dir.join("motd");
+
+        // `env` runs the program unchanged, standing in for sudo
+        run("env", &[&"tee", &"--", &file], Some(b"hello")).unwrap();
+        assert_eq!(run("env", &[&"cat", &"--", &file], None).unwrap(), b"hello");
+        assert!(run("env -i", &[&"false"], None).is_err());
+        assert!(run("", &[&"true"], None).is_err());
+
+        assert!(!needs_root(&file));
+        assert!(!needs_root(&dir.join("missing/deeper")));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
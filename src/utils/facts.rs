@@ -0,0 +1,21 @@
+/// Hostname of this machine
+pub fn hostname() -> String {
+    gethostname::gethostname().to_string_lossy().to_string()
+}
+
+/// Name of the current user
+pub fn user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_default()
+}
+
+/// Operating system, e.g. `linux` or `macos`
+pub fn os() -> &'static str {
+    std::env::consts::OS
+}
+
+/// CPU architecture, e.g. `x86_64` or `aarch64`
+pub fn arch() -> &'static str {
+    std::env::consts::ARCH
+}
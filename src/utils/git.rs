@@ -0,0 +1,254 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
+
+/// How far the current branch is from the branch it tracks
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Upstream {
+    /// Name of the tracked branch, e.g. `origin/main`
+    pub name: String,
+    pub ahead: usize,
+    pub behind: usize,
+}
+
+/// Run git in `repo` and return its trimmed standard output
+fn git(repo: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .output()
+        .context("Failed to run git, is it installed?")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Clone `source`, a URL or a local path, into the new directory `dest`
+///
+/// Relative local paths are taken from the current directory, not from where git runs.
+pub fn clone(source: &str, dest: &Path) -> Result<()> {
+    let dest = std::path::absolute(dest)
+        .with_context(|| format!("Failed to resolve {}", dest.display()))?;
+    let parent = dest
+        .parent()
+        .ok_or_else(|| anyhow!("{} has no parent directory", dest.display()))?;
+    fs::create_dir_all(parent)
+        .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+
+    let source = if Path::new(source).exists() {
+        std::path::absolute(source)?.to_string_lossy().to_string()
+    } else {
+        source.to_string()
+    };
+    let dest = dest
+        .to_str()
+        .ok_or_else(|| anyhow!("{} is not valid UTF-8", dest.display()))?;
+    git(parent, &["clone", "--quiet", &source, dest])?;
+    Ok(())
+}
+
+/// Whether `path` is inside a git work tree
+pub fn is_repo(path: &Path) -> bool {
+    git(path, &["rev-parse", "--is-inside-work-tree"]).is_ok_and(|out| out == "true")
+}
+
+/// Whether `path` is a bare repository, which has no work tree to be inside of
+pub fn is_bare_repo(path: &Path) -> bool {
+    git(path, &["rev-parse", "--is-bare-repository"]).is_ok_and(|out| out == "true")
+}
+
+/// The commit checked out, `None` before the first commit
+pub fn head(repo: &Path) -> Result<Option<String>> {
+    if git(repo, &["rev-parse", "--verify", "--quiet", "HEAD"]).is_err() {
+        return Ok(None);
+    }
+    git(repo, &["rev-parse", "HEAD"]).map(Some)
+}
+
+/// Commit the current state of `paths` and nothing else, returning the short hash
+///
+/// Returns `None` when none of the paths changed since the last commit.
+pub fn commit_paths(repo: &Path, paths: &[PathBuf], message: &str) -> Result<Option<String>> {
+    let paths: Vec<&str> = paths
+        .iter()
+        .filter(|path| path.exists())
+        .filter_map(|path| path.to_str())
+        .collect();
+    if paths.is_empty() {
+        return Ok(None);
+    }
+
+    let mut add = vec!["add", "--all", "--"];
+    add.extend(&paths);
+    git(repo, &add)?;
+
+    let mut staged = vec!["diff", "--cached", "--name-only", "--"];
+    staged.extend(&paths);
+    if git(repo, &staged)?.is_empty() {
+        return Ok(None);
+    }
+
+    let mut commit = vec!["commit", "--quiet", "--message", message, "--"];
+    commit.extend(&paths);
+    git(repo, &commit)?;
+
+    git(repo, &["rev-parse", "--short", "HEAD"]).map(Some)
+}
+
+/// Fast-forward the current branch to its upstream
+pub fn pull(repo: &Path) -> Result<()> {
+    git(repo, &["pull", "--ff-only", "--quiet"])
+        .context("Pulling failed, merge or rebase the repository by hand and sync again")?;
+    Ok(())
+}
+
+/// Push the current branch to its upstream
+pub fn push(repo: &Path) -> Result<()> {
+    git(repo, &["push", "--quiet"])?;
+    Ok(())
+}
+
+/// Paths changed between two commits, relative to `repo`
+///
+/// Without a `from` commit every path in `to` counts as changed.
+pub fn changed_paths(repo: &Path, from: Option<&str>, to: &str) -> Result<Vec<PathBuf>> {
+    let output = match from {
+        Some(from) => git(repo, &["diff", "--name-only", "--relative", from, to])?,
+        None => git(repo, &["ls-tree", "-r", "--name-only", to])?,
+    };
+
+    Ok(output.lines().map(PathBuf::from).collect())
+}
+
+/// Ahead and behind counts against the tracked branch, as of the last fetch
+///
+/// Returns `None` outside a git repository or when the branch tracks nothing.
+pub fn upstream(repo: &Path) -> Option<Upstream> {
+    let name = git(
+        repo,
+        &[
+            "rev-parse",
+            "--abbrev-ref",
+            "--symbolic-full-name",
+            "@{upstream}",
+        ],
+    )
+    .ok()?;
+    let counts = git(
+        repo,
+        &["rev-list", "--left-right", "--count", "HEAD...@{upstream}"],
+    )
+    .ok()?;
+
+    let mut counts = counts.split_whitespace().map(str::parse::<usize>);
+    match (counts.next(), counts.next()) {
+        (Some(Ok(ahead)), Some(Ok(behind))) => Some(Upstream {
+            name,
+            ahead,
+            behind,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clone_with_identity(remote: &Path, path: &Path) {
+        clone(remote.to_str().unwrap(), path).unwrap();
+        git(path, &["config", "user.name", "Dotzilla Test"]).unwrap();
+        git(path, &["config", "user.email", "test@dotzilla.invalid"]).unwrap();
+    }
+
+    #[test]
+    fn test_commit_and_sync_through_bare_repo() {
+        let root = Path::new("/tmp/test_dotzilla_git");
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+
+        let remote = root.join("remote.git");
+        git(
+            root,
+            &["init", "--quiet", "--bare", remote.to_str().unwrap()],
+        )
+        .unwrap();
+        let first = root.join("first");
+        let second = root.join("second");
+        clone_with_identity(&remote, &first);
+
+        assert!(is_repo(&first));
+        assert!(!is_repo(root));
+        assert!(is_bare_repo(&remote));
+        assert!(!is_bare_repo(&first));
+        assert_eq!(head(&first).unwrap(), None);
+
+        fs::write(first.join("bashrc"), "one").unwrap();
+        fs::write(first.join("untouched"), "scratch").unwrap();
+        let committed = commit_paths(
+            &first,
+            &[first.join("bashrc"), first.join("gone")],
+            "Add bashrc",
+        )
+        .unwrap();
+        assert!(committed.is_some());
+        assert!(
+            commit_paths(&first, &[first.join("bashrc")], "Nothing")
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            git(&first, &["status", "--porcelain"]).unwrap(),
+            "?? untouched"
+        );
+
+        git(&first, &["push", "--quiet", "-u", "origin", "HEAD"]).unwrap();
+        assert_eq!(upstream(&first).map(|u| (u.ahead, u.behind)), Some((0, 0)));
+
+        clone_with_identity(&remote, &second);
+        fs::write(second.join("bashrc"), "two").unwrap();
+        commit_paths(&second, &[second.join("bashrc")], "Edit bashrc").unwrap();
+        assert_eq!(upstream(&second).map(|u| (u.ahead, u.behind)), Some((1, 0)));
+        push(&second).unwrap();
+
+        git(&first, &["fetch", "--quiet"]).unwrap();
+        assert_eq!(upstream(&first).map(|u| (u.ahead, u.behind)), Some((0, 1)));
+
+        let before = head(&first).unwrap();
+        pull(&first).unwrap();
+        let after = head(&first).unwrap().unwrap();
+        assert_eq!(
+            changed_paths(&first, before.as_deref(), &after).unwrap(),
+            vec![PathBuf::from("bashrc")]
+        );
+        assert_eq!(fs::read_to_string(first.join("bashrc")).unwrap(), "two");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_clone_relative_paths() {
+        // Tests run from the crate root, so these resolve under `target`
+        let root = Path::new("target/test_dotzilla_clone");
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root).unwrap();
+        git(root, &["init", "--quiet", "--bare", "src.git"]).unwrap();
+
+        clone("target/test_dotzilla_clone/src.git", &root.join("sub/dots")).unwrap();
+        assert!(is_repo(&root.join("sub/dots")));
+        assert!(!root.join("sub/sub").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use super::ignore::IgnoreRules;
+use super::walk::{EntryKind, WalkEntry, walk};
+
+/// Compute the SHA-256 digest of a file or directory as a hex string.
+///
+/// Files are hashed by content. Directories get a Merkle-style digest built
+/// from the sorted names, kinds and digests of their children, so any change
+/// deep inside the tree changes the root digest. Paths matched by `ignore` are left
+/// out of a directory digest, and inner symlinks are hashed by its symlink policy.
+pub fn hash_path(path: &Path, ignore: &IgnoreRules) -> io::Result<String> {
+    let metadata = fs::symlink_metadata(path)?;
+
+    if metadata.file_type().is_symlink() {
+        if fs::metadata(path)?.is_dir() {
+            return hash_dir(path, ignore);
+        }
+        return hash_file(path);
+    }
+
+    if metadata.is_dir() {
+        hash_dir(path, ignore)
+    } else {
+        hash_file(path)
+    }
+}
+
+/// Compute the SHA-256 digest of a single file's content
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 8192];
+
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn hash_dir(root: &Path, ignore: &IgnoreRules) -> io::Result<String> {
+    let entries = walk(root, ignore)?;
+    let mut pos = 0;
+    hash_children(root, &entries, &mut pos, Path::new(""))
+}
+
+/// Digest the entries directly inside `dir`, which the walk lists right after it
+fn hash_children(
+    root: &Path,
+    entries: &[WalkEntry],
+    pos: &mut usize,
+    dir: &Path,
+) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    while let Some(entry) = entries.get(*pos)
+        && entry.relative.parent() == Some(dir)
+    {
+        *pos += 1;
+
+        let (kind, digest) = match &entry.kind {
+            // Hash a preserved link itself rather than what it points to
+            EntryKind::Symlink(target) => (
+                "l",
+                hex::encode(Sha256::digest(target.as_os_str().as_encoded_bytes())),
+            ),
+            EntryKind::Dir => ("d", hash_children(root, entries, pos, &entry.relative)?),
+            EntryKind::File => ("f", hash_file(&root.join(&entry.relative))?),
+        };
+
+        hasher.update(
+            entry
+                .relative
+                .file_name()
+                .unwrap_or_default()
+                .as_encoded_bytes(),
+        );
+        hasher.update([0]);
+        hasher.update(kind.as_bytes());
+        hasher.update([0]);
+        hasher.update(digest.as_bytes());
+        hasher.update(b"\n");
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_file() {
+        let tmp_file = Path::new("/tmp/test_hash_file.txt");
+        std::fs::write(tmp_file, b"Hello, world!").unwrap();
+
+        assert_eq!(
+            hash_path(tmp_file, &IgnoreRules::default()).unwrap(),
+            "315f5bdb76d078c43b8ac0064e4a0164612b1fce77c869345bfc94c75894edd3"
+        );
+    }
+
+    #[test]
+    fn test_hash_dir_changes_with_content() {
+        let tmp_dir = Path::new("/tmp/test_hash_dir");
+        let _ = std::fs::remove_dir_all(tmp_dir);
+        std::fs::create_dir_all(tmp_dir.join("nested")).unwrap();
+        std::fs::write(tmp_dir.join("nested/file.txt"), b"one").unwrap();
+
+        let no_rules = IgnoreRules::default();
+        let before = hash_path(tmp_dir, &no_rules).unwrap();
+        assert_eq!(before, hash_path(tmp_dir, &no_rules).unwrap());
+
+        std::fs::write(tmp_dir.join("nested/file.txt"), b"two").unwrap();
+        assert_ne!(before, hash_path(tmp_dir, &no_rules).unwrap());
+
+        let ignore = IgnoreRules::new(["*.log"]).unwrap();
+        let tracked = hash_path(tmp_dir, &ignore).unwrap();
+        std::fs::write(tmp_dir.join("nested/debug.log"), b"noise").unwrap();
+        assert_eq!(tracked, hash_path(tmp_dir, &ignore).unwrap());
+        assert_ne!(tracked, hash_path(tmp_dir, &no_rules).unwrap());
+    }
+
+    #[test]
+    fn test_hash_dir_symlink_policies() {
+        use crate::utils::walk::SymlinkPolicy;
+
+        let tmp_dir = Path::new("/tmp/test_hash_dir_symlinks");
+        let _ = std::fs::remove_dir_all(tmp_dir);
+        std::fs::create_dir_all(tmp_dir).unwrap();
+        std::fs::write(tmp_dir.join("dark.toml"), b"dark").unwrap();
+        std::fs::write(tmp_dir.join("light.toml"), b"light").unwrap();
+        std::os::unix::fs::symlink("dark.toml", tmp_dir.join("current.toml")).unwrap();
+
+        let hashes = || {
+            [
+                SymlinkPolicy::Preserve,
+                SymlinkPolicy::Dereference,
+                SymlinkPolicy::Skip,
+            ]
+            .map(|policy| {
+                hash_path(tmp_dir, &IgnoreRules::default().with_symlinks(policy)).unwrap()
+            })
+        };
+
+        let [preserved, dereferenced, skipped] = hashes();
+        assert_ne!(preserved, dereferenced);
+        assert_ne!(preserved, skipped);
+        assert_ne!(dereferenced, skipped);
+
+        // Pointing the link elsewhere only matters when the link is tracked
+        std::fs::remove_file(tmp_dir.join("current.toml")).unwrap();
+        std::os::unix::fs::symlink("light.toml", tmp_dir.join("current.toml")).unwrap();
+        let [repointed, redereferenced, reskipped] = hashes();
+        assert_ne!(preserved, repointed);
+        assert_ne!(dereferenced, redereferenced);
+        assert_eq!(skipped, reskipped);
+
+        // A dereferenced link hashes like a copy of its target
+        std::fs::remove_file(tmp_dir.join("current.toml")).unwrap();
+        std::fs::write(tmp_dir.join("current.toml"), b"light").unwrap();
+        assert_eq!(
+            redereferenced,
+            hash_path(tmp_dir, &IgnoreRules::default()).unwrap()
+        );
+
+        std::fs::remove_dir_all(tmp_dir).unwrap();
+    }
+}
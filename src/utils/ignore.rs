@@ -0,0 +1,173 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobMatcher};
+
+use super::walk::SymlinkPolicy;
+
+/// Name of the repository-wide ignore file
+pub const IGNORE_FILE: &str = ".dotzillaignore";
+
+#[derive(Debug, Clone)]
+struct Rule {
+    matcher: GlobMatcher,
+    negated: bool,
+    dir_only: bool,
+}
+
+/// Gitignore-style patterns for the paths inside a directory dotfile to leave out
+///
+/// Patterns are matched against paths relative to the dotfile's root. A pattern
+/// without a slash matches at any depth, a leading or inner slash anchors it to the
+/// root, a trailing slash matches only directories and `!` re-includes a path. As
+/// with git, the last matching pattern wins and nothing inside an ignored directory
+/// can be re-included. The rules also carry how symlinks inside the dotfile are walked.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+    symlinks: SymlinkPolicy,
+}
+
+impl IgnoreRules {
+    /// Compile patterns, skipping blank lines and `#` comments
+    pub fn new<S: AsRef<str>>(patterns: impl IntoIterator<Item = S>) -> Result<Self> {
+        let mut rules = Vec::new();
+
+        for pattern in patterns {
+            let pattern = pattern.as_ref().trim();
+            if pattern.is_empty() || pattern.starts_with('#') {
+                continue;
+            }
+
+            let (negated, pattern) = match pattern.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, pattern),
+            };
+            let (dir_only, pattern) = match pattern.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, pattern),
+            };
+            let glob = match pattern.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if pattern.contains('/') => pattern.to_string(),
+                None => format!("**/{}", pattern),
+            };
+
+            let matcher = GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("Invalid ignore pattern: {}", pattern))?
+                .compile_matcher();
+
+            rules.push(Rule {
+                matcher,
+                negated,
+                dir_only,
+            });
+        }
+
+        Ok(IgnoreRules {
+            rules,
+            symlinks: SymlinkPolicy::default(),
+        })
+    }
+
+    /// Read patterns from an ignore file, no rules when it doesn't exist
+    pub fn from_file(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(IgnoreRules::default());
+        }
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        IgnoreRules::new(content.lines())
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Add the rules of `other` after these, so they take precedence
+    pub fn extend(&mut self, other: IgnoreRules) {
+        self.rules.extend(other.rules);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The same rules, walking inner symlinks by `policy`
+    pub fn with_symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    pub fn symlinks(&self) -> SymlinkPolicy {
+        self.symlinks
+    }
+
+    /// Whether a path relative to the dotfile's root is ignored, itself or through
+    /// one of its parent directories
+    pub fn is_ignored(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+
+        let components: Vec<_> = relative.components().collect();
+        let mut prefix = PathBuf::new();
+        for (i, component) in components.iter().enumerate() {
+            prefix.push(component);
+            let prefix_is_dir = i + 1 < components.len() || is_dir;
+            if self.matches(&prefix, prefix_is_dir) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn matches(&self, relative: &Path, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            if rule.matcher.is_match(relative) {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gitignore_semantics() {
+        let rules = IgnoreRules::new([
+            "# editor state",
+            "",
+            "*.log",
+            "!keep.log",
+            "cache/",
+            "/lazy-lock.json",
+            "plugins/*/build",
+        ])
+        .unwrap();
+
+        assert!(rules.is_ignored(Path::new("debug.log"), false));
+        assert!(rules.is_ignored(Path::new("a/b/debug.log"), false));
+        assert!(!rules.is_ignored(Path::new("a/keep.log"), false));
+
+        assert!(rules.is_ignored(Path::new("cache"), true));
+        assert!(!rules.is_ignored(Path::new("cache"), false));
+        assert!(rules.is_ignored(Path::new("a/cache/entry"), false));
+
+        assert!(rules.is_ignored(Path::new("lazy-lock.json"), false));
+        assert!(!rules.is_ignored(Path::new("lua/lazy-lock.json"), false));
+
+        assert!(rules.is_ignored(Path::new("plugins/telescope/build"), true));
+        assert!(!rules.is_ignored(Path::new("plugins/a/b/build"), true));
+        assert!(!rules.is_ignored(Path::new("init.lua"), false));
+
+        assert!(IgnoreRules::new(["a[b"]).is_err());
+    }
+}
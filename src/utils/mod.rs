@@ -1,7 +1,18 @@
+pub mod atomic;
+pub mod crypto;
 pub mod diff_tools;
+pub mod escalate;
+pub mod facts;
 pub mod filter;
+pub mod git;
+pub mod hash;
+pub mod ignore;
+pub mod mode;
 mod path;
 pub mod path_compare;
+pub mod template;
+pub mod tree;
+pub mod walk;
 pub use path::*;
 pub mod confirm;
 pub use confirm::*;
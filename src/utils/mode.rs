@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::fs::{self, Metadata};
+use std::io;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::ignore::IgnoreRules;
+use super::walk::{EntryKind, walk};
+
+/// Permission bits and owner of a file or directory inside a dotfile
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileMode {
+    /// Permission bits, written in octal like `0644`
+    #[serde(with = "octal")]
+    pub mode: u32,
+    /// `user:group`, only recorded when it isn't the current user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+/// Modes of the files of a dotfile by path relative to its root, `.` for the root itself
+pub type Modes = BTreeMap<PathBuf, FileMode>;
+
+impl FileMode {
+    fn from_metadata(metadata: &Metadata) -> Self {
+        let owner =
+            (metadata.uid() != current_uid() || metadata.gid() != current_gid()).then(|| {
+                format!(
+                    "{}:{}",
+                    user_name(metadata.uid()),
+                    group_name(metadata.gid())
+                )
+            });
+
+        FileMode {
+            mode: metadata.permissions().mode() & 0o7777,
+            owner,
+        }
+    }
+}
+
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04o}", self.mode)?;
+        if let Some(owner) = &self.owner {
+            write!(f, " {}", owner)?;
+        }
+        Ok(())
+    }
+}
+
+/// A file whose mode or owner no longer matches the recorded one
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeChange {
+    pub path: PathBuf,
+    pub expected: FileMode,
+    pub actual: FileMode,
+}
+
+impl ModeChange {
+    pub fn mode_differs(&self) -> bool {
+        self.expected.mode != self.actual.mode
+    }
+
+    /// The recorded owner when the file belongs to someone else
+    pub fn owner_differs(&self) -> Option<&str> {
+        self.expected
+            .owner
+            .as_deref()
+            .filter(|owner| self.actual.owner.as_deref() != Some(*owner))
+    }
+}
+
+/// The path a recorded mode applies to under `root`
+pub fn resolve(root: &Path, relative: &Path) -> PathBuf {
+    if relative == Path::new(".") {
+        root.to_path_buf()
+    } else {
+        root.join(relative)
+    }
+}
+
+/// Record the modes of a file, or of a directory and everything in it that `rules`
+/// don't leave out
+///
+/// The root is followed when it is a symlink, preserved symlinks inside it have no mode
+/// of their own and are skipped. Directories the current user can't read only get their
+/// own mode recorded.
+pub fn capture(path: &Path, rules: &IgnoreRules) -> io::Result<Modes> {
+    let metadata = fs::metadata(path)?;
+    let mut modes = Modes::new();
+    modes.insert(PathBuf::from("."), FileMode::from_metadata(&metadata));
+    if !metadata.is_dir() {
+        return Ok(modes);
+    }
+
+    let entries = match walk(path, rules) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => return Ok(modes),
+        Err(err) => return Err(err),
+    };
+    for entry in entries {
+        if let EntryKind::Symlink(_) = entry.kind {
+            continue;
+        }
+        let metadata = fs::metadata(path.join(&entry.relative))?;
+        modes.insert(entry.relative, FileMode::from_metadata(&metadata));
+    }
+    Ok(modes)
+}
+
+/// Record the modes of `root` and the given files in it, following symlinks and
+/// falling back to `fallback` for what is missing under `root`
+pub fn capture_files(root: &Path, fallback: &Path, files: &[PathBuf]) -> io::Result<Modes> {
+    let mut modes = Modes::new();
+    for relative in std::iter::once(Path::new(".")).chain(files.iter().map(PathBuf::as_path)) {
+        let metadata = match fs::metadata(resolve(root, relative)) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                fs::metadata(resolve(fallback, relative))?
+            }
+            Err(err) => return Err(err),
+        };
+        modes.insert(relative.to_path_buf(), FileMode::from_metadata(&metadata));
+    }
+    Ok(modes)
+}
+
+/// The same modes without owners
+pub fn without_owners(modes: &Modes) -> Modes {
+    modes
+        .iter()
+        .map(|(path, mode)| {
+            let mode = FileMode {
+                mode: mode.mode,
+                owner: None,
+            };
+            (path.clone(), mode)
+        })
+        .collect()
+}
+
+/// The same modes with only the permission bits in `mask` kept
+pub fn masked(modes: &Modes, mask: u32) -> Modes {
+    modes
+        .iter()
+        .map(|(path, mode)| {
+            let mode = FileMode {
+                mode: mode.mode & mask,
+                owner: mode.owner.clone(),
+            };
+            (path.clone(), mode)
+        })
+        .collect()
+}
+
+/// Compare the recorded modes with the files under `root`, following symlinks
+///
+/// Missing files aren't changes, and owners are only compared where one was recorded.
+pub fn changes(root: &Path, modes: &Modes) -> Vec<ModeChange> {
+    modes
+        .iter()
+        .filter_map(|(relative, expected)| {
+            let metadata = fs::metadata(resolve(root, relative)).ok()?;
+            let change = ModeChange {
+                path: relative.clone(),
+                expected: expected.clone(),
+                actual: FileMode::from_metadata(&metadata),
+            };
+            (change.mode_differs() || change.owner_differs().is_some()).then_some(change)
+        })
+        .collect()
+}
+
+/// Put the recorded modes and owners back on the files under `root`, returning how
+/// many files changed
+pub fn restore(root: &Path, modes: &Modes) -> io::Result<usize> {
+    let changes = changes(root, modes);
+    for change in &changes {
+        let path = resolve(root, &change.path);
+        if change.mode_differs() {
+            fs::set_permissions(&path, fs::Permissions::from_mode(change.expected.mode))?;
+        }
+        if let Some(owner) = change.owner_differs() {
+            let (uid, gid) = owner_ids(owner).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No such user or group on this machine: {}", owner),
+                )
+            })?;
+            std::os::unix::fs::chown(&path, Some(uid), Some(gid))?;
+        }
+    }
+    Ok(changes.len())
+}
+
+fn current_uid() -> u32 {
+    unsafe { libc::geteuid() }
+}
+
+fn current_gid() -> u32 {
+    unsafe { libc::getegid() }
+}
+
+fn user_name(uid: u32) -> String {
+    let passwd = unsafe { libc::getpwuid(uid) };
+    if passwd.is_null() {
+        return uid.to_string();
+    }
+    unsafe { CStr::from_ptr((*passwd).pw_name) }
+        .to_string_lossy()
+        .to_string()
+}
+
+fn group_name(gid: u32) -> String {
+    let group = unsafe { libc::getgrgid(gid) };
+    if group.is_null() {
+        return gid.to_string();
+    }
+    unsafe { CStr::from_ptr((*group).gr_name) }
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Look up the ids of a `user:group` owner, numeric ids are taken as they are
+fn owner_ids(owner: &str) -> Option<(u32, u32)> {
+    let (user, group) = owner.split_once(':')?;
+
+    let uid = user.parse().ok().or_else(|| {
+        let name = CString::new(user).ok()?;
+        let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+        (!passwd.is_null()).then(|| unsafe { (*passwd).pw_uid })
+    })?;
+    let gid = group.parse().ok().or_else(|| {
+        let name = CString::new(group).ok()?;
+        let group = unsafe { libc::getgrnam(name.as_ptr()) };
+        (!group.is_null()).then(|| unsafe { (*group).gr_gid })
+    })?;
+
+    Some((uid, gid))
+}
+
+mod octal {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(mode: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:04o}", mode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        let mode = String::deserialize(deserializer)?;
+        u32::from_str_radix(&mode, 8)
+            .map_err(|_| D::Error::custom(format!("invalid file mode: {}", mode)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_and_restore() {
+        let root = Path::new("/tmp/test_dotzilla_mode");
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root.join("bin")).unwrap();
+        fs::write(root.join("bin/script"), "#!/bin/sh\n").unwrap();
+        fs::write(root.join("debug.log"), "noise").unwrap();
+        fs::set_permissions(root.join("bin/script"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(root.join("bin"), fs::Permissions::from_mode(0o700)).unwrap();
+
+        let modes = capture(root, &IgnoreRules::new(["*.log"]).unwrap()).unwrap();
+        assert_eq!(modes.len(), 3);
+        assert_eq!(modes[Path::new("bin/script")].mode, 0o755);
+        assert_eq!(modes[Path::new("bin")].mode, 0o700);
+        assert!(changes(root, &modes).is_empty());
+
+        fs::set_permissions(root.join("bin/script"), fs::Permissions::from_mode(0o644)).unwrap();
+        let found = changes(root, &modes);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, Path::new("bin/script"));
+        assert_eq!(found[0].actual.to_string(), "0644");
+
+        assert_eq!(restore(root, &modes).unwrap(), 1);
+        assert!(changes(root, &modes).is_empty());
+
+        let json = serde_json::to_string(&modes[Path::new("bin")]).unwrap();
+        assert_eq!(json, r#"{"mode":"0700"}"#);
+        assert_eq!(serde_json::from_str::<FileMode>(&json).unwrap().mode, 0o700);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
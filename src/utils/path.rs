@@ -1,5 +1,5 @@
 use home::home_dir;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
 /// Expand tilde to home directory in path
 pub fn expand_tilde(path: &str) -> PathBuf {
@@ -64,6 +64,25 @@ pub fn replace_home(path: &str, new_home: &str) -> PathBuf {
     PathBuf::from(path)
 }
 
+/// Express `target` relative to the directory `from_dir`, e.g. for a relative symlink
+///
+/// Both paths must be absolute.
+pub fn relative_path(from_dir: &Path, target: &Path) -> PathBuf {
+    let from: Vec<Component> = from_dir.components().collect();
+    let to: Vec<Component> = target.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &to[common..] {
+        relative.push(component);
+    }
+
+    relative
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
@@ -128,4 +147,22 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(
+                Path::new("/home/user"),
+                Path::new("/home/user/dotfiles/.bashrc")
+            ),
+            PathBuf::from("dotfiles/.bashrc")
+        );
+        assert_eq!(
+            relative_path(
+                Path::new("/home/user/.config/nvim"),
+                Path::new("/home/user/dotfiles/.config/nvim/init.lua")
+            ),
+            PathBuf::from("../../dotfiles/.config/nvim/init.lua")
+        );
+    }
 }
@@ -1,13 +1,30 @@
 use std::fs::{self, File};
-use std::io::{BufReader, Read};
+use std::io::{self, BufReader, Read};
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 
+use serde::Serialize;
+
+use super::hash::hash_path;
+use super::ignore::IgnoreRules;
+use super::walk::{EntryKind, walk};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum CompareResult {
     Equal,
     NotEqual,
     Linked,
 }
 
+/// Which side drifted from the digest recorded at add/commit time
+#[derive(Debug, PartialEq)]
+pub enum ChangeResult {
+    Unchanged,
+    LocalChanged,
+    RepoChanged,
+    BothChanged,
+}
+
 pub fn compare_file(a: &Path, b: &Path) -> Result<bool, Box<dyn std::error::Error>> {
     let file_a = File::open(a)?;
     let file_b = File::open(b)?;
@@ -37,38 +54,67 @@ pub fn compare_file(a: &Path, b: &Path) -> Result<bool, Box<dyn std::error::Erro
     Ok(true)
 }
 
-fn walk_dir(path: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
-    let mut paths = Vec::new();
-    for entry in path.read_dir()? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            paths.extend(walk_dir(&path)?);
-        } else {
-            paths.push(path);
-        }
+/// The files of a directory tree relative to its root, sorted, leaving out paths
+/// matched by `ignore`; preserved symlinks count as files
+pub fn relative_files(
+    root: &Path,
+    ignore: &IgnoreRules,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut files: Vec<PathBuf> = walk(root, ignore)?
+        .into_iter()
+        .filter(|entry| entry.kind != EntryKind::Dir)
+        .map(|entry| entry.relative)
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Where a symlink points, with a relative target resolved against the link's directory
+pub fn resolve_link(link: &Path) -> io::Result<PathBuf> {
+    let target = fs::read_link(link)?;
+
+    match link.parent() {
+        Some(parent) if target.is_relative() => Ok(parent.join(target)),
+        _ => Ok(target),
     }
-    Ok(paths)
 }
 
+/// Whether two paths name the same file, however they are spelled
+fn same_path(a: &Path, b: &Path) -> bool {
+    a == b || matches!((fs::canonicalize(a), fs::canonicalize(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+/// Whether one path is a symlink to the other, or both link to the same file
+///
+/// Relative and absolute links to the same file are treated as equal.
 pub fn linked_paths(a: &Path, b: &Path) -> Result<bool, Box<dyn std::error::Error>> {
     if a.is_symlink() && b.is_symlink() {
-        let target_a = fs::read_link(a)?;
-        let target_b = fs::read_link(b)?;
-        return Ok(target_a == target_b);
+        return Ok(same_path(&resolve_link(a)?, &resolve_link(b)?));
     }
     if a.is_symlink() {
-        let target_a = fs::read_link(a)?;
-        return Ok(target_a == b);
+        return Ok(same_path(&resolve_link(a)?, b));
     }
     if b.is_symlink() {
-        let target_b = fs::read_link(b)?;
-        return Ok(a == target_b);
+        return Ok(same_path(a, &resolve_link(b)?));
     }
     Ok(false)
 }
 
-pub fn compare_paths(a: &Path, b: &Path) -> Result<CompareResult, Box<dyn std::error::Error>> {
+/// Whether two paths are hard links to the same file
+pub fn hardlinked_paths(a: &Path, b: &Path) -> bool {
+    let (Ok(meta_a), Ok(meta_b)) = (fs::symlink_metadata(a), fs::symlink_metadata(b)) else {
+        return false;
+    };
+
+    meta_a.is_file() && meta_a.dev() == meta_b.dev() && meta_a.ino() == meta_b.ino()
+}
+
+/// Compare two files or directory trees by content, leaving out paths matched by `ignore`
+pub fn compare_paths(
+    a: &Path,
+    b: &Path,
+    ignore: &IgnoreRules,
+) -> Result<CompareResult, Box<dyn std::error::Error>> {
     if a.exists() != b.exists() || a.is_dir() != b.is_dir() {
         return Ok(CompareResult::NotEqual);
     }
@@ -85,46 +131,58 @@ pub fn compare_paths(a: &Path, b: &Path) -> Result<CompareResult, Box<dyn std::e
         }
     }
 
-    let paths_a = walk_dir(a)?;
-    let paths_b = walk_dir(b)?;
-    if paths_a.len() != paths_b.len() {
+    // Both walks come out in the same order, so equal trees line up entry by entry
+    let entries_a = walk(a, ignore)?;
+    let entries_b = walk(b, ignore)?;
+    if entries_a.len() != entries_b.len() {
         return Ok(CompareResult::NotEqual);
     }
 
-    let mut processed_paths = Vec::new();
-    for path_b in &paths_b {
-        let relative_path = path_b.strip_prefix(b)?;
-        if processed_paths.contains(&relative_path) {
-            continue;
-        }
-        let corresponding_path_a = a.join(relative_path);
-        if !corresponding_path_a.exists() {
+    for (entry_a, entry_b) in entries_a.iter().zip(&entries_b) {
+        if entry_a.relative != entry_b.relative {
             return Ok(CompareResult::NotEqual);
         }
-        processed_paths.push(relative_path);
-    }
 
-    for path_a in &paths_a {
-        let relative_path = path_a.strip_prefix(a)?;
-        if processed_paths.contains(&relative_path) {
-            continue;
-        }
-        let corresponding_path_b = b.join(relative_path);
-        if !corresponding_path_b.exists() {
+        let equal = match (&entry_a.kind, &entry_b.kind) {
+            (EntryKind::File, EntryKind::File) => {
+                compare_file(&a.join(&entry_a.relative), &b.join(&entry_b.relative))?
+            }
+            (EntryKind::Dir, EntryKind::Dir) => true,
+            (EntryKind::Symlink(value_a), EntryKind::Symlink(value_b)) => value_a == value_b,
+            _ => false,
+        };
+        if !equal {
             return Ok(CompareResult::NotEqual);
         }
-        processed_paths.push(relative_path);
     }
 
-    for path in processed_paths {
-        let corresponding_path_a = a.join(path);
-        let corresponding_path_b = b.join(path);
-        if !compare_file(&corresponding_path_a, &corresponding_path_b)? {
-            return Ok(CompareResult::NotEqual);
-        }
+    Ok(CompareResult::Equal)
+}
+
+/// Compare the local and repository copies against a recorded baseline digest
+pub fn compare_with_baseline(
+    local: &Path,
+    repo: &Path,
+    baseline: &str,
+    ignore: &IgnoreRules,
+) -> Result<ChangeResult, Box<dyn std::error::Error>> {
+    let local_hash = hash_path(local, ignore)?;
+    let repo_hash = hash_path(repo, ignore)?;
+
+    Ok(change_from_hashes(&local_hash, &repo_hash, baseline))
+}
+
+/// Classify already computed local and repository digests against a baseline digest
+pub fn change_from_hashes(local_hash: &str, repo_hash: &str, baseline: &str) -> ChangeResult {
+    if local_hash == repo_hash {
+        return ChangeResult::Unchanged;
     }
 
-    Ok(CompareResult::Equal)
+    match (local_hash == baseline, repo_hash == baseline) {
+        (true, _) => ChangeResult::RepoChanged,
+        (_, true) => ChangeResult::LocalChanged,
+        _ => ChangeResult::BothChanged,
+    }
 }
 
 #[cfg(test)]
@@ -140,7 +198,7 @@ mod tests {
         std::fs::write(tmp_file_a, content).unwrap();
         std::fs::write(tmp_file_b, content).unwrap();
 
-        match compare_paths(tmp_file_a, tmp_file_b) {
+        match compare_paths(tmp_file_a, tmp_file_b, &IgnoreRules::default()) {
             Ok(CompareResult::Equal) => println!("Files are equal"),
             Ok(CompareResult::NotEqual) => println!("Files are not equal"),
             Ok(CompareResult::Linked) => println!("Files are linked"),
@@ -160,11 +218,122 @@ mod tests {
         std::fs::write(tmp_dir_a.join("file.txt"), content).unwrap();
         std::fs::write(tmp_dir_b.join("file.txt"), content).unwrap();
 
-        match compare_paths(tmp_dir_a, tmp_dir_b) {
+        match compare_paths(tmp_dir_a, tmp_dir_b, &IgnoreRules::default()) {
             Ok(CompareResult::Equal) => println!("Files are equal"),
             Ok(CompareResult::NotEqual) => println!("Files are not equal"),
             Ok(CompareResult::Linked) => println!("Files are linked"),
             Err(e) => panic!("Error comparing directories: {}", e),
         }
     }
+
+    #[test]
+    fn test_compare_with_baseline() {
+        let local = Path::new("/tmp/test_baseline_local.txt");
+        let repo = Path::new("/tmp/test_baseline_repo.txt");
+
+        std::fs::write(local, b"base").unwrap();
+        std::fs::write(repo, b"base").unwrap();
+        let no_rules = IgnoreRules::default();
+        let baseline = hash_path(repo, &no_rules).unwrap();
+
+        assert_eq!(
+            compare_with_baseline(local, repo, &baseline, &no_rules).unwrap(),
+            ChangeResult::Unchanged
+        );
+
+        std::fs::write(local, b"local edit").unwrap();
+        assert_eq!(
+            compare_with_baseline(local, repo, &baseline, &no_rules).unwrap(),
+            ChangeResult::LocalChanged
+        );
+
+        std::fs::write(local, b"base").unwrap();
+        std::fs::write(repo, b"repo edit").unwrap();
+        assert_eq!(
+            compare_with_baseline(local, repo, &baseline, &no_rules).unwrap(),
+            ChangeResult::RepoChanged
+        );
+
+        std::fs::write(local, b"local edit").unwrap();
+        assert_eq!(
+            compare_with_baseline(local, repo, &baseline, &no_rules).unwrap(),
+            ChangeResult::BothChanged
+        );
+    }
+
+    #[test]
+    fn test_hardlinked_paths() {
+        let original = Path::new("/tmp/test_hardlink_original.txt");
+        let hardlink = Path::new("/tmp/test_hardlink_link.txt");
+        let copy = Path::new("/tmp/test_hardlink_copy.txt");
+        let _ = std::fs::remove_file(hardlink);
+
+        std::fs::write(original, b"content").unwrap();
+        std::fs::write(copy, b"content").unwrap();
+        std::fs::hard_link(original, hardlink).unwrap();
+
+        assert!(hardlinked_paths(original, hardlink));
+        assert!(!hardlinked_paths(original, copy));
+    }
+
+    #[test]
+    fn test_relative_and_absolute_links_are_equal() {
+        let dir = Path::new("/tmp/test_relative_links");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir.join("repo")).unwrap();
+        std::fs::write(dir.join("repo/file"), b"content").unwrap();
+
+        std::os::unix::fs::symlink("repo/file", dir.join("relative")).unwrap();
+        std::os::unix::fs::symlink(dir.join("repo/file"), dir.join("absolute")).unwrap();
+
+        assert!(linked_paths(&dir.join("relative"), &dir.join("repo/file")).unwrap());
+        assert!(linked_paths(&dir.join("absolute"), &dir.join("repo/file")).unwrap());
+        assert!(linked_paths(&dir.join("relative"), &dir.join("absolute")).unwrap());
+    }
+
+    #[test]
+    fn test_relative_files() {
+        let dir = Path::new("/tmp/test_relative_files");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir.join("functions")).unwrap();
+        std::fs::write(dir.join("config.fish"), b"set").unwrap();
+        std::fs::write(dir.join("fish_history"), b"ls").unwrap();
+        std::fs::write(dir.join("functions/ll.fish"), b"ls -l").unwrap();
+
+        let ignore = IgnoreRules::new(["fish_history"]).unwrap();
+        assert_eq!(
+            relative_files(dir, &ignore).unwrap(),
+            vec![
+                PathBuf::from("config.fish"),
+                PathBuf::from("functions/ll.fish")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compare_changed_link_target() {
+        use crate::utils::walk::SymlinkPolicy;
+
+        let dir = Path::new("/tmp/test_compare_link_target");
+        let _ = std::fs::remove_dir_all(dir);
+        for side in ["a", "b"] {
+            std::fs::create_dir_all(dir.join(side)).unwrap();
+            std::fs::write(dir.join(side).join("dark.toml"), b"dark").unwrap();
+            std::fs::write(dir.join(side).join("light.toml"), b"dark").unwrap();
+        }
+        std::os::unix::fs::symlink("dark.toml", dir.join("a/current.toml")).unwrap();
+        std::os::unix::fs::symlink("light.toml", dir.join("b/current.toml")).unwrap();
+
+        let compare = |policy| {
+            let rules = IgnoreRules::default().with_symlinks(policy);
+            compare_paths(&dir.join("a"), &dir.join("b"), &rules).unwrap()
+        };
+
+        // Both targets hold the same content, only a preserved link tells them apart
+        assert_eq!(compare(SymlinkPolicy::Preserve), CompareResult::NotEqual);
+        assert_eq!(compare(SymlinkPolicy::Dereference), CompareResult::Equal);
+        assert_eq!(compare(SymlinkPolicy::Skip), CompareResult::Equal);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
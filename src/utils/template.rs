@@ -0,0 +1,58 @@
+use anyhow::{Result, anyhow};
+
+/// Render `{{ name }}` placeholders, looking each name up with `lookup`
+pub fn render(content: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut rendered = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| {
+            let line = content[..content.len() - rest.len() + start]
+                .lines()
+                .count()
+                .max(1);
+            anyhow!("Unclosed '{{{{' on line {}", line)
+        })?;
+
+        let name = after[..end].trim();
+        let value = lookup(name).ok_or_else(|| anyhow!("Undefined template variable: {}", name))?;
+        rendered.push_str(&value);
+
+        rest = &after[end + 2..];
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "email" => Some("me@example.com".to_string()),
+            "font_size" => Some("12".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let rendered = render("email = {{ email }}\nsize={{font_size}}\n", lookup).unwrap();
+        assert_eq!(rendered, "email = me@example.com\nsize=12\n");
+    }
+
+    #[test]
+    fn test_render_errors() {
+        assert!(render("{{ missing }}", lookup).is_err());
+        assert!(render("a\n{{ email", lookup).is_err());
+        assert_eq!(
+            render("no placeholders", lookup).unwrap(),
+            "no placeholders"
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<OsString, Node>,
+    index: Option<usize>,
+}
+
+/// Lay out relative paths as a tree, one line per directory and file
+///
+/// Each line comes with the index of the path it shows, `None` for directories that
+/// only group other paths.
+pub fn tree_lines(paths: &[PathBuf]) -> Vec<(String, Option<usize>)> {
+    let mut root = Node::default();
+    for (index, path) in paths.iter().enumerate() {
+        let mut node = &mut root;
+        for component in path.iter() {
+            node = node.children.entry(component.to_os_string()).or_default();
+        }
+        node.index = Some(index);
+    }
+
+    let mut lines = Vec::new();
+    push_lines(&root, "", &mut lines);
+    lines
+}
+
+fn push_lines(node: &Node, prefix: &str, lines: &mut Vec<(String, Option<usize>)>) {
+    let count = node.children.len();
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        let last = i + 1 == count;
+        let branch = if last { "└── " } else { "├── " };
+        lines.push((
+            format!("{}{}{}", prefix, branch, name.to_string_lossy()),
+            child.index,
+        ));
+
+        let indent = if last { "    " } else { "│   " };
+        push_lines(child, &format!("{}{}", prefix, indent), lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_lines() {
+        let paths = [
+            PathBuf::from("config.fish"),
+            PathBuf::from("functions/ll.fish"),
+            PathBuf::from("functions/la.fish"),
+        ];
+
+        assert_eq!(
+            tree_lines(&paths),
+            vec![
+                ("├── config.fish".to_string(), Some(0)),
+                ("└── functions".to_string(), None),
+                ("    ├── la.fish".to_string(), Some(2)),
+                ("    └── ll.fish".to_string(), Some(1)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use colored::*;
+use serde::{Deserialize, Serialize};
+
+use super::ignore::IgnoreRules;
+
+/// What happens to symlinks found inside a directory dotfile
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    /// Keep them as links, compared by where they point
+    #[default]
+    Preserve,
+    /// Follow them and track what they point to, skipping links back into the tree
+    Dereference,
+    /// Leave them out
+    Skip,
+}
+
+impl SymlinkPolicy {
+    pub fn is_preserve(&self) -> bool {
+        *self == SymlinkPolicy::Preserve
+    }
+}
+
+/// What a path inside a directory dotfile is tracked as
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    /// A preserved symlink with the value it holds
+    Symlink(PathBuf),
+}
+
+/// A path found walking a directory dotfile
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalkEntry {
+    /// Path relative to the root of the walk
+    pub relative: PathBuf,
+    pub kind: EntryKind,
+}
+
+/// Every path under `root` that is tracked, each directory before its contents and
+/// the contents of a directory sorted by name
+///
+/// Paths matched by `rules` are left out and inner symlinks handled by its symlink
+/// policy; symlinks are never followed unless the policy dereferences them. Sockets,
+/// FIFOs and devices are skipped with a warning, as are broken or cyclic symlinks that
+/// should be dereferenced and the contents of directories below `root` that can't be
+/// read.
+pub fn walk(root: &Path, rules: &IgnoreRules) -> io::Result<Vec<WalkEntry>> {
+    let mut entries = Vec::new();
+    let mut stack = vec![fs::canonicalize(root)?];
+    walk_dir(root, Path::new(""), rules, &mut stack, &mut entries)?;
+    Ok(entries)
+}
+
+fn walk_dir(
+    root: &Path,
+    dir: &Path,
+    rules: &IgnoreRules,
+    stack: &mut Vec<PathBuf>,
+    entries: &mut Vec<WalkEntry>,
+) -> io::Result<()> {
+    let children = match fs::read_dir(root.join(dir)) {
+        Ok(children) => children,
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied && dir != Path::new("") => {
+            warn_skipped(&root.join(dir), "its contents can't be read");
+            return Ok(());
+        }
+        Err(err) => return Err(err),
+    };
+    let mut children = children.collect::<io::Result<Vec<_>>>()?;
+    children.sort_by_key(|child| child.file_name());
+
+    for child in children {
+        let path = child.path();
+        let relative = dir.join(child.file_name());
+        let kind = match classify(&path, child.file_type()?, rules, stack)? {
+            Some(kind) => kind,
+            None => continue,
+        };
+
+        let is_dir = kind == Ok(EntryKind::Dir);
+        if rules.is_ignored(&relative, is_dir) {
+            continue;
+        }
+        let kind = match kind {
+            Ok(kind) => kind,
+            Err(reason) => {
+                warn_skipped(&path, reason);
+                continue;
+            }
+        };
+
+        entries.push(WalkEntry {
+            relative: relative.clone(),
+            kind,
+        });
+        if is_dir {
+            stack.push(fs::canonicalize(&path)?);
+            walk_dir(root, &relative, rules, stack, entries)?;
+            stack.pop();
+        }
+    }
+
+    Ok(())
+}
+
+/// How a path is tracked, the reason it is skipped with a warning, or `None` when the
+/// symlink policy leaves it out
+fn classify(
+    path: &Path,
+    file_type: fs::FileType,
+    rules: &IgnoreRules,
+    stack: &[PathBuf],
+) -> io::Result<Option<Result<EntryKind, &'static str>>> {
+    if !file_type.is_symlink() {
+        return Ok(Some(plain_kind(file_type)));
+    }
+
+    let kind = match rules.symlinks() {
+        SymlinkPolicy::Skip => return Ok(None),
+        SymlinkPolicy::Preserve => Ok(EntryKind::Symlink(fs::read_link(path)?)),
+        SymlinkPolicy::Dereference => match fs::metadata(path) {
+            Err(_) => Err("it is a broken symlink"),
+            Ok(metadata) if metadata.is_dir() => {
+                let target = fs::canonicalize(path)?;
+                if stack.iter().any(|dir| dir.starts_with(&target)) {
+                    Err("it links back to a directory it is in")
+                } else {
+                    Ok(EntryKind::Dir)
+                }
+            }
+            Ok(metadata) => plain_kind(metadata.file_type()),
+        },
+    };
+    Ok(Some(kind))
+}
+
+fn plain_kind(file_type: fs::FileType) -> Result<EntryKind, &'static str> {
+    if file_type.is_dir() {
+        Ok(EntryKind::Dir)
+    } else if file_type.is_file() {
+        Ok(EntryKind::File)
+    } else {
+        Err("sockets, FIFOs and devices can't be tracked")
+    }
+}
+
+/// Warn once per run about a path that is left out
+fn warn_skipped(path: &Path, reason: &str) {
+    static WARNED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+    let first = WARNED
+        .lock()
+        .map(|mut warned| warned.insert(path.to_path_buf()))
+        .unwrap_or(true);
+    if first {
+        eprintln!("{} Skipped {}: {}", "!".yellow(), path.display(), reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_symlink_policies() {
+        let root = Path::new("/tmp/test_dotzilla_walk");
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root.join("themes")).unwrap();
+        fs::write(root.join("themes/dark.toml"), "dark").unwrap();
+        symlink("themes/dark.toml", root.join("current.toml")).unwrap();
+        symlink("..", root.join("themes/up")).unwrap();
+        let fifo = CString::new(root.join("pipe").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+
+        let walked = |policy| {
+            let rules = IgnoreRules::default().with_symlinks(policy);
+            walk(root, &rules)
+                .unwrap()
+                .into_iter()
+                .map(|entry| (entry.relative, entry.kind))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            walked(SymlinkPolicy::Preserve),
+            vec![
+                (
+                    PathBuf::from("current.toml"),
+                    EntryKind::Symlink(PathBuf::from("themes/dark.toml"))
+                ),
+                (PathBuf::from("themes"), EntryKind::Dir),
+                (PathBuf::from("themes/dark.toml"), EntryKind::File),
+                (
+                    PathBuf::from("themes/up"),
+                    EntryKind::Symlink(PathBuf::from(".."))
+                ),
+            ]
+        );
+
+        // The link back up to the root is a cycle and left out
+        assert_eq!(
+            walked(SymlinkPolicy::Dereference),
+            vec![
+                (PathBuf::from("current.toml"), EntryKind::File),
+                (PathBuf::from("themes"), EntryKind::Dir),
+                (PathBuf::from("themes/dark.toml"), EntryKind::File),
+            ]
+        );
+
+        assert_eq!(
+            walked(SymlinkPolicy::Skip),
+            vec![
+                (PathBuf::from("themes"), EntryKind::Dir),
+                (PathBuf::from("themes/dark.toml"), EntryKind::File),
+            ]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}